@@ -35,6 +35,17 @@ export class MasterHandle implements Disposable {
 	}
 }
 
+export class SlaveHandle implements Disposable {
+	readonly handle: Pointer;
+	constructor(handle: Pointer) {
+		this.handle = handle;
+	}
+
+	[Symbol.dispose](): void {
+		symbols.pty_free_slave(this.handle);
+	}
+}
+
 export class ChildHandle implements Disposable {
 	readonly handle: Pointer;
 	constructor(handle: Pointer) {
@@ -46,6 +57,39 @@ export class ChildHandle implements Disposable {
 	}
 }
 
+export class BuilderHandle implements Disposable {
+	readonly handle: Pointer;
+	constructor(handle: Pointer) {
+		this.handle = handle;
+	}
+
+	[Symbol.dispose](): void {
+		symbols.pty_free_builder(this.handle);
+	}
+}
+
+export class ChannelHandle implements Disposable {
+	readonly handle: Pointer;
+	constructor(handle: Pointer) {
+		this.handle = handle;
+	}
+
+	[Symbol.dispose](): void {
+		symbols.pty_free_channel(this.handle);
+	}
+}
+
+export class VtHandle implements Disposable {
+	readonly handle: Pointer;
+	constructor(handle: Pointer) {
+		this.handle = handle;
+	}
+
+	[Symbol.dispose](): void {
+		symbols.pty_free_vt(this.handle);
+	}
+}
+
 const libName =
 	process.platform === "win32" ? "rust_wrapper" : "librust_wrapper";
 const libPath = path.join(
@@ -108,6 +152,24 @@ export function pty_get_reader(master: MasterHandle) {
 	return new ReaderHandle(reader);
 }
 
+export function pty_get_stderr_reader(master: MasterHandle) {
+	const readerOut = new BigUint64Array(1);
+	const errOut = new BigUint64Array(1);
+	const status = symbols.pty_get_stderr_reader(
+		master.handle,
+		readerOut,
+		errOut,
+	);
+	if (status !== 0) {
+		const errMsg = extractErrorMessage(errOut[0]);
+		throw new Error(`pty_get_stderr_reader failed: ${errMsg}`);
+	}
+	const reader = Number(readerOut[0]) as Pointer;
+	if (!reader)
+		throw new Error("pty_get_stderr_reader failed to create reader handle");
+	return new ReaderHandle(reader);
+}
+
 export function pty_get_writer(master: MasterHandle) {
 	const writerOut = new BigUint64Array(1);
 	const errOut = new BigUint64Array(1);
@@ -140,13 +202,31 @@ export function pty_write(writer: WriterHandle, text: string) {
 		buf.length,
 		errOut,
 	);
-	if (bytesWritten === -1n) {
+	if (bytesWritten < 0n) {
 		const errMsg = extractErrorMessage(errOut[0]);
 		throw new Error(`pty_write failed: ${errMsg}`);
 	}
 	return Number(bytesWritten);
 }
 
+/** Blocks until the child exits. The handle stays valid and later waits return the same status. */
+export function pty_child_wait(child: ChildHandle) {
+	const exitCodeOut = new Int32Array(1);
+	const signalOut = new Int32Array(1);
+	const errOut = new BigUint64Array(1);
+	const status = symbols.pty_child_wait(
+		child.handle,
+		exitCodeOut,
+		signalOut,
+		errOut,
+	);
+	if (status !== 0) {
+		const errMsg = extractErrorMessage(errOut[0]);
+		throw new Error(`pty_child_wait failed: ${errMsg}`);
+	}
+	return { exitCode: exitCodeOut[0], signal: signalOut[0] };
+}
+
 export const { symbols } = dlopen(libPath, {
 	pty_open_and_spawn: {
 		args: [
@@ -161,10 +241,98 @@ export const { symbols } = dlopen(libPath, {
 		],
 		returns: FFIType.i32,
 	},
+	pty_open_and_spawn_fd_limited: {
+		args: [
+			FFIType.u16,
+			FFIType.u16,
+			FFIType.ptr,
+			FFIType.ptr,
+			FFIType.u64,
+			FFIType.u64,
+			FFIType.ptr,
+			FFIType.ptr,
+			FFIType.ptr,
+		],
+		returns: FFIType.i32,
+	},
+	pty_open_and_spawn_full: {
+		args: [
+			FFIType.u16,
+			FFIType.u16,
+			FFIType.ptr,
+			FFIType.ptr,
+			FFIType.ptr,
+			FFIType.u64,
+			FFIType.ptr,
+			FFIType.ptr,
+			FFIType.ptr,
+		],
+		returns: FFIType.i32,
+	},
+	pty_open_and_spawn_preexec: {
+		args: [
+			FFIType.u16,
+			FFIType.u16,
+			FFIType.ptr,
+			FFIType.ptr,
+			FFIType.ptr,
+			FFIType.u64,
+			FFIType.ptr,
+			FFIType.ptr,
+			FFIType.ptr,
+		],
+		returns: FFIType.i32,
+	},
+	pty_open: {
+		args: [FFIType.u16, FFIType.u16, FFIType.ptr, FFIType.ptr, FFIType.ptr],
+		returns: FFIType.i32,
+	},
+	pty_open_ex: {
+		args: [
+			FFIType.u16,
+			FFIType.u16,
+			FFIType.bool,
+			FFIType.bool,
+			FFIType.ptr,
+			FFIType.ptr,
+			FFIType.ptr,
+		],
+		returns: FFIType.i32,
+	},
+	pty_open_slave_path: {
+		args: [
+			FFIType.u16,
+			FFIType.u16,
+			FFIType.ptr,
+			FFIType.ptr,
+			FFIType.u64,
+			FFIType.ptr,
+		],
+		returns: FFIType.i32,
+	},
+	pty_master_ptn: {
+		args: [FFIType.ptr, FFIType.ptr, FFIType.ptr],
+		returns: FFIType.i32,
+	},
+	pty_slave_respawn: {
+		args: [
+			FFIType.ptr,
+			FFIType.ptr,
+			FFIType.ptr,
+			FFIType.u64,
+			FFIType.ptr,
+			FFIType.ptr,
+		],
+		returns: FFIType.i32,
+	},
 	pty_get_reader: {
 		args: [FFIType.ptr, FFIType.ptr, FFIType.ptr],
 		returns: FFIType.i32,
 	},
+	pty_get_stderr_reader: {
+		args: [FFIType.ptr, FFIType.ptr, FFIType.ptr],
+		returns: FFIType.i32,
+	},
 	pty_get_writer: {
 		args: [FFIType.ptr, FFIType.ptr, FFIType.ptr],
 		returns: FFIType.i32,
@@ -173,33 +341,601 @@ export const { symbols } = dlopen(libPath, {
 		args: [FFIType.ptr, FFIType.ptr, FFIType.u64, FFIType.ptr],
 		returns: FFIType.i64,
 	},
-	pty_write: {
+	pty_read_clearing: {
 		args: [FFIType.ptr, FFIType.ptr, FFIType.u64, FFIType.ptr],
 		returns: FFIType.i64,
 	},
-	pty_resize: {
-		args: [FFIType.ptr, FFIType.u16, FFIType.u16, FFIType.ptr],
+	pty_read_bounded: {
+		args: [
+			FFIType.ptr,
+			FFIType.ptr,
+			FFIType.u64,
+			FFIType.u32,
+			FFIType.ptr,
+			FFIType.ptr,
+			FFIType.ptr,
+		],
 		returns: FFIType.i32,
 	},
-	pty_child_wait: {
-		args: [FFIType.ptr, FFIType.ptr, FFIType.ptr, FFIType.ptr],
+	pty_read_min: {
+		args: [
+			FFIType.ptr,
+			FFIType.ptr,
+			FFIType.u64,
+			FFIType.u64,
+			FFIType.u32,
+			FFIType.ptr,
+			FFIType.ptr,
+		],
 		returns: FFIType.i32,
 	},
-	pty_child_try_wait: {
-		args: [FFIType.ptr, FFIType.ptr, FFIType.ptr, FFIType.ptr],
+	pty_reader_set_rate_limit: {
+		args: [FFIType.ptr, FFIType.u64],
 		returns: FFIType.i32,
 	},
-	pty_child_kill: {
-		args: [FFIType.ptr, FFIType.ptr],
+	pty_reader_set_framed_log: {
+		args: [FFIType.ptr, FFIType.i32, FFIType.bool],
 		returns: FFIType.i32,
 	},
-	pty_child_is_alive: {
+	pty_reader_tap: {
+		args: [FFIType.ptr, FFIType.ptr, FFIType.ptr],
+		returns: FFIType.i32,
+	},
+	pty_read_loop: {
+		args: [FFIType.ptr, FFIType.function, FFIType.ptr, FFIType.ptr],
+		returns: FFIType.i32,
+	},
+	pty_read_to_end_cb: {
+		args: [
+			FFIType.ptr,
+			FFIType.function,
+			FFIType.function,
+			FFIType.function,
+			FFIType.ptr,
+			FFIType.ptr,
+			FFIType.ptr,
+			FFIType.ptr,
+		],
+		returns: FFIType.i32,
+	},
+	pty_reader_buffered_len: { args: [FFIType.ptr], returns: FFIType.u64 },
+	pty_reader_high_water: { args: [FFIType.ptr], returns: FFIType.u64 },
+	pty_peek_line_len: {
+		args: [FFIType.ptr, FFIType.u32, FFIType.ptr, FFIType.ptr],
+		returns: FFIType.i32,
+	},
+	pty_reader_peek_is_binary: {
+		args: [FFIType.ptr, FFIType.u64, FFIType.ptr, FFIType.ptr],
+		returns: FFIType.i32,
+	},
+	pty_enable_cwd_tracking: {
+		args: [FFIType.ptr, FFIType.bool],
+		returns: FFIType.i32,
+	},
+	pty_reader_watch_osc: {
+		args: [FFIType.ptr, FFIType.u32, FFIType.function, FFIType.ptr],
+		returns: FFIType.i32,
+	},
+	pty_get_tracked_cwd: {
+		args: [FFIType.ptr, FFIType.ptr, FFIType.u64, FFIType.ptr],
+		returns: FFIType.i32,
+	},
+	pty_read_idle_framed: {
+		args: [
+			FFIType.ptr,
+			FFIType.ptr,
+			FFIType.u64,
+			FFIType.u32,
+			FFIType.u32,
+			FFIType.ptr,
+			FFIType.ptr,
+		],
+		returns: FFIType.i32,
+	},
+	pty_reader_set_prompt_pattern: {
+		args: [FFIType.ptr, FFIType.ptr, FFIType.ptr],
+		returns: FFIType.i32,
+	},
+	pty_read_until_prompt: {
+		args: [
+			FFIType.ptr,
+			FFIType.ptr,
+			FFIType.u64,
+			FFIType.ptr,
+			FFIType.u32,
+			FFIType.ptr,
+		],
+		returns: FFIType.i32,
+	},
+	pty_reader_compile_pattern: {
+		args: [FFIType.ptr, FFIType.ptr, FFIType.ptr],
+		returns: FFIType.i32,
+	},
+	pty_read_until_match: {
+		args: [
+			FFIType.ptr,
+			FFIType.ptr,
+			FFIType.u64,
+			FFIType.ptr,
+			FFIType.ptr,
+			FFIType.ptr,
+			FFIType.u32,
+			FFIType.ptr,
+		],
+		returns: FFIType.i32,
+	},
+	pty_reader_set_encoding: {
+		args: [FFIType.ptr, FFIType.ptr, FFIType.ptr],
+		returns: FFIType.i32,
+	},
+	pty_reader_set_invalid_utf8_policy: {
+		args: [FFIType.ptr, FFIType.i32],
+		returns: FFIType.i32,
+	},
+	pty_reader_invalid_utf8_count: {
 		args: [FFIType.ptr],
+		returns: FFIType.u64,
+	},
+	pty_reader_set_nul_policy: {
+		args: [FFIType.ptr, FFIType.i32],
+		returns: FFIType.i32,
+	},
+	pty_reader_flush_input: {
+		args: [FFIType.ptr, FFIType.ptr, FFIType.ptr],
+		returns: FFIType.i32,
+	},
+	pty_reader_take_since: {
+		args: [
+			FFIType.ptr,
+			FFIType.ptr,
+			FFIType.u64,
+			FFIType.ptr,
+			FFIType.ptr,
+			FFIType.ptr,
+		],
+		returns: FFIType.i32,
+	},
+	pty_reader_set_drain_capacity: {
+		args: [FFIType.ptr, FFIType.u64, FFIType.ptr],
+		returns: FFIType.i32,
+	},
+	pty_reader_channel: {
+		args: [FFIType.ptr, FFIType.u64, FFIType.ptr, FFIType.ptr],
+		returns: FFIType.i32,
+	},
+	pty_channel_recv: {
+		args: [
+			FFIType.ptr,
+			FFIType.ptr,
+			FFIType.u64,
+			FFIType.u32,
+			FFIType.ptr,
+			FFIType.ptr,
+		],
+		returns: FFIType.i32,
+	},
+	pty_write: {
+		args: [FFIType.ptr, FFIType.ptr, FFIType.u64, FFIType.ptr],
+		returns: FFIType.i64,
+	},
+	pty_write_checked: {
+		args: [FFIType.ptr, FFIType.ptr, FFIType.ptr, FFIType.u64, FFIType.ptr],
+		returns: FFIType.i64,
+	},
+	pty_write_raw: {
+		args: [FFIType.ptr, FFIType.ptr, FFIType.u64, FFIType.ptr],
+		returns: FFIType.i64,
+	},
+	pty_write_script: {
+		args: [FFIType.ptr, FFIType.ptr, FFIType.u64, FFIType.u32, FFIType.ptr],
+		returns: FFIType.i32,
+	},
+	pty_write_paste: {
+		args: [FFIType.ptr, FFIType.ptr, FFIType.u64, FFIType.ptr],
+		returns: FFIType.i32,
+	},
+	pty_type: {
+		args: [
+			FFIType.ptr,
+			FFIType.ptr,
+			FFIType.u64,
+			FFIType.u32,
+			FFIType.u32,
+			FFIType.u64,
+			FFIType.ptr,
+		],
+		returns: FFIType.i32,
+	},
+	pty_write_from_fd: {
+		args: [FFIType.ptr, FFIType.i32, FFIType.ptr, FFIType.ptr],
+		returns: FFIType.i32,
+	},
+	pty_write_line_ending: {
+		args: [FFIType.ptr, FFIType.ptr, FFIType.u64, FFIType.i32, FFIType.ptr],
+		returns: FFIType.i32,
+	},
+	pty_write_expect: {
+		args: [
+			FFIType.ptr,
+			FFIType.ptr,
+			FFIType.ptr,
+			FFIType.u64,
+			FFIType.ptr,
+			FFIType.u64,
+			FFIType.u32,
+			FFIType.ptr,
+		],
+		returns: FFIType.i32,
+	},
+	pty_set_title: {
+		args: [FFIType.ptr, FFIType.ptr, FFIType.ptr],
+		returns: FFIType.i32,
+	},
+	pty_get_title: {
+		args: [FFIType.ptr, FFIType.ptr, FFIType.ptr],
+		returns: FFIType.i32,
+	},
+	pty_master_created_at: {
+		args: [FFIType.ptr, FFIType.ptr, FFIType.ptr],
+		returns: FFIType.i32,
+	},
+	pty_master_session_id: {
+		args: [FFIType.ptr, FFIType.ptr],
+		returns: FFIType.i32,
+	},
+	pty_list_sessions: {
+		args: [FFIType.ptr, FFIType.u64, FFIType.ptr],
+		returns: FFIType.i32,
+	},
+	pty_shutdown_all: {
+		args: [FFIType.u32],
+		returns: FFIType.i32,
+	},
+	pty_master_set_label: {
+		args: [FFIType.ptr, FFIType.ptr],
+		returns: FFIType.i32,
+	},
+	pty_master_get_label: {
+		args: [FFIType.ptr, FFIType.ptr, FFIType.u64, FFIType.ptr],
+		returns: FFIType.i32,
+	},
+	pty_get_termios_blob: {
+		args: [FFIType.ptr, FFIType.ptr, FFIType.u64, FFIType.ptr, FFIType.ptr],
+		returns: FFIType.i32,
+	},
+	pty_set_termios_blob: {
+		args: [FFIType.ptr, FFIType.ptr, FFIType.u64, FFIType.ptr],
+		returns: FFIType.i32,
+	},
+	pty_reset_terminal: { args: [FFIType.ptr, FFIType.ptr], returns: FFIType.i32 },
+	pty_line_discipline: {
+		args: [FFIType.ptr, FFIType.ptr, FFIType.ptr],
+		returns: FFIType.i32,
+	},
+	pty_resize: {
+		args: [FFIType.ptr, FFIType.u16, FFIType.u16, FFIType.ptr],
+		returns: FFIType.i32,
+	},
+	pty_resize_redraw: {
+		args: [FFIType.ptr, FFIType.u16, FFIType.u16, FFIType.ptr],
+		returns: FFIType.i32,
+	},
+	pty_resize_and_wait_output: {
+		args: [
+			FFIType.ptr,
+			FFIType.ptr,
+			FFIType.u16,
+			FFIType.u16,
+			FFIType.u32,
+			FFIType.ptr,
+		],
+		returns: FFIType.i32,
+	},
+	pty_pause_output: {
+		args: [FFIType.ptr, FFIType.ptr],
+		returns: FFIType.i32,
+	},
+	pty_resume_output: {
+		args: [FFIType.ptr, FFIType.ptr],
+		returns: FFIType.i32,
+	},
+	pty_foreground_pgrp: {
+		args: [FFIType.ptr, FFIType.ptr, FFIType.ptr],
+		returns: FFIType.i32,
+	},
+	pty_set_foreground_pgrp: {
+		args: [FFIType.ptr, FFIType.i32, FFIType.ptr],
+		returns: FFIType.i32,
+	},
+	pty_drain: {
+		args: [FFIType.ptr, FFIType.ptr],
+		returns: FFIType.i32,
+	},
+	pty_writer_would_block: {
+		args: [FFIType.ptr, FFIType.ptr, FFIType.ptr],
+		returns: FFIType.i32,
+	},
+	pty_flush_queues: {
+		args: [FFIType.ptr, FFIType.i32, FFIType.ptr],
+		returns: FFIType.i32,
+	},
+	pty_follow_host_resize: {
+		args: [FFIType.ptr, FFIType.bool, FFIType.ptr],
+		returns: FFIType.i32,
+	},
+	pty_on_resize: {
+		args: [FFIType.ptr, FFIType.function, FFIType.ptr, FFIType.ptr],
+		returns: FFIType.i32,
+	},
+	pty_cancel_on_resize: { args: [FFIType.ptr], returns: FFIType.i32 },
+	pty_child_wait: {
+		args: [FFIType.ptr, FFIType.ptr, FFIType.ptr, FFIType.ptr],
+		returns: FFIType.i32,
+	},
+	pty_child_wait_progress: {
+		args: [
+			FFIType.ptr,
+			FFIType.u32,
+			FFIType.function,
+			FFIType.ptr,
+			FFIType.ptr,
+			FFIType.ptr,
+		],
+		returns: FFIType.i32,
+	},
+	pty_child_wait_with_tail: {
+		args: [
+			FFIType.ptr,
+			FFIType.ptr,
+			FFIType.ptr,
+			FFIType.u64,
+			FFIType.ptr,
+			FFIType.ptr,
+			FFIType.ptr,
+		],
+		returns: FFIType.i32,
+	},
+	pty_child_try_wait: {
+		args: [FFIType.ptr, FFIType.ptr, FFIType.ptr, FFIType.ptr],
+		returns: FFIType.i32,
+	},
+	pty_child_reset_status: { args: [FFIType.ptr], returns: FFIType.i32 },
+	pty_child_detach: {
+		args: [FFIType.ptr, FFIType.ptr],
+		returns: FFIType.i32,
+	},
+	pty_child_kill: {
+		args: [FFIType.ptr, FFIType.ptr],
+		returns: FFIType.i32,
+	},
+	pty_child_signal_safe: {
+		args: [FFIType.ptr, FFIType.i32, FFIType.ptr],
+		returns: FFIType.i32,
+	},
+	pty_child_is_alive: {
+		args: [FFIType.ptr],
+		returns: FFIType.i32,
+	},
+	pty_child_status_ex: {
+		args: [FFIType.ptr, FFIType.ptr, FFIType.ptr, FFIType.ptr, FFIType.ptr],
+		returns: FFIType.i32,
+	},
+	pty_child_proc_stats: {
+		args: [FFIType.ptr, FFIType.ptr, FFIType.ptr],
+		returns: FFIType.i32,
+	},
+	pty_child_start_time: {
+		args: [FFIType.ptr, FFIType.ptr, FFIType.ptr],
+		returns: FFIType.i32,
+	},
+	pty_child_has_ctty: {
+		args: [FFIType.ptr, FFIType.ptr, FFIType.ptr],
+		returns: FFIType.i32,
+	},
+	pty_child_exe_path: {
+		args: [FFIType.ptr, FFIType.ptr, FFIType.u64, FFIType.ptr, FFIType.ptr],
+		returns: FFIType.i32,
+	},
+	pty_ansi_scan: {
+		args: [FFIType.ptr, FFIType.u64, FFIType.ptr],
+		returns: FFIType.i32,
+	},
+	pty_vt_new: {
+		args: [FFIType.u16, FFIType.u16, FFIType.ptr, FFIType.ptr],
+		returns: FFIType.i32,
+	},
+	pty_vt_feed: {
+		args: [FFIType.ptr, FFIType.ptr, FFIType.u64],
+		returns: FFIType.i32,
+	},
+	pty_vt_dump: {
+		args: [FFIType.ptr, FFIType.ptr, FFIType.u64, FFIType.ptr, FFIType.ptr],
+		returns: FFIType.i32,
+	},
+	pty_builder_new: {
+		args: [FFIType.ptr, FFIType.ptr, FFIType.ptr],
+		returns: FFIType.i32,
+	},
+	pty_builder_clone: {
+		args: [FFIType.ptr, FFIType.ptr, FFIType.ptr],
+		returns: FFIType.i32,
+	},
+	pty_builder_args: {
+		args: [FFIType.ptr, FFIType.ptr, FFIType.u64],
+		returns: FFIType.i32,
+	},
+	pty_builder_env_exact: {
+		args: [FFIType.ptr, FFIType.ptr, FFIType.u64, FFIType.ptr],
+		returns: FFIType.i32,
+	},
+	pty_builder_size: {
+		args: [FFIType.ptr, FFIType.u16, FFIType.u16],
+		returns: FFIType.i32,
+	},
+	pty_builder_reset_signals: {
+		args: [FFIType.ptr, FFIType.bool],
+		returns: FFIType.i32,
+	},
+	pty_builder_minimal_fds: {
+		args: [FFIType.ptr, FFIType.bool],
+		returns: FFIType.i32,
+	},
+	pty_builder_max_fds: {
+		args: [FFIType.ptr, FFIType.u64],
+		returns: FFIType.i32,
+	},
+	pty_builder_namespaces: {
+		args: [FFIType.ptr, FFIType.u32],
+		returns: FFIType.i32,
+	},
+	pty_builder_cpu_affinity: {
+		args: [FFIType.ptr, FFIType.ptr, FFIType.u64, FFIType.ptr],
+		returns: FFIType.i32,
+	},
+	pty_builder_stdio: {
+		args: [FFIType.ptr, FFIType.i32, FFIType.i32, FFIType.i32],
+		returns: FFIType.i32,
+	},
+	pty_builder_title: {
+		args: [FFIType.ptr, FFIType.ptr, FFIType.ptr],
+		returns: FFIType.i32,
+	},
+	pty_builder_conpty_settle: {
+		args: [FFIType.ptr, FFIType.bool],
+		returns: FFIType.i32,
+	},
+	pty_builder_output_log: {
+		args: [FFIType.ptr, FFIType.ptr],
+		returns: FFIType.i32,
+	},
+	pty_output_log_error: {
+		args: [FFIType.ptr, FFIType.ptr],
+		returns: FFIType.i32,
+	},
+	pty_builder_spawn: {
+		args: [FFIType.ptr, FFIType.ptr, FFIType.ptr, FFIType.ptr],
+		returns: FFIType.i32,
+	},
+	pty_open_spawn_write: {
+		args: [
+			FFIType.ptr,
+			FFIType.ptr,
+			FFIType.u64,
+			FFIType.ptr,
+			FFIType.ptr,
+			FFIType.ptr,
+		],
+		returns: FFIType.i32,
+	},
+	pty_run_capture: {
+		args: [
+			FFIType.ptr,
+			FFIType.function,
+			FFIType.ptr,
+			FFIType.ptr,
+			FFIType.ptr,
+		],
+		returns: FFIType.i32,
+	},
+	pty_run_capture_limited: {
+		args: [
+			FFIType.ptr,
+			FFIType.function,
+			FFIType.ptr,
+			FFIType.u64,
+			FFIType.bool,
+			FFIType.ptr,
+			FFIType.ptr,
+		],
+		returns: FFIType.i32,
+	},
+	pty_run_capture_first_byte: {
+		args: [
+			FFIType.ptr,
+			FFIType.function,
+			FFIType.ptr,
+			FFIType.u32,
+			FFIType.ptr,
+			FFIType.ptr,
+		],
+		returns: FFIType.i32,
+	},
+	pty_run: {
+		args: [
+			FFIType.ptr,
+			FFIType.u32,
+			FFIType.u64,
+			FFIType.ptr,
+			FFIType.ptr,
+			FFIType.ptr,
+			FFIType.ptr,
+			FFIType.ptr,
+			FFIType.ptr,
+		],
+		returns: FFIType.i32,
+	},
+	pty_run_with_input: {
+		args: [
+			FFIType.ptr,
+			FFIType.ptr,
+			FFIType.u64,
+			FFIType.ptr,
+			FFIType.u64,
+			FFIType.ptr,
+			FFIType.ptr,
+			FFIType.ptr,
+		],
+		returns: FFIType.i32,
+	},
+	pty_default_shell: {
+		args: [FFIType.ptr, FFIType.u64, FFIType.ptr],
+		returns: FFIType.i32,
+	},
+	pty_resolve_executable: {
+		args: [FFIType.ptr, FFIType.ptr, FFIType.u64, FFIType.ptr, FFIType.ptr],
+		returns: FFIType.i32,
+	},
+	pty_available_ptys: {
+		args: [FFIType.ptr, FFIType.ptr, FFIType.ptr],
+		returns: FFIType.i32,
+	},
+	pty_is_master_fd: {
+		args: [FFIType.i32, FFIType.ptr, FFIType.ptr],
+		returns: FFIType.i32,
+	},
+	pty_backend: {
+		args: [],
+		returns: FFIType.i32,
+	},
+	pty_arg_max: {
+		args: [],
+		returns: FFIType.u64,
+	},
+	pty_force_backend: {
+		args: [FFIType.i32, FFIType.ptr],
+		returns: FFIType.i32,
+	},
+	pty_open_with_system: {
+		args: [
+			FFIType.i32,
+			FFIType.u16,
+			FFIType.u16,
+			FFIType.ptr,
+			FFIType.ptr,
+			FFIType.ptr,
+		],
 		returns: FFIType.i32,
 	},
 	pty_free_master: { args: [FFIType.ptr], returns: FFIType.void },
+	pty_free_slave: { args: [FFIType.ptr], returns: FFIType.void },
 	pty_free_child: { args: [FFIType.ptr], returns: FFIType.void },
 	pty_free_reader: { args: [FFIType.ptr], returns: FFIType.void },
 	pty_free_writer: { args: [FFIType.ptr], returns: FFIType.void },
+	pty_free_builder: { args: [FFIType.ptr], returns: FFIType.void },
+	pty_free_channel: { args: [FFIType.ptr], returns: FFIType.void },
+	pty_free_vt: { args: [FFIType.ptr], returns: FFIType.void },
 	pty_free_err_msg: { args: [FFIType.ptr], returns: FFIType.void },
+	pty_err_msg_len: { args: [FFIType.ptr], returns: FFIType.u64 },
+	pty_free_string: { args: [FFIType.ptr], returns: FFIType.void },
+	pty_set_panic_mode: { args: [FFIType.i32], returns: FFIType.i32 },
 } as const);
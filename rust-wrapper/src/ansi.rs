@@ -0,0 +1,152 @@
+//! Minimal scanner for terminal escape sequences.
+//!
+//! It only finds where sequences start and end; it does not interpret them. Covered forms:
+//! CSI (`ESC [` ... final byte), OSC (`ESC ]` ... BEL or ST), SS3 (`ESC O` + one byte),
+//! the DCS/SOS/PM/APC string sequences (terminated like OSC), and two-byte `ESC` escapes
+//! with optional intermediates. 8-bit C1 introducers are not recognised, since they
+//! collide with UTF-8 continuation bytes.
+
+use crate::error::ffi_call;
+use crate::{buf_ref, out_ref};
+
+const ESC: u8 = 0x1b;
+const BEL: u8 = 0x07;
+
+/// Result of `pty_ansi_scan`.
+#[repr(C)]
+#[derive(Default)]
+pub struct PtyAnsiScan {
+    /// Complete CSI sequences.
+    pub csi: usize,
+    /// Complete OSC sequences.
+    pub osc: usize,
+    /// Complete SS3 sequences.
+    pub ss3: usize,
+    /// Other complete escapes: DCS/SOS/PM/APC strings, two-byte escapes, and malformed
+    /// sequences cut short by an unexpected byte.
+    pub other: usize,
+    /// Length of the longest prefix that does not end inside an escape sequence. Equal to
+    /// the buffer length unless the buffer ends with an incomplete sequence.
+    pub safe_len: usize,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Kind {
+    Csi,
+    Osc,
+    Ss3,
+    Other,
+}
+
+/// Measures the escape sequence at the start of `buf`, which must begin with ESC.
+/// Returns its kind and length, or `None` if `buf` ends before the sequence does.
+pub(crate) fn sequence_at(buf: &[u8]) -> Option<(Kind, usize)> {
+    debug_assert_eq!(buf.first(), Some(&ESC));
+    let introducer = *buf.get(1)?;
+    match introducer {
+        b'[' => {
+            for (i, &c) in buf.iter().enumerate().skip(2) {
+                match c {
+                    0x40..=0x7e => return Some((Kind::Csi, i + 1)),
+                    0x20..=0x3f => {}
+                    _ => return Some((Kind::Other, i)),
+                }
+            }
+            None
+        }
+        b']' | b'P' | b'X' | b'^' | b'_' => {
+            let kind = if introducer == b']' {
+                Kind::Osc
+            } else {
+                Kind::Other
+            };
+            string_end(buf, 2).map(|end| (kind, end))
+        }
+        b'O' => buf.get(2).map(|_| (Kind::Ss3, 3)),
+        0x20..=0x2f => {
+            for (i, &c) in buf.iter().enumerate().skip(2) {
+                match c {
+                    0x30..=0x7e => return Some((Kind::Other, i + 1)),
+                    0x20..=0x2f => {}
+                    _ => return Some((Kind::Other, i)),
+                }
+            }
+            None
+        }
+        0x30..=0x7e => Some((Kind::Other, 2)),
+        // ESC followed by a control byte: the lone ESC is the whole sequence.
+        _ => Some((Kind::Other, 1)),
+    }
+}
+
+/// Finds the end of a string sequence body starting at `from`: just past BEL or ST
+/// (`ESC \`). An ESC that does not start ST ends the string without being consumed.
+fn string_end(buf: &[u8], from: usize) -> Option<usize> {
+    let mut i = from;
+    while i < buf.len() {
+        match buf[i] {
+            BEL => return Some(i + 1),
+            ESC => {
+                return match buf.get(i + 1) {
+                    Some(b'\\') => Some(i + 2),
+                    Some(_) => Some(i),
+                    None => None,
+                };
+            }
+            _ => i += 1,
+        }
+    }
+    None
+}
+
+/// Counts the complete escape sequences in `buf` and finds the safe split point.
+pub(crate) fn scan(buf: &[u8]) -> PtyAnsiScan {
+    let mut result = PtyAnsiScan::default();
+    let mut i = 0;
+    while let Some(offset) = buf[i..].iter().position(|&c| c == ESC) {
+        let start = i + offset;
+        let Some((kind, len)) = sequence_at(&buf[start..]) else {
+            result.safe_len = start;
+            return result;
+        };
+        match kind {
+            Kind::Csi => result.csi += 1,
+            Kind::Osc => result.osc += 1,
+            Kind::Ss3 => result.ss3 += 1,
+            Kind::Other => result.other += 1,
+        }
+        i = start + len;
+    }
+    result.safe_len = buf.len();
+    result
+}
+
+/// Scan a buffer of terminal output for escape sequences.
+/// Returns 0 on success, -1 on error.
+///
+/// Fills `out_scan` with per-kind counts of the complete sequences found and with
+/// `safe_len`, the offset at which the buffer can be split without cutting a sequence in
+/// two. Callers that buffer output can hand `buf[..safe_len]` on and keep the rest until
+/// more data arrives.
+///
+/// # Safety
+///
+/// Caller must ensure:
+/// - `buf` is a valid pointer to at least `len` readable bytes (it may be null if `len` is 0).
+/// - `out_scan` is a valid, non-null pointer to mutable memory.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn pty_ansi_scan(
+    buf: *const u8,
+    len: usize,
+    out_scan: *mut PtyAnsiScan,
+) -> i32 {
+    ffi_call("pty_ansi_scan", std::ptr::null_mut(), || unsafe {
+        let out_scan = out_ref(out_scan)?;
+        *out_scan = if len == 0 {
+            scan(&[])
+        } else {
+            scan(buf_ref(buf, len)?)
+        };
+        Ok(0)
+    })
+}
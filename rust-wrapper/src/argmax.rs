@@ -0,0 +1,107 @@
+//! Checking a command against the platform's limit on the size of arguments and environment.
+//!
+//! Exceeding it makes the spawn fail with E2BIG ("Argument list too long") from deep inside
+//! exec, which says nothing about how far over the limit the command is. Spawns check up
+//! front instead and fail with `PTY_ERR_ARGS_TOO_LONG` and the sizes involved.
+
+use crate::error::{PTY_ERR_ARGS_TOO_LONG, PtyError, PtyResult};
+use portable_pty::CommandBuilder;
+
+/// Longest command line `CreateProcessW` accepts, in UTF-16 units.
+#[cfg(windows)]
+const COMMAND_LINE_MAX: usize = 32767;
+
+/// The platform's limit in bytes: on Unix the space exec has for arguments, environment and
+/// their pointers (`ARG_MAX`); on Windows the longest command line.
+pub(crate) fn arg_max() -> usize {
+    #[cfg(unix)]
+    {
+        match unsafe { libc::sysconf(libc::_SC_ARG_MAX) } {
+            n if n > 0 => n as usize,
+            // POSIX minimum.
+            _ => 4096,
+        }
+    }
+    #[cfg(windows)]
+    {
+        COMMAND_LINE_MAX
+    }
+}
+
+/// Longest single argument or environment entry Linux accepts, including its NUL.
+#[cfg(target_os = "linux")]
+fn arg_strlen_max() -> usize {
+    (unsafe { libc::sysconf(libc::_SC_PAGESIZE) }.max(4096) as usize) * 32
+}
+
+/// Fails with `PTY_ERR_ARGS_TOO_LONG` if exec would reject `cmd` for its size.
+pub(crate) fn check(cmd: &CommandBuilder) -> PtyResult<()> {
+    let argv = cmd.get_argv();
+    #[cfg(unix)]
+    {
+        use std::os::unix::ffi::OsStrExt;
+        let env: Vec<usize> = cmd
+            .iter_full_env_as_str()
+            .map(|(key, value)| key.len() + 1 + value.len())
+            .collect();
+        let args = argv.iter().map(|arg| arg.as_bytes().len());
+        #[cfg(target_os = "linux")]
+        if let Some(len) = args.clone().chain(env.iter().copied()).max()
+            && len + 1 > arg_strlen_max()
+        {
+            return Err(PtyError::with_code(
+                PTY_ERR_ARGS_TOO_LONG,
+                format!(
+                    "Arguments too long: one argument or environment entry is {} bytes, more \
+                     than the {} the system allows for each",
+                    len + 1,
+                    arg_strlen_max()
+                ),
+            ));
+        }
+        let strings: usize = args.chain(env.iter().copied()).map(|len| len + 1).sum();
+        let pointers = (argv.len() + env.len() + 2) * size_of::<*const libc::c_char>();
+        let total = strings + pointers;
+        if total > arg_max() {
+            return Err(PtyError::with_code(
+                PTY_ERR_ARGS_TOO_LONG,
+                format!(
+                    "Arguments too long: arguments and environment take {total} bytes, more \
+                     than the {} the system allows",
+                    arg_max()
+                ),
+            ));
+        }
+    }
+    #[cfg(windows)]
+    {
+        // Each argument plus a separating space; quoting can add a little more.
+        let total: usize = argv
+            .iter()
+            .map(|arg| arg.to_string_lossy().encode_utf16().count() + 1)
+            .sum();
+        if total > COMMAND_LINE_MAX {
+            return Err(PtyError::with_code(
+                PTY_ERR_ARGS_TOO_LONG,
+                format!(
+                    "Arguments too long: the command line is {total} characters, more than \
+                     the {COMMAND_LINE_MAX} Windows allows"
+                ),
+            ));
+        }
+    }
+    Ok(())
+}
+
+/// Get the platform's limit on the size of a spawned command's arguments, in bytes.
+///
+/// On Unix this is `ARG_MAX`: the space exec has for the arguments and environment
+/// together, each string counted with its terminating NUL plus a pointer to it. On Linux
+/// it follows the stack size limit, and each single argument or environment entry is
+/// further limited to 32 pages (128 KiB). On Windows it is the longest command line,
+/// 32767 characters; the environment is not counted. Spawns that would exceed either
+/// limit fail with `PTY_ERR_ARGS_TOO_LONG` (-6) before anything is started.
+#[unsafe(no_mangle)]
+pub extern "C" fn pty_arg_max() -> usize {
+    arg_max()
+}
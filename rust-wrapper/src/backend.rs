@@ -0,0 +1,156 @@
+//! Which PTY implementation spawns use, and opening a PTY with an explicit one.
+//!
+//! Unix always uses the system's pseudo-terminals. On Windows, portable-pty 0.9 only ships
+//! ConPTY: its winpty fallback was removed, so winpty can be named but never selected.
+
+use crate::error::{PTY_ERR_UNSUPPORTED, PtyError, PtyResult, ffi_call};
+use crate::handle::into_handle;
+use crate::{Master, MasterHandle, Slave, SlaveHandle, out_ref, slave_device};
+use portable_pty::{PtySize, PtySystem, native_pty_system};
+use std::sync::atomic::{AtomicI32, Ordering};
+
+/// Pick the platform's backend automatically.
+const PTY_BACKEND_AUTO: i32 = 0;
+/// Unix pseudo-terminals (`openpty`).
+const PTY_BACKEND_UNIX: i32 = 1;
+/// Windows pseudoconsoles (`CreatePseudoConsole`, Windows 10 1809 and later).
+const PTY_BACKEND_CONPTY: i32 = 2;
+/// winpty, for Windows versions without ConPTY. Not available in this build.
+const PTY_BACKEND_WINPTY: i32 = 3;
+
+/// The backend `pty_force_backend` pinned, or `PTY_BACKEND_AUTO`.
+static FORCED: AtomicI32 = AtomicI32::new(PTY_BACKEND_AUTO);
+
+/// The only backend this build can use.
+fn native_backend() -> i32 {
+    if cfg!(windows) {
+        PTY_BACKEND_CONPTY
+    } else {
+        PTY_BACKEND_UNIX
+    }
+}
+
+fn backend_name(backend: i32) -> &'static str {
+    match backend {
+        PTY_BACKEND_AUTO => "automatic",
+        PTY_BACKEND_UNIX => "Unix PTY",
+        PTY_BACKEND_CONPTY => "ConPTY",
+        PTY_BACKEND_WINPTY => "winpty",
+        _ => "unknown",
+    }
+}
+
+/// Error for a known backend this platform does not have.
+fn unavailable(backend: i32) -> PtyError {
+    PtyError::with_code(
+        PTY_ERR_UNSUPPORTED,
+        format!(
+            "The {} backend is not available on this platform",
+            backend_name(backend)
+        ),
+    )
+}
+
+/// The PTY system implementing `backend`, with `PTY_BACKEND_AUTO` meaning the current one.
+fn pty_system(backend: i32) -> PtyResult<Box<dyn PtySystem + Send>> {
+    let backend = match backend {
+        PTY_BACKEND_AUTO => pty_backend(),
+        backend => backend,
+    };
+    match backend {
+        #[cfg(unix)]
+        PTY_BACKEND_UNIX => Ok(Box::new(portable_pty::unix::UnixPtySystem::default())),
+        #[cfg(windows)]
+        PTY_BACKEND_CONPTY => Ok(Box::new(portable_pty::win::conpty::ConPtySystem::default())),
+        PTY_BACKEND_UNIX..=PTY_BACKEND_WINPTY => Err(unavailable(backend)),
+        _ => Err(PtyError::new(format!("Unknown PTY backend: {backend}"))),
+    }
+}
+
+/// Get the PTY backend spawns use: 1 = Unix PTY, 2 = ConPTY, 3 = winpty.
+#[unsafe(no_mangle)]
+pub extern "C" fn pty_backend() -> i32 {
+    match FORCED.load(Ordering::Relaxed) {
+        PTY_BACKEND_AUTO => native_backend(),
+        forced => forced,
+    }
+}
+
+/// Pin the PTY backend: 0 = automatic (the default), 1 = Unix PTY, 2 = ConPTY, 3 = winpty.
+/// Returns 0 on success, -1 on error, -2 if the backend is not available on this platform; sets out_err_msg (caller frees via pty_free_err_msg).
+///
+/// Forcing ConPTY checks that the running Windows version provides it by opening and
+/// closing a pseudoconsole, so an old system fails here rather than on the first spawn.
+/// winpty is not supported by this build and always fails. The setting is process-wide.
+///
+/// # Safety
+///
+/// Caller must ensure:
+/// - `out_err_msg` is a valid, non-null pointer to mutable memory.
+/// - The caller must free any error message using `pty_free_err_msg`.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn pty_force_backend(
+    backend: i32,
+    out_err_msg: *mut *mut libc::c_char,
+) -> i32 {
+    ffi_call("pty_force_backend", out_err_msg, || {
+        match backend {
+            PTY_BACKEND_AUTO => {}
+            PTY_BACKEND_UNIX | PTY_BACKEND_CONPTY | PTY_BACKEND_WINPTY => {
+                if backend != native_backend() {
+                    return Err(unavailable(backend));
+                }
+                if backend == PTY_BACKEND_CONPTY {
+                    native_pty_system()
+                        .openpty(PtySize::default())
+                        .map_err(|e| PtyError::new(format!("ConPTY is not available: {e}")))?;
+                }
+            }
+            _ => return Err(PtyError::new(format!("Unknown PTY backend: {backend}"))),
+        }
+        FORCED.store(backend, Ordering::Relaxed);
+        Ok(0)
+    })
+}
+
+/// Open a PTY with an explicitly chosen backend: 0 = the current one, 1 = Unix PTY, 2 = ConPTY, 3 = winpty.
+/// Returns 0 on success, -1 on error, -2 if the backend is not available on this platform; sets out_err_msg (caller frees via pty_free_err_msg).
+///
+/// Like `pty_open`, but names the PTY implementation instead of going through the
+/// automatic selection, which helps tell a backend problem apart from an environment one.
+/// Each platform has exactly one backend in this build (see `pty_backend`), so any other
+/// choice fails with -2. Spawn on the returned slave with `pty_slave_respawn`.
+///
+/// # Safety
+///
+/// Caller must ensure:
+/// - `master_out`, `slave_out`, and `out_err_msg` are valid, non-null pointers to mutable memory.
+/// - The caller must free any error message returned in `out_err_msg` using `pty_free_err_msg`.
+/// - Handles returned in `master_out` and `slave_out` must be freed using `pty_free_master` and `pty_free_slave` respectively.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn pty_open_with_system(
+    system: i32,
+    rows: u16,
+    cols: u16,
+    master_out: *mut MasterHandle,
+    slave_out: *mut SlaveHandle,
+    out_err_msg: *mut *mut libc::c_char,
+) -> i32 {
+    ffi_call("pty_open_with_system", out_err_msg, || unsafe {
+        let master_out = out_ref(master_out)?;
+        let slave_out = out_ref(slave_out)?;
+        let pair = pty_system(system)?.openpty(PtySize {
+            rows,
+            cols,
+            pixel_width: 0,
+            pixel_height: 0,
+        })?;
+        let slave = Slave {
+            tty_dev: slave_device(&*pair.master),
+            inner: pair.slave,
+        };
+        *master_out = into_handle(Master::new(pair.master));
+        *slave_out = into_handle(slave);
+        Ok(0)
+    })
+}
@@ -0,0 +1,761 @@
+//! Spawn options behind `BuilderHandle`.
+//!
+//! A builder collects everything needed to open a PTY and spawn a command into it, so
+//! functions that spawn internally (like `pty_run_capture`) take one handle instead of a
+//! growing list of parameters. New options are added as setters, keeping the ABI stable.
+
+use crate::error::{PtyError, PtyResult, ffi_call};
+use crate::handle::{free_handle, handle_mut, into_handle};
+use crate::{
+    BuilderHandle, Child, ChildHandle, Master, MasterHandle, buf_ref, command_from_argv, out_ref,
+    str_from_ptr, strings_from_argv,
+};
+use portable_pty::{CommandBuilder, PtySize};
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::path::PathBuf;
+
+/// `pty_builder_stdio` target: the PTY (the default).
+const PTY_STDIO_PTY: i32 = 0;
+/// `pty_builder_stdio` target: the null device.
+const PTY_STDIO_NULL: i32 = 1;
+/// `pty_builder_stdio` target: a file descriptor supplied by the caller.
+const PTY_STDIO_FD: i32 = 2;
+/// `pty_builder_stdio` target: a pipe read through `pty_get_stderr_reader` (stderr only).
+const PTY_STDIO_PIPE: i32 = 3;
+
+/// How long after spawn `pty_builder_conpty_settle` repeats the resize: long enough for
+/// ConPTY to have drawn its first frame.
+#[cfg(not(unix))]
+const CONPTY_SETTLE_DELAY: std::time::Duration = std::time::Duration::from_millis(50);
+
+/// Where one of the child's standard streams goes.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub(crate) enum StdioTarget {
+    Pty,
+    Null,
+    /// Duplicated at spawn time; the caller keeps ownership.
+    Fd(i32),
+    /// A pipe created at spawn time, its read end kept by the master.
+    Pipe,
+}
+
+#[derive(Clone)]
+pub(crate) struct Builder {
+    pub(crate) cmd: CommandBuilder,
+    pub(crate) size: PtySize,
+    /// Unix: start the child with default signal dispositions and an empty signal mask.
+    pub(crate) reset_signals: bool,
+    /// Unix: the child's RLIMIT_NOFILE, if lowered.
+    pub(crate) max_fds: Option<u64>,
+    /// Unix: make sure no descriptor above stderr survives into the child.
+    pub(crate) minimal_fds: bool,
+    /// Unix: targets of stdin, stdout and stderr.
+    pub(crate) stdio: [StdioTarget; 3],
+    /// Unix: window title announced to the host through OSC 2 at spawn.
+    pub(crate) title: Option<String>,
+    /// Unix: icon name announced to the host through OSC 1 at spawn.
+    pub(crate) icon_name: Option<String>,
+    /// Windows: repeat the initial resize shortly after spawn.
+    pub(crate) conpty_settle: bool,
+    /// File that receives a copy of all output.
+    pub(crate) output_log: Option<PathBuf>,
+    /// Linux: `PTY_NS_*` flags of the namespaces the child is started in.
+    pub(crate) namespaces: u32,
+    /// Unix: the name the program sees as argv[0], if not the program as given.
+    pub(crate) arg0: Option<String>,
+    /// Linux: CPUs the child may run on, bit i of word w selecting CPU 64 * w + i; empty
+    /// to inherit.
+    pub(crate) cpu_affinity: Vec<u64>,
+}
+
+impl Builder {
+    /// A builder for `cmd` on a PTY of `size` with every other option at its default.
+    pub(crate) fn new(cmd: CommandBuilder, size: PtySize) -> Self {
+        Builder {
+            cmd,
+            size,
+            reset_signals: true,
+            max_fds: None,
+            minimal_fds: false,
+            stdio: [StdioTarget::Pty; 3],
+            title: None,
+            icon_name: None,
+            conpty_settle: false,
+            output_log: None,
+            namespaces: 0,
+            arg0: None,
+            cpu_affinity: Vec::new(),
+        }
+    }
+
+    /// Opens a PTY of the configured size and spawns the configured command on it.
+    pub(crate) fn spawn(&self) -> PtyResult<(Master, Child)> {
+        crate::argmax::check(&self.cmd)?;
+        let log = match &self.output_log {
+            Some(path) => Some(
+                OpenOptions::new()
+                    .create(true)
+                    .append(true)
+                    .open(path)
+                    .map_err(|e| {
+                        PtyError::new(format!("Unable to open output log {}: {e}", path.display()))
+                    })?,
+            ),
+            None => None,
+        };
+        let (mut master, child) = self.spawn_child()?;
+        if let Some(log) = log {
+            let inner = master.inner.try_clone_reader()?;
+            master.log_pump = Some(crate::reader::Pump::start(inner, Some(log)));
+        }
+        Ok((master, child))
+    }
+
+    fn spawn_child(&self) -> PtyResult<(Master, Child)> {
+        #[cfg(unix)]
+        return crate::spawn::spawn(self);
+        #[cfg(not(unix))]
+        {
+            let (master, child) = crate::spawn_pty(self.size, self.cmd.clone())?;
+            if self.conpty_settle {
+                std::thread::sleep(CONPTY_SETTLE_DELAY);
+                master.inner.resize(self.size)?;
+            }
+            Ok((master, child))
+        }
+    }
+}
+
+/// Create a builder for spawning `prog` on a 24x80 PTY.
+/// Returns 0 on success, -1 on error; sets out_err_msg (caller frees via pty_free_err_msg).
+///
+/// # Safety
+///
+/// Caller must ensure:
+/// - `prog` is a valid, non-null pointer to a null-terminated C string.
+/// - `builder_out` and `out_err_msg` are valid, non-null pointers to mutable memory.
+/// - The handle returned in `builder_out` must be freed using `pty_free_builder`.
+/// - The caller must free any error message using `pty_free_err_msg`.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn pty_builder_new(
+    prog: *const libc::c_char,
+    builder_out: *mut BuilderHandle,
+    out_err_msg: *mut *mut libc::c_char,
+) -> i32 {
+    ffi_call("pty_builder_new", out_err_msg, || unsafe {
+        let builder_out = out_ref(builder_out)?;
+        *builder_out = into_handle(Builder::new(
+            command_from_argv(prog, std::ptr::null(), 0)?,
+            PtySize::default(),
+        ));
+        Ok(0)
+    })
+}
+
+/// Create an independent copy of a builder, for launching many similar commands.
+/// Returns 0 on success, -1 on error; sets out_err_msg (caller frees via pty_free_err_msg).
+///
+/// The copy starts with everything configured so far: program, arguments, environment,
+/// size and every other option. Set up what a group of commands has in common once, such
+/// as an environment from `pty_builder_env_exact`, then clone it for each command and add
+/// only what differs, such as its arguments. The copy is deep: changing either builder
+/// afterwards does not affect the other, and each must be freed. A file descriptor given
+/// to `pty_builder_stdio` is shared by number, so it must stay open until both have
+/// spawned.
+///
+/// # Safety
+///
+/// Caller must ensure:
+/// - `builder` is a valid, non-null handle obtained from `pty_builder_new`.
+/// - `builder_out` and `out_err_msg` are valid, non-null pointers to mutable memory.
+/// - The handle returned in `builder_out` must be freed using `pty_free_builder`.
+/// - The caller must free any error message using `pty_free_err_msg`.
+/// - No concurrent mutation of the builder handle.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn pty_builder_clone(
+    builder: BuilderHandle,
+    builder_out: *mut BuilderHandle,
+    out_err_msg: *mut *mut libc::c_char,
+) -> i32 {
+    ffi_call("pty_builder_clone", out_err_msg, || unsafe {
+        let builder_struct = handle_mut(builder)?;
+        let builder_out = out_ref(builder_out)?;
+        *builder_out = into_handle(builder_struct.clone());
+        Ok(0)
+    })
+}
+
+/// Append arguments to the builder's command line.
+/// Returns 0 on success, -1 on error.
+///
+/// # Safety
+///
+/// Caller must ensure:
+/// - `builder` is a valid, non-null handle obtained from `pty_builder_new`.
+/// - If `argc` > 0, `argv` is a valid, non-null pointer to an array of `argc` pointers, each pointing to a null-terminated C string or null.
+/// - No concurrent access to the builder handle.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn pty_builder_args(
+    builder: BuilderHandle,
+    argv: *const *const libc::c_char,
+    argc: usize,
+) -> i32 {
+    ffi_call("pty_builder_args", std::ptr::null_mut(), || unsafe {
+        handle_mut(builder)?.cmd.args(strings_from_argv(argv, argc));
+        Ok(0)
+    })
+}
+
+/// Replace the environment of the builder's command with exactly the given variables.
+/// Returns 0 on success, -1 on error; sets out_err_msg (caller frees via pty_free_err_msg).
+///
+/// Each of the `count` entries is a `KEY=VALUE` string, split at the first `=`; null
+/// entries are skipped. Everything inherited from this process is dropped first, so the
+/// child sees only these variables, for runs that must not depend on the caller's
+/// environment. A key given twice takes the later value. If any entry lacks a `=` or has
+/// an empty key, the builder is left unchanged.
+///
+/// Without `PATH` among the entries, the program can only be found by a path containing a
+/// `/`. On Unix the spawn always sets `SHELL`: to the entry's value if one is given,
+/// otherwise to the user's login shell. On Windows, leave out `SYSTEMROOT` only
+/// deliberately: many programs, including anything using Winsock or cryptography, fail to
+/// start or misbehave without it.
+///
+/// # Safety
+///
+/// Caller must ensure:
+/// - `builder` is a valid, non-null handle obtained from `pty_builder_new`.
+/// - If `count` > 0, `entries` is a valid, non-null pointer to an array of `count` pointers, each pointing to a null-terminated C string or null.
+/// - `out_err_msg` is a valid, non-null pointer to mutable memory.
+/// - The caller must free any error message using `pty_free_err_msg`.
+/// - No concurrent access to the builder handle.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn pty_builder_env_exact(
+    builder: BuilderHandle,
+    entries: *const *const libc::c_char,
+    count: usize,
+    out_err_msg: *mut *mut libc::c_char,
+) -> i32 {
+    ffi_call("pty_builder_env_exact", out_err_msg, || unsafe {
+        let builder_struct = handle_mut(builder)?;
+        let vars = strings_from_argv(entries, count)
+            .into_iter()
+            .map(|entry| match entry.split_once('=') {
+                Some((key, value)) if !key.is_empty() => Ok((key.to_owned(), value.to_owned())),
+                _ => Err(PtyError::new(format!(
+                    "Invalid environment entry {entry:?}: expected KEY=VALUE"
+                ))),
+            })
+            .collect::<PtyResult<Vec<_>>>()?;
+        builder_struct.cmd.env_clear();
+        for (key, value) in vars {
+            builder_struct.cmd.env(key, value);
+        }
+        Ok(0)
+    })
+}
+
+/// Set the size of the PTY the builder opens.
+/// Returns 0 on success, -1 on error.
+///
+/// # Safety
+///
+/// Caller must ensure:
+/// - `builder` is a valid, non-null handle obtained from `pty_builder_new`.
+/// - No concurrent access to the builder handle.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn pty_builder_size(builder: BuilderHandle, rows: u16, cols: u16) -> i32 {
+    ffi_call("pty_builder_size", std::ptr::null_mut(), || unsafe {
+        let builder_struct = handle_mut(builder)?;
+        builder_struct.size.rows = rows;
+        builder_struct.size.cols = cols;
+        Ok(0)
+    })
+}
+
+/// Choose whether the child starts with a clean signal state (the default).
+/// Returns 0 on success, -1 on error.
+///
+/// When enabled, every signal disposition is reset to default and all signals are
+/// unblocked between fork and exec, so the child is not affected by signals the host
+/// process ignores (Bun ignores SIGPIPE, for example, which would otherwise stop a child
+/// from dying on a closed pipe). Disable it to let the child inherit the host's ignored
+/// signals and signal mask. Has no effect on Windows.
+///
+/// # Safety
+///
+/// Caller must ensure:
+/// - `builder` is a valid, non-null handle obtained from `pty_builder_new`.
+/// - No concurrent access to the builder handle.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn pty_builder_reset_signals(builder: BuilderHandle, enabled: bool) -> i32 {
+    ffi_call(
+        "pty_builder_reset_signals",
+        std::ptr::null_mut(),
+        || unsafe {
+            handle_mut(builder)?.reset_signals = enabled;
+            Ok(0)
+        },
+    )
+}
+
+/// Choose whether to guarantee that the child inherits no file descriptors besides stdin,
+/// stdout and stderr (Unix only).
+/// Returns 0 on success, -1 on error.
+///
+/// Spawning already closes the stray descriptors it finds by listing `/dev/fd`, but that
+/// is best effort: without `/proc` mounted, as in some containers and chroots, the list
+/// cannot be read and everything the host process has open leaks into the program. When
+/// enabled, every descriptor above 2 is also marked close-on-exec between fork and exec,
+/// so the kernel closes whatever is left when the program starts, whether or not it could
+/// be found. On Linux 5.11 and later that is a single `close_range` call. Elsewhere it
+/// takes one call per possible descriptor up to the RLIMIT_NOFILE soft limit, which with
+/// the limits of a million some systems set adds tens of milliseconds to every spawn.
+/// Off by default; has no effect on Windows.
+///
+/// # Safety
+///
+/// Caller must ensure:
+/// - `builder` is a valid, non-null handle obtained from `pty_builder_new`.
+/// - No concurrent access to the builder handle.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn pty_builder_minimal_fds(builder: BuilderHandle, enabled: bool) -> i32 {
+    ffi_call("pty_builder_minimal_fds", std::ptr::null_mut(), || unsafe {
+        handle_mut(builder)?.minimal_fds = enabled;
+        Ok(0)
+    })
+}
+
+/// Choose whether to resize the pseudoconsole again shortly after spawn (Windows only).
+/// Returns 0 on success, -1 on error.
+///
+/// ConPTY sometimes renders the first screen of output garbled or misaligned, because it
+/// lays out that frame before the console host and the child agree on the size. When
+/// enabled, spawning waits 50 ms and then resizes the pseudoconsole to the size it already
+/// has, which makes ConPTY redraw the screen with the settled layout. Spawning takes that
+/// much longer, and the redraw repeats output the host has already seen, so leave it off
+/// unless the first output comes out garbled. Off by default; has no effect on other
+/// platforms.
+///
+/// # Safety
+///
+/// Caller must ensure:
+/// - `builder` is a valid, non-null handle obtained from `pty_builder_new`.
+/// - No concurrent access to the builder handle.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn pty_builder_conpty_settle(builder: BuilderHandle, enabled: bool) -> i32 {
+    ffi_call(
+        "pty_builder_conpty_settle",
+        std::ptr::null_mut(),
+        || unsafe {
+            handle_mut(builder)?.conpty_settle = enabled;
+            Ok(0)
+        },
+    )
+}
+
+/// Cap the number of file descriptors the child may have open (Unix only).
+/// Returns 0 on success, -1 on error, -2 on other platforms.
+///
+/// Sets the child's RLIMIT_NOFILE soft and hard limits to `max_fds` between fork and exec,
+/// so any descriptor numbered `max_fds` or above cannot be opened and the call fails with
+/// EMFILE. Meant for tests that check a program does not leak descriptors. Stdin, stdout
+/// and stderr count against the limit, and a dynamically linked program needs one more to
+/// load its libraries, so below 4 it exits with a loader error right after starting.
+///
+/// # Safety
+///
+/// Caller must ensure:
+/// - `builder` is a valid, non-null handle obtained from `pty_builder_new`.
+/// - No concurrent access to the builder handle.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn pty_builder_max_fds(builder: BuilderHandle, max_fds: u64) -> i32 {
+    ffi_call("pty_builder_max_fds", std::ptr::null_mut(), || unsafe {
+        let builder_struct = handle_mut(builder)?;
+        #[cfg(unix)]
+        {
+            builder_struct.max_fds = Some(max_fds);
+            Ok(0)
+        }
+        #[cfg(not(unix))]
+        {
+            let _ = (builder_struct, max_fds);
+            Err(PtyError::unsupported("pty_builder_max_fds"))
+        }
+    })
+}
+
+/// Start the child in new Linux namespaces, as a lightweight sandbox (Linux only).
+/// Returns 0 on success, -1 on error, -2 on other platforms.
+///
+/// `flags` combines `PTY_NS_USER` (1), `PTY_NS_PID` (2), `PTY_NS_NET` (4) and
+/// `PTY_NS_MOUNT` (8); 0 turns namespaces off. The child enters them between fork and
+/// exec, so the command never runs outside:
+/// - User: the child keeps its user and group ids, mapped to themselves. Supplementary
+///   groups show up as the overflow group and cannot be changed, and capabilities held in
+///   the namespace do not extend outside it.
+/// - PID: the command becomes PID 1 of the namespace. Like any init it ignores signals it
+///   has no handler for, except SIGKILL, and everything left in the namespace is killed
+///   when it exits. The child handle refers to a small supervisor outside the namespace,
+///   which passes signals sent to it on to the command and exits the way the command does.
+/// - Net: no network access; the namespace only has a loopback interface, which is down.
+/// - Mount: a private copy of the mount table, so mounts made inside stay there. Combined
+///   with PID, /proc is remounted to show only the namespace's processes.
+///
+/// Creating namespaces takes CAP_SYS_ADMIN, except for a user namespace, which any user
+/// may create where the system allows it (some distributions disable or restrict
+/// unprivileged user namespaces). Together with `PTY_NS_USER` the others need no
+/// privileges, since the new user namespace owns them. When a namespace cannot be created
+/// the spawn fails with an error saying so, and no command runs.
+///
+/// # Safety
+///
+/// Caller must ensure:
+/// - `builder` is a valid, non-null handle obtained from `pty_builder_new`.
+/// - No concurrent access to the builder handle.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn pty_builder_namespaces(builder: BuilderHandle, flags: u32) -> i32 {
+    ffi_call("pty_builder_namespaces", std::ptr::null_mut(), || unsafe {
+        let builder_struct = handle_mut(builder)?;
+        #[cfg(target_os = "linux")]
+        {
+            if flags & !crate::namespace::PTY_NS_ALL != 0 {
+                return Err(PtyError::new(format!(
+                    "Unknown namespace flags: {flags:#x}"
+                )));
+            }
+            builder_struct.namespaces = flags;
+            Ok(0)
+        }
+        #[cfg(not(target_os = "linux"))]
+        {
+            let _ = (builder_struct, flags);
+            Err(PtyError::unsupported("pty_builder_namespaces"))
+        }
+    })
+}
+
+/// Pin the child to a set of CPUs (Linux only; ignored elsewhere).
+/// Returns 0 on success, -1 on error; sets out_err_msg (caller frees via pty_free_err_msg).
+///
+/// `mask` holds `words` 64-bit words, bit i of word w selecting CPU 64 * w + i, so a
+/// single word `0b1100` allows CPUs 2 and 3. The child is moved onto them with
+/// `sched_setaffinity` between fork and exec, and the processes it starts inherit the
+/// setting, which keeps e.g. a heavy build off the cores the UI runs on. `words` = 0
+/// drops the setting, so the child runs wherever this process may. A mask that selects
+/// no CPU the system has, or one beyond the 1024 the kernel interface covers, is
+/// rejected here. When the kernel refuses the mask at spawn time, because a cpuset
+/// confines this process to other CPUs, the spawn fails with EINVAL and no command
+/// runs. On other platforms the mask is accepted and has no effect.
+///
+/// # Safety
+///
+/// Caller must ensure:
+/// - `builder` is a valid, non-null handle obtained from `pty_builder_new`.
+/// - If `words` > 0, `mask` is a valid, non-null pointer to an array of `words` `u64`s.
+/// - `out_err_msg` is a valid, non-null pointer to mutable memory.
+/// - The caller must free any error message using `pty_free_err_msg`.
+/// - No concurrent access to the builder handle.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn pty_builder_cpu_affinity(
+    builder: BuilderHandle,
+    mask: *const u64,
+    words: usize,
+    out_err_msg: *mut *mut libc::c_char,
+) -> i32 {
+    ffi_call("pty_builder_cpu_affinity", out_err_msg, || unsafe {
+        let builder_struct = handle_mut(builder)?;
+        if words == 0 {
+            builder_struct.cpu_affinity.clear();
+            return Ok(0);
+        }
+        if mask.is_null() {
+            return Err("Null pointer provided".into());
+        }
+        let mask = std::slice::from_raw_parts(mask, words);
+        #[cfg(target_os = "linux")]
+        {
+            let cpus = (0..words * 64).filter(|&cpu| mask[cpu / 64] & (1 << (cpu % 64)) != 0);
+            if let Some(cpu) = cpus.clone().find(|&cpu| cpu >= libc::CPU_SETSIZE as usize) {
+                return Err(PtyError::new(format!(
+                    "CPU {cpu} is beyond the {} CPUs an affinity mask covers",
+                    libc::CPU_SETSIZE
+                )));
+            }
+            let configured = libc::sysconf(libc::_SC_NPROCESSORS_CONF).max(1) as usize;
+            if !cpus.clone().any(|cpu| cpu < configured) {
+                return Err(PtyError::new(format!(
+                    "The CPU mask selects none of the system's {configured} CPUs"
+                )));
+            }
+        }
+        builder_struct.cpu_affinity = mask.to_vec();
+        Ok(0)
+    })
+}
+
+/// Redirect one of the child's standard streams: 0 = stdin, 1 = stdout, 2 = stderr.
+/// Returns 0 on success, -1 on error, -2 if the redirection is not available on this platform.
+///
+/// `target` is 0 = the PTY (the default), 1 = the null device, 2 = the file descriptor
+/// `fd`, which is ignored for the other targets, or 3 = a pipe the library creates, for
+/// stderr only. Any combination is valid on Unix: for example stdout to the null device
+/// hides a command's output while its errors still show, and stderr to a pipe separates
+/// the two. With target 3, `pty_get_stderr_reader` on the spawned master returns a reader
+/// for the pipe; its output must be read, or the command blocks once the pipe is full, so
+/// the one-shot `pty_run*` functions reject it. For 2>&1 semantics leave both on the PTY,
+/// which merges them; to merge them somewhere else, pass the same fd for both. The child
+/// keeps the PTY as its controlling terminal whatever its streams are, so `/dev/tty`,
+/// Ctrl-C and resizes still reach it. `fd` is duplicated at spawn time and stays owned by
+/// the caller, who must keep it open until then. ConPTY always attaches all three streams
+/// to the pseudoconsole, so on Windows only target 0 is accepted.
+///
+/// # Safety
+///
+/// Caller must ensure:
+/// - `builder` is a valid, non-null handle obtained from `pty_builder_new`.
+/// - No concurrent access to the builder handle.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn pty_builder_stdio(
+    builder: BuilderHandle,
+    stream: i32,
+    target: i32,
+    fd: i32,
+) -> i32 {
+    ffi_call("pty_builder_stdio", std::ptr::null_mut(), || unsafe {
+        let builder_struct = handle_mut(builder)?;
+        let index = usize::try_from(stream)
+            .ok()
+            .filter(|&index| index < 3)
+            .ok_or_else(|| PtyError::new(format!("Unknown stream: {stream}")))?;
+        let target = match target {
+            PTY_STDIO_PTY => StdioTarget::Pty,
+            PTY_STDIO_NULL => StdioTarget::Null,
+            PTY_STDIO_FD if fd >= 0 => StdioTarget::Fd(fd),
+            PTY_STDIO_FD => return Err(PtyError::new(format!("Invalid file descriptor {fd}"))),
+            PTY_STDIO_PIPE if index == 2 => StdioTarget::Pipe,
+            PTY_STDIO_PIPE => return Err("Only stderr can be redirected to a pipe".into()),
+            _ => return Err(PtyError::new(format!("Unknown stdio target: {target}"))),
+        };
+        if cfg!(not(unix)) && target != StdioTarget::Pty {
+            return Err(PtyError::unsupported("pty_builder_stdio"));
+        }
+        builder_struct.stdio[index] = target;
+        Ok(0)
+    })
+}
+
+/// Reads an optional label for `pty_builder_title`, rejecting control characters, which
+/// would end the OSC sequence carrying it early.
+unsafe fn label_from_ptr(ptr: *const libc::c_char) -> PtyResult<Option<String>> {
+    if ptr.is_null() {
+        return Ok(None);
+    }
+    let label = unsafe { str_from_ptr(ptr) }?;
+    if label.chars().any(char::is_control) {
+        return Err(PtyError::new(format!(
+            "Title contains control characters: {label:?}"
+        )));
+    }
+    Ok(Some(label))
+}
+
+/// Set the window title and icon name announced when the builder spawns (Unix only).
+/// Returns 0 on success, -1 on error, -2 on other platforms.
+///
+/// At spawn, before the command starts, `title` is written to the PTY's output as OSC 2
+/// and `icon_name` as OSC 1, so they are the first thing a host reading the master sees
+/// and `pty_get_title` reports the title right away. Either may be null to leave it unset;
+/// the child can still change both later. This is cooperative: only terminals and UIs that
+/// interpret these sequences show the labels, and a host that does not should strip them.
+/// Control characters are rejected.
+///
+/// # Safety
+///
+/// Caller must ensure:
+/// - `builder` is a valid, non-null handle obtained from `pty_builder_new`.
+/// - `title` and `icon_name` are each null or a valid pointer to a null-terminated C string.
+/// - No concurrent access to the builder handle.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn pty_builder_title(
+    builder: BuilderHandle,
+    title: *const libc::c_char,
+    icon_name: *const libc::c_char,
+) -> i32 {
+    ffi_call("pty_builder_title", std::ptr::null_mut(), || unsafe {
+        let builder_struct = handle_mut(builder)?;
+        let title = label_from_ptr(title)?;
+        let icon_name = label_from_ptr(icon_name)?;
+        #[cfg(unix)]
+        {
+            builder_struct.title = title;
+            builder_struct.icon_name = icon_name;
+            Ok(0)
+        }
+        #[cfg(not(unix))]
+        {
+            let _ = (builder_struct, title, icon_name);
+            Err(PtyError::unsupported("pty_builder_title"))
+        }
+    })
+}
+
+/// Copy all output of PTYs the builder spawns to a file.
+/// Returns 0 on success, -1 on error.
+///
+/// The file at `path` is opened for appending (and created if missing) at each spawn, and a
+/// thread started with the child writes everything the PTY outputs to it while readers
+/// from `pty_get_reader` still receive the same output. The log is written as output is
+/// read from the PTY, at most 64 KiB ahead of the readers: when nobody reads, the child
+/// blocks on output as usual and the log waits with it. Output is logged raw, escape
+/// sequences included. A null `path` turns logging off.
+///
+/// Failing to open the file fails the spawn. A write that fails later only ends the
+/// logging, without disturbing the child or the readers; check with `pty_output_log_error`.
+///
+/// # Safety
+///
+/// Caller must ensure:
+/// - `builder` is a valid, non-null handle obtained from `pty_builder_new`.
+/// - `path` is null or a valid pointer to a null-terminated C string.
+/// - No concurrent access to the builder handle.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn pty_builder_output_log(
+    builder: BuilderHandle,
+    path: *const libc::c_char,
+) -> i32 {
+    ffi_call("pty_builder_output_log", std::ptr::null_mut(), || unsafe {
+        let builder_struct = handle_mut(builder)?;
+        builder_struct.output_log = if path.is_null() {
+            None
+        } else {
+            Some(PathBuf::from(str_from_ptr(path)?))
+        };
+        Ok(0)
+    })
+}
+
+/// Check whether the output log of a PTY spawned by a builder is still being written.
+/// Returns 0 on success, -1 on error; sets out_err_msg (caller frees via pty_free_err_msg).
+///
+/// Fails with the reason once a write to the log set by `pty_builder_output_log` has failed;
+/// nothing more is logged after that. Succeeds while the log is healthy and for masters
+/// spawned without a log.
+///
+/// # Safety
+///
+/// Caller must ensure:
+/// - `master` is a valid, non-null handle obtained from `pty_builder_spawn`.
+/// - `out_err_msg` is a valid, non-null pointer to mutable memory.
+/// - The caller must free any error message using `pty_free_err_msg`.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn pty_output_log_error(
+    master: MasterHandle,
+    out_err_msg: *mut *mut libc::c_char,
+) -> i32 {
+    ffi_call("pty_output_log_error", out_err_msg, || unsafe {
+        let master_struct = handle_mut(master)?;
+        match master_struct
+            .log_pump
+            .as_ref()
+            .and_then(|pump| pump.log_error())
+        {
+            Some(message) => Err(PtyError::new(message)),
+            None => Ok(0),
+        }
+    })
+}
+
+/// Open a PTY and spawn the builder's command on it. The builder is not consumed and can
+/// spawn again.
+/// Returns 0 on success, -1 on error; sets out_err_msg (caller frees via pty_free_err_msg).
+///
+/// # Safety
+///
+/// Caller must ensure:
+/// - `builder` is a valid, non-null handle obtained from `pty_builder_new`.
+/// - `master_out`, `child_out`, and `out_err_msg` are valid, non-null pointers to mutable memory.
+/// - Handles returned in `master_out` and `child_out` must be freed using `pty_free_master` and `pty_free_child` respectively.
+/// - The caller must free any error message using `pty_free_err_msg`.
+/// - No concurrent mutation of the builder handle.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn pty_builder_spawn(
+    builder: BuilderHandle,
+    master_out: *mut MasterHandle,
+    child_out: *mut ChildHandle,
+    out_err_msg: *mut *mut libc::c_char,
+) -> i32 {
+    ffi_call("pty_builder_spawn", out_err_msg, || unsafe {
+        let builder_struct = handle_mut(builder)?;
+        let master_out = out_ref(master_out)?;
+        let child_out = out_ref(child_out)?;
+        let (master, child) = builder_struct.spawn()?;
+        *master_out = into_handle(master);
+        *child_out = into_handle(child);
+        Ok(0)
+    })
+}
+
+/// Open a PTY, spawn the builder's command on it, and write `initial_input` before returning.
+/// Returns 0 on success, -1 on error; sets out_err_msg (caller frees via pty_free_err_msg).
+///
+/// For programs that read input as soon as they start, such as a command fed a heredoc:
+/// the input is already waiting in the PTY when they first read, so none of it can arrive
+/// late. The PTY echoes it like typed input unless the program turns echo off, which it
+/// can only do after the input arrived, so expect the echo in the output. The PTY holds
+/// about 4 KiB of unread input; a longer `initial_input` blocks until the program reads
+/// the rest. The writer used here is kept for `pty_get_writer`, which still works once.
+///
+/// # Safety
+///
+/// Caller must ensure:
+/// - `builder` is a valid, non-null handle obtained from `pty_builder_new`.
+/// - `initial_input` is a valid, non-null pointer to immutable memory of at least `initial_len` bytes.
+/// - `master_out`, `child_out`, and `out_err_msg` are valid, non-null pointers to mutable memory.
+/// - Handles returned in `master_out` and `child_out` must be freed using `pty_free_master` and `pty_free_child` respectively.
+/// - The caller must free any error message using `pty_free_err_msg`.
+/// - No concurrent mutation of the builder handle.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn pty_open_spawn_write(
+    builder: BuilderHandle,
+    initial_input: *const u8,
+    initial_len: usize,
+    master_out: *mut MasterHandle,
+    child_out: *mut ChildHandle,
+    out_err_msg: *mut *mut libc::c_char,
+) -> i32 {
+    ffi_call("pty_open_spawn_write", out_err_msg, || unsafe {
+        let builder_struct = handle_mut(builder)?;
+        let input = buf_ref(initial_input, initial_len)?;
+        let master_out = out_ref(master_out)?;
+        let child_out = out_ref(child_out)?;
+        let (mut master, mut child) = builder_struct.spawn()?;
+        let mut writer = master.inner.take_writer()?;
+        if let Err(e) = writer.write_all(input).and_then(|()| writer.flush()) {
+            // Reap the killed child so it does not linger as a zombie.
+            let _ = child.inner.kill();
+            let _ = child.wait();
+            return Err(PtyError::new(format!(
+                "Writing the initial input failed: {e}"
+            )));
+        }
+        master.writer = Some(writer);
+        *master_out = into_handle(master);
+        *child_out = into_handle(child);
+        Ok(0)
+    })
+}
+
+/// Free the builder handle.
+///
+/// # Safety
+///
+/// Caller must ensure:
+/// - `builder` is either null or a valid handle obtained from `pty_builder_new`.
+/// - The handle is not used after freeing.
+/// - No double-free (call at most once per handle).
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn pty_free_builder(builder: BuilderHandle) {
+    unsafe { free_handle(builder) }
+}
@@ -0,0 +1,167 @@
+//! Reader channels: a thread that drains a reader into a bounded queue of chunks.
+//!
+//! The thread blocks once the queue is full and stops reading, so a slow consumer slows the
+//! child down through the PTY instead of growing memory.
+
+use crate::error::{PtyError, ffi_call};
+use crate::handle::{free_handle, handle_mut, into_handle, take_handle};
+use crate::reader::Reader;
+use crate::{ChannelHandle, ReaderHandle, buf_mut, out_ref};
+use std::io::{self, Read};
+use std::sync::mpsc::{Receiver, RecvTimeoutError, SyncSender, TryRecvError, sync_channel};
+use std::thread;
+use std::time::Duration;
+
+const CHUNK_SIZE: usize = 4096;
+
+/// `pty_channel_recv` status: bytes were received.
+const PTY_RECV_DATA: i32 = 0;
+/// `pty_channel_recv` status: the timeout elapsed first.
+const PTY_RECV_TIMEOUT: i32 = 1;
+/// `pty_channel_recv` status: the PTY reached end of file and the queue is drained.
+const PTY_RECV_EOF: i32 = 2;
+
+pub(crate) struct Channel {
+    chunks: Receiver<io::Result<Vec<u8>>>,
+    /// Rest of a chunk that did not fit into the caller's buffer.
+    partial: Vec<u8>,
+}
+
+impl Channel {
+    pub(crate) fn spawn(reader: Reader, capacity: usize) -> Self {
+        let (tx, rx) = sync_channel(capacity);
+        thread::spawn(move || run_channel(reader, tx));
+        Channel {
+            chunks: rx,
+            partial: Vec::new(),
+        }
+    }
+
+    /// Copies the next available output into `buf`. Returns the status and byte count.
+    pub(crate) fn recv(&mut self, buf: &mut [u8], timeout: Duration) -> io::Result<(i32, usize)> {
+        if self.partial.is_empty() {
+            let next = if timeout.is_zero() {
+                self.chunks.try_recv().map_err(|e| match e {
+                    TryRecvError::Empty => RecvTimeoutError::Timeout,
+                    TryRecvError::Disconnected => RecvTimeoutError::Disconnected,
+                })
+            } else {
+                self.chunks.recv_timeout(timeout)
+            };
+            match next {
+                Ok(chunk) => self.partial = chunk?,
+                Err(RecvTimeoutError::Timeout) => return Ok((PTY_RECV_TIMEOUT, 0)),
+                Err(RecvTimeoutError::Disconnected) => return Ok((PTY_RECV_EOF, 0)),
+            }
+        }
+        let n = buf.len().min(self.partial.len());
+        buf[..n].copy_from_slice(&self.partial[..n]);
+        self.partial.drain(..n);
+        Ok((PTY_RECV_DATA, n))
+    }
+}
+
+/// Body of the channel thread. Exits at EOF, on a read error (which is queued for the
+/// consumer), or once the channel handle is freed.
+fn run_channel(mut reader: Reader, tx: SyncSender<io::Result<Vec<u8>>>) {
+    loop {
+        let mut chunk = vec![0u8; CHUNK_SIZE];
+        match reader.read(&mut chunk) {
+            Ok(0) => return,
+            Ok(n) => {
+                chunk.truncate(n);
+                if tx.send(Ok(chunk)).is_err() {
+                    return;
+                }
+            }
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+            Err(e) => {
+                let _ = tx.send(Err(e));
+                return;
+            }
+        }
+    }
+}
+
+/// Move a reader onto a background thread that queues its output in a bounded channel.
+/// Returns 0 on success, -1 on error; sets out_err_msg (caller frees via pty_free_err_msg).
+///
+/// Consumes the reader handle, which must not be used or freed afterwards. The thread
+/// queues up to `capacity` chunks of at most 4 KiB each and then blocks until the consumer
+/// catches up with `pty_channel_recv`, which leaves the child blocked on a full PTY rather
+/// than buffering without limit.
+///
+/// # Safety
+///
+/// Caller must ensure:
+/// - `reader` is a valid, non-null handle obtained from `pty_get_reader`.
+/// - `channel_out` and `out_err_msg` are valid, non-null pointers to mutable memory.
+/// - The handle returned in `channel_out` must be freed using `pty_free_channel`.
+/// - The caller must free any error message using `pty_free_err_msg`.
+/// - No concurrent access to the reader handle.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn pty_reader_channel(
+    reader: ReaderHandle,
+    capacity: usize,
+    channel_out: *mut ChannelHandle,
+    out_err_msg: *mut *mut libc::c_char,
+) -> i32 {
+    ffi_call("pty_reader_channel", out_err_msg, || unsafe {
+        let channel_out = out_ref(channel_out)?;
+        if capacity == 0 {
+            return Err(PtyError::new("Channel capacity must be at least 1"));
+        }
+        let reader_struct = take_handle(reader)?;
+        *channel_out = into_handle(Channel::spawn(reader_struct, capacity));
+        Ok(0)
+    })
+}
+
+/// Receive queued output from a channel, waiting at most `timeout_ms`.
+/// Returns 0 when bytes were received, 1 on timeout, 2 at EOF, -1 on error; sets out_err_msg (caller frees via pty_free_err_msg).
+///
+/// `out_len` receives the number of bytes stored in `buf`. A chunk larger than `len` is
+/// handed out over several calls. EOF is only reported once every queued chunk has been
+/// received. A `timeout_ms` of 0 never waits.
+///
+/// # Safety
+///
+/// Caller must ensure:
+/// - `channel` is a valid, non-null handle obtained from `pty_reader_channel`.
+/// - `buf` is a valid, non-null pointer to mutable memory of at least `len` bytes.
+/// - `out_len` and `out_err_msg` are valid, non-null pointers to mutable memory.
+/// - The caller must free any error message using `pty_free_err_msg`.
+/// - No concurrent access to the channel handle.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn pty_channel_recv(
+    channel: ChannelHandle,
+    buf: *mut u8,
+    len: usize,
+    timeout_ms: u32,
+    out_len: *mut usize,
+    out_err_msg: *mut *mut libc::c_char,
+) -> i32 {
+    ffi_call("pty_channel_recv", out_err_msg, || unsafe {
+        let channel_struct = handle_mut(channel)?;
+        let slice = buf_mut(buf, len)?;
+        let out_len = out_ref(out_len)?;
+        *out_len = 0;
+        let (status, n) =
+            channel_struct.recv(slice, Duration::from_millis(u64::from(timeout_ms)))?;
+        *out_len = n;
+        Ok(status)
+    })
+}
+
+/// Free the channel handle. The reader thread stops once it next has output to queue.
+///
+/// # Safety
+///
+/// Caller must ensure:
+/// - `channel` is either null or a valid handle obtained from `pty_reader_channel`.
+/// - The handle is not used after freeing.
+/// - No double-free (call at most once per handle).
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn pty_free_channel(channel: ChannelHandle) {
+    unsafe { free_handle(channel) }
+}
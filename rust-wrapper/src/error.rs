@@ -0,0 +1,179 @@
+//! Error plumbing shared by the FFI functions.
+//!
+//! Function bodies return `PtyResult<R>` and run inside `ffi_call`, which turns an error
+//! into its status code plus a message in `out_err_msg`, and a panic into -1 plus a
+//! "something is wrong in <function>" message, or into an abort if the host chose that
+//! with `pty_set_panic_mode`.
+
+use crate::set_err_msg;
+use std::fmt;
+use std::io;
+use std::panic::{AssertUnwindSafe, catch_unwind};
+use std::sync::atomic::{AtomicI32, Ordering};
+
+/// Generic failure.
+pub(crate) const PTY_ERR: i32 = -1;
+/// The function exists on every platform so bindings can load it, but not this one.
+pub(crate) const PTY_ERR_UNSUPPORTED: i32 = -2;
+/// A looked-up program, file or process does not exist.
+pub(crate) const PTY_ERR_NOT_FOUND: i32 = -3;
+/// Input can no longer be delivered because the child has exited.
+pub(crate) const PTY_ERR_CHILD_GONE: i32 = -4;
+/// A command produced more output than the caller allowed.
+pub(crate) const PTY_ERR_OUTPUT_TOO_LARGE: i32 = -5;
+/// A command's arguments and environment exceed what the platform can pass to it.
+pub(crate) const PTY_ERR_ARGS_TOO_LONG: i32 = -6;
+/// A PTY in non-blocking mode has nothing to read, or no room to write, right now.
+pub(crate) const PTY_ERR_WOULD_BLOCK: i32 = -7;
+/// A command produced no output within the time the caller allowed, and was killed.
+pub(crate) const PTY_ERR_NO_OUTPUT: i32 = -8;
+
+/// `pty_set_panic_mode` mode: a panic fails the call with -1 (the default).
+const PTY_PANIC_RECOVER: i32 = 0;
+/// `pty_set_panic_mode` mode: a panic aborts the process.
+const PTY_PANIC_ABORT: i32 = 1;
+
+/// The mode set by `pty_set_panic_mode`.
+static PANIC_MODE: AtomicI32 = AtomicI32::new(PTY_PANIC_RECOVER);
+
+pub(crate) struct PtyError {
+    pub(crate) code: i32,
+    pub(crate) message: String,
+}
+
+pub(crate) type PtyResult<T> = Result<T, PtyError>;
+
+impl PtyError {
+    pub(crate) fn new(message: impl Into<String>) -> Self {
+        PtyError::with_code(PTY_ERR, message)
+    }
+
+    pub(crate) fn with_code(code: i32, message: impl Into<String>) -> Self {
+        PtyError {
+            code,
+            message: message.into(),
+        }
+    }
+
+    pub(crate) fn not_found(message: impl Into<String>) -> Self {
+        PtyError::with_code(PTY_ERR_NOT_FOUND, message)
+    }
+
+    pub(crate) fn child_gone() -> Self {
+        PtyError::with_code(
+            PTY_ERR_CHILD_GONE,
+            "The child has exited; input can no longer be delivered",
+        )
+    }
+
+    pub(crate) fn unsupported(func: &str) -> Self {
+        PtyError::with_code(
+            PTY_ERR_UNSUPPORTED,
+            format!("{func} is not supported on this platform"),
+        )
+    }
+}
+
+impl fmt::Display for PtyError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.message)
+    }
+}
+
+impl From<String> for PtyError {
+    fn from(message: String) -> Self {
+        PtyError::new(message)
+    }
+}
+
+impl From<&str> for PtyError {
+    fn from(message: &str) -> Self {
+        PtyError::new(message)
+    }
+}
+
+impl From<io::Error> for PtyError {
+    fn from(e: io::Error) -> Self {
+        if e.kind() == io::ErrorKind::WouldBlock {
+            return PtyError::with_code(PTY_ERR_WOULD_BLOCK, e.to_string());
+        }
+        PtyError::new(e.to_string())
+    }
+}
+
+impl From<anyhow::Error> for PtyError {
+    fn from(e: anyhow::Error) -> Self {
+        PtyError::new(e.to_string())
+    }
+}
+
+/// Integer types FFI functions return status codes in.
+pub(crate) trait StatusCode {
+    fn from_code(code: i32) -> Self;
+}
+
+impl StatusCode for i32 {
+    fn from_code(code: i32) -> Self {
+        code
+    }
+}
+
+impl StatusCode for isize {
+    fn from_code(code: i32) -> Self {
+        code as isize
+    }
+}
+
+impl StatusCode for i64 {
+    fn from_code(code: i32) -> Self {
+        i64::from(code)
+    }
+}
+
+/// Runs the body of the FFI function `func`, reporting errors and panics through
+/// `out_err_msg` (which may be null for functions without one).
+pub(crate) fn ffi_call<R: StatusCode>(
+    func: &str,
+    out_err_msg: *mut *mut libc::c_char,
+    body: impl FnOnce() -> PtyResult<R>,
+) -> R {
+    match catch_unwind(AssertUnwindSafe(body)) {
+        Ok(Ok(value)) => value,
+        Ok(Err(e)) => {
+            unsafe { set_err_msg(out_err_msg, &e.message) };
+            R::from_code(e.code)
+        }
+        Err(_) => {
+            if PANIC_MODE.load(Ordering::Relaxed) == PTY_PANIC_ABORT {
+                std::process::abort();
+            }
+            unsafe { set_err_msg(out_err_msg, &format!("something is wrong in {func}")) };
+            R::from_code(PTY_ERR)
+        }
+    }
+}
+
+/// Choose what a panic inside a library call does: 0 = fail the call (the default), 1 = abort.
+/// Returns 0 on success, -1 on error.
+///
+/// A panic is a bug in this library. By default the call that hit it returns -1 with a
+/// "something is wrong in <function>" message, and the host carries on. `PTY_PANIC_ABORT`
+/// (1) aborts the process instead, after the panic message is printed to stderr, for hosts
+/// that would rather fail fast than keep running with state the panic may have left
+/// inconsistent. The setting is process-wide and applies to calls made after it.
+///
+/// Recovering is only possible because the library is built with Rust's default
+/// `panic = "unwind"` strategy, which lets `catch_unwind` stop a panic before it crosses
+/// into the host; a build with `panic = "abort"` aborts on every panic whatever the mode.
+/// Panics on the library's own background threads, such as reader pumps, never reach a
+/// call and are not affected.
+#[unsafe(no_mangle)]
+pub extern "C" fn pty_set_panic_mode(mode: i32) -> i32 {
+    ffi_call("pty_set_panic_mode", std::ptr::null_mut(), || {
+        if !matches!(mode, PTY_PANIC_RECOVER | PTY_PANIC_ABORT) {
+            return Err(PtyError::new(format!("Unknown panic mode: {mode}")));
+        }
+        PANIC_MODE.store(mode, Ordering::Relaxed);
+        Ok(0)
+    })
+}
@@ -0,0 +1,73 @@
+//! expect(1)-style helpers: send input, then wait for the program to answer.
+
+use crate::error::{PtyError, ffi_call};
+use crate::handle::handle_mut;
+use crate::{ReaderHandle, WriterHandle, buf_ref, write_error};
+use std::io::{self, Write};
+use std::time::{Duration, Instant};
+
+/// Returned by `pty_write_expect` when the timeout elapsed before a match.
+const PTY_EXPECT_TIMEOUT: i32 = 1;
+
+/// End offset of the first occurrence of `needle` in `haystack`, skipping the part before
+/// `searched` that was already ruled out.
+pub(crate) fn find_after(haystack: &[u8], searched: usize, needle: &[u8]) -> Option<usize> {
+    if needle.is_empty() {
+        return Some(0);
+    }
+    let start = searched.saturating_sub(needle.len() - 1);
+    haystack[start..]
+        .windows(needle.len())
+        .position(|window| window == needle)
+        .map(|pos| start + pos + needle.len())
+}
+
+/// Write `input`, then read until `expect` appears in the output.
+/// Returns 0 once `expect` was seen, 1 on timeout, -1 on error, -4 if the child has exited; sets out_err_msg (caller frees via pty_free_err_msg).
+///
+/// Output up to and including the first occurrence of `expect` is consumed; anything after
+/// it stays buffered in the reader for the next read. On timeout nothing is consumed, so
+/// the output that did arrive can still be read. Output ending before `expect` appears is
+/// an error. A `timeout_ms` of 0 only checks output that has already arrived.
+///
+/// # Safety
+///
+/// Caller must ensure:
+/// - `writer` is a valid, non-null handle obtained from `pty_get_writer`, and `reader` one obtained from `pty_get_reader`.
+/// - `input` and `expect` are valid, non-null pointers to immutable memory of at least `input_len` and `expect_len` bytes.
+/// - `out_err_msg` is a valid, non-null pointer to mutable memory.
+/// - The caller must free any error message using `pty_free_err_msg`.
+/// - No concurrent access to the writer or reader handle.
+#[unsafe(no_mangle)]
+#[allow(clippy::too_many_arguments)]
+pub unsafe extern "C" fn pty_write_expect(
+    writer: WriterHandle,
+    reader: ReaderHandle,
+    input: *const u8,
+    input_len: usize,
+    expect: *const u8,
+    expect_len: usize,
+    timeout_ms: u32,
+    out_err_msg: *mut *mut libc::c_char,
+) -> i32 {
+    ffi_call("pty_write_expect", out_err_msg, || unsafe {
+        let writer_struct = handle_mut(writer)?;
+        let reader_struct = handle_mut(reader)?;
+        let input = buf_ref(input, input_len)?;
+        let expect = buf_ref(expect, expect_len)?;
+
+        let deadline = Instant::now() + Duration::from_millis(u64::from(timeout_ms));
+        writer_struct.inner.write_all(input).map_err(write_error)?;
+        writer_struct.inner.flush().map_err(write_error)?;
+        match reader_struct.read_until(Some(deadline), |pending, searched| {
+            find_after(pending, searched, expect)
+        }) {
+            Ok(Some(_)) => Ok(0),
+            Ok(None) => Ok(PTY_EXPECT_TIMEOUT),
+            Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => Err(PtyError::new(
+                "Output ended before the expected text appeared",
+            )),
+            Err(e) => Err(e.into()),
+        }
+    })
+}
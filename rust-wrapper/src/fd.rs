@@ -0,0 +1,74 @@
+//! Helpers for raw PTY file descriptors.
+
+use crate::error::{PtyError, ffi_call};
+#[cfg(unix)]
+use crate::error::PtyResult;
+use crate::out_ref;
+#[cfg(unix)]
+use portable_pty::MasterPty;
+#[cfg(unix)]
+use std::os::fd::OwnedFd;
+
+/// Opens the slave side of `master` through its device path, for work only the slave can
+/// do. Read-write, close-on-exec, and never made the caller's controlling terminal.
+#[cfg(unix)]
+pub(crate) fn open_slave(master: &dyn MasterPty) -> PtyResult<OwnedFd> {
+    use std::os::unix::fs::OpenOptionsExt;
+    let path = master
+        .tty_name()
+        .ok_or("Unable to determine the slave device path")?;
+    let slave = std::fs::OpenOptions::new()
+        .read(true)
+        .write(true)
+        .custom_flags(libc::O_NOCTTY | libc::O_CLOEXEC)
+        .open(path)?;
+    Ok(slave.into())
+}
+
+/// The index N of the `/dev/pts/N` slave belonging to master `fd`, or `None` if `fd` is not
+/// a PTY master.
+#[cfg(target_os = "linux")]
+pub(crate) fn pts_index(fd: libc::c_int) -> PtyResult<Option<u32>> {
+    let mut index: libc::c_uint = 0;
+    if unsafe { libc::ioctl(fd, libc::TIOCGPTN, &mut index) } == 0 {
+        return Ok(Some(index));
+    }
+    let err = std::io::Error::last_os_error();
+    match err.raw_os_error() {
+        Some(libc::EBADF) => Err(PtyError::new(format!("Invalid file descriptor {fd}"))),
+        // ENOTTY for non-terminals and slaves, EINVAL for some other devices.
+        _ => Ok(None),
+    }
+}
+
+/// Check whether `fd` is the master side of a PTY (Linux only).
+/// Returns 0 on success, -1 on error, -2 on other platforms; sets out_err_msg (caller frees via pty_free_err_msg).
+///
+/// `out_is_master` receives 1 if `fd` is a PTY master and 0 for anything else, including
+/// PTY slaves. A closed or invalid `fd` is an error.
+///
+/// # Safety
+///
+/// Caller must ensure:
+/// - `out_is_master` and `out_err_msg` are valid, non-null pointers to mutable memory.
+/// - The caller must free any error message using `pty_free_err_msg`.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn pty_is_master_fd(
+    fd: i32,
+    out_is_master: *mut i32,
+    out_err_msg: *mut *mut libc::c_char,
+) -> i32 {
+    ffi_call("pty_is_master_fd", out_err_msg, || unsafe {
+        let out_is_master = out_ref(out_is_master)?;
+        #[cfg(target_os = "linux")]
+        {
+            *out_is_master = i32::from(pts_index(fd)?.is_some());
+            Ok(0)
+        }
+        #[cfg(not(target_os = "linux"))]
+        {
+            let _ = (fd, out_is_master);
+            Err(PtyError::unsupported("pty_is_master_fd"))
+        }
+    })
+}
@@ -0,0 +1,203 @@
+//! Keeping PTYs the same size as the host terminal.
+//!
+//! On Unix the first `pty_follow_host_resize` installs a SIGWINCH handler that only writes
+//! to a pipe (the one thing a handler may safely do). A background thread waits on that
+//! pipe and copies the host's size to every following master. Any handler the host had
+//! installed before keeps being called.
+
+use crate::MasterHandle;
+#[cfg(not(unix))]
+use crate::error::PtyError;
+#[cfg(unix)]
+use crate::error::PtyResult;
+use crate::error::ffi_call;
+use crate::handle::handle_mut;
+#[cfg(unix)]
+use std::io;
+#[cfg(unix)]
+use std::os::fd::RawFd;
+#[cfg(unix)]
+use std::sync::atomic::{AtomicI32, Ordering};
+#[cfg(unix)]
+use std::sync::{Mutex, OnceLock};
+
+/// Master fds that follow the host's size.
+#[cfg(unix)]
+static FOLLOWERS: Mutex<Vec<RawFd>> = Mutex::new(Vec::new());
+/// Write end of the pipe the signal handler wakes the resize thread through.
+#[cfg(unix)]
+static WAKE_FD: AtomicI32 = AtomicI32::new(-1);
+/// The SIGWINCH action in place before ours, called from our handler.
+#[cfg(unix)]
+static PREVIOUS_ACTION: OnceLock<libc::sigaction> = OnceLock::new();
+/// Outcome of installing the handler and starting the thread, which happens once.
+#[cfg(unix)]
+static STARTED: OnceLock<Result<(), String>> = OnceLock::new();
+
+/// Size of the terminal the host runs in, taken from the first standard stream that is one.
+#[cfg(unix)]
+fn host_size() -> Option<libc::winsize> {
+    (0..=2).find_map(|fd| unsafe {
+        let mut size: libc::winsize = std::mem::zeroed();
+        (libc::ioctl(fd, libc::TIOCGWINSZ, &mut size) == 0).then_some(size)
+    })
+}
+
+#[cfg(unix)]
+fn apply_size(fd: RawFd, size: &libc::winsize) {
+    unsafe { libc::ioctl(fd, libc::TIOCSWINSZ, size) };
+}
+
+#[cfg(unix)]
+pub(crate) type SigInfoHandler =
+    extern "C" fn(libc::c_int, *mut libc::siginfo_t, *mut libc::c_void);
+
+#[cfg(unix)]
+extern "C" fn on_winch(signo: libc::c_int, info: *mut libc::siginfo_t, context: *mut libc::c_void) {
+    unsafe {
+        let saved_errno = io::Error::last_os_error().raw_os_error().unwrap_or(0);
+        let byte = 0u8;
+        libc::write(WAKE_FD.load(Ordering::Relaxed), (&raw const byte).cast(), 1);
+        set_errno(saved_errno);
+
+        if let Some(previous) = PREVIOUS_ACTION.get() {
+            let handler = previous.sa_sigaction;
+            if handler == libc::SIG_DFL || handler == libc::SIG_IGN {
+                return;
+            }
+            if previous.sa_flags & libc::SA_SIGINFO != 0 {
+                let handler: SigInfoHandler = std::mem::transmute(handler);
+                handler(signo, info, context);
+            } else {
+                let handler: extern "C" fn(libc::c_int) = std::mem::transmute(handler);
+                handler(signo);
+            }
+        }
+    }
+}
+
+#[cfg(unix)]
+unsafe fn set_errno(value: libc::c_int) {
+    unsafe {
+        #[cfg(any(target_os = "linux", target_os = "android"))]
+        {
+            *libc::__errno_location() = value;
+        }
+        #[cfg(any(target_os = "macos", target_os = "ios", target_os = "freebsd"))]
+        {
+            *libc::__error() = value;
+        }
+    }
+}
+
+/// Installs the SIGWINCH handler and starts the resize thread.
+#[cfg(unix)]
+fn start() -> io::Result<()> {
+    unsafe {
+        let mut fds = [0; 2];
+        if libc::pipe(fds.as_mut_ptr()) == -1 {
+            return Err(io::Error::last_os_error());
+        }
+        let [read_fd, write_fd] = fds;
+        for fd in fds {
+            libc::fcntl(fd, libc::F_SETFD, libc::FD_CLOEXEC);
+        }
+        // A burst of resizes only needs one wake-up; the handler must never block.
+        libc::fcntl(write_fd, libc::F_SETFL, libc::O_NONBLOCK);
+        WAKE_FD.store(write_fd, Ordering::Relaxed);
+
+        std::thread::Builder::new()
+            .name("pty-follow-host-resize".into())
+            .spawn(move || {
+                let mut buf = [0u8; 64];
+                loop {
+                    let n = libc::read(read_fd, buf.as_mut_ptr().cast(), buf.len());
+                    if n <= 0 && io::Error::last_os_error().kind() != io::ErrorKind::Interrupted {
+                        return;
+                    }
+                    if let Some(size) = host_size() {
+                        for &fd in FOLLOWERS.lock().unwrap().iter() {
+                            apply_size(fd, &size);
+                        }
+                    }
+                }
+            })?;
+
+        let mut action: libc::sigaction = std::mem::zeroed();
+        action.sa_sigaction = on_winch as SigInfoHandler as usize;
+        action.sa_flags = libc::SA_SIGINFO | libc::SA_RESTART;
+        libc::sigemptyset(&mut action.sa_mask);
+        let mut previous: libc::sigaction = std::mem::zeroed();
+        if libc::sigaction(libc::SIGWINCH, &action, &mut previous) == -1 {
+            return Err(io::Error::last_os_error());
+        }
+        let _ = PREVIOUS_ACTION.set(previous);
+    }
+    Ok(())
+}
+
+/// Starts following the host's size with master `fd`, resizing it right away.
+#[cfg(unix)]
+fn follow(fd: RawFd) -> PtyResult<()> {
+    let size = host_size().ok_or("The host is not running in a terminal")?;
+    STARTED
+        .get_or_init(|| start().map_err(|e| e.to_string()))
+        .clone()?;
+    let mut followers = FOLLOWERS.lock().unwrap();
+    if !followers.contains(&fd) {
+        followers.push(fd);
+    }
+    apply_size(fd, &size);
+    Ok(())
+}
+
+/// Stops resizing master `fd`. Called when a master is dropped, before its fd is closed.
+#[cfg(unix)]
+pub(crate) fn unfollow(fd: RawFd) {
+    FOLLOWERS.lock().unwrap().retain(|&other| other != fd);
+}
+
+/// Make the PTY follow the host terminal's size (Unix only).
+/// Returns 0 on success, -1 on error, -2 on other platforms; sets out_err_msg (caller frees via pty_free_err_msg).
+///
+/// While enabled, whenever the host process receives SIGWINCH the size of the terminal on
+/// its stdin, stdout or stderr is applied to the PTY, so a child mirroring the host terminal
+/// resizes along with it. Enabling also applies the current size right away, and fails if
+/// the host is not running in a terminal. Pass `enabled = false` to stop following; freeing
+/// the master stops it as well. A SIGWINCH handler the host installed before is preserved.
+///
+/// # Safety
+///
+/// Caller must ensure:
+/// - `master` is a valid, non-null handle obtained from `pty_open_and_spawn`.
+/// - `out_err_msg` is a valid, non-null pointer to mutable memory.
+/// - The caller must free any error message using `pty_free_err_msg`.
+/// - No concurrent mutation or invalidation of the master handle.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn pty_follow_host_resize(
+    master: MasterHandle,
+    enabled: bool,
+    out_err_msg: *mut *mut libc::c_char,
+) -> i32 {
+    ffi_call("pty_follow_host_resize", out_err_msg, || unsafe {
+        let master_struct = handle_mut(master)?;
+        #[cfg(unix)]
+        {
+            let fd = master_struct
+                .inner
+                .as_raw_fd()
+                .ok_or("Master PTY has no file descriptor")?;
+            if enabled {
+                follow(fd)?;
+            } else {
+                unfollow(fd);
+            }
+            Ok(0)
+        }
+        #[cfg(not(unix))]
+        {
+            let _ = (master_struct, enabled);
+            Err(PtyError::unsupported("pty_follow_host_resize"))
+        }
+    })
+}
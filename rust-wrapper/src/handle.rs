@@ -0,0 +1,111 @@
+//! Allocation and validation of FFI handles.
+//!
+//! Every handle is a pointer to a `Tagged<T>`: the value plus a tag naming its type. With
+//! handle validation enabled (debug builds, or the `handle-validation` feature) each call
+//! checks the tag and each free wipes it, so passing a freed or wrong-typed handle fails
+//! with an "Invalid handle" error instead of crashing somewhere inside the library.
+//!
+//! This is a best-effort guard for catching binding bugs, not a guarantee: using a freed
+//! handle is still undefined behaviour, and once the allocator reuses the memory the check
+//! can be fooled. Release builds without the feature skip the tag entirely.
+
+use crate::error::{PtyError, PtyResult};
+
+/// Implemented by every struct handed out as an opaque handle.
+pub(crate) trait HandleType {
+    /// Distinct per type, so a handle passed to the wrong function is caught.
+    const ID: u32;
+    /// Type name used in error messages.
+    const NAME: &'static str;
+}
+
+#[repr(C)]
+pub(crate) struct Tagged<T> {
+    // Must stay the first field: the check reads it before trusting the handle's type.
+    tag: Tag,
+    value: T,
+}
+
+#[cfg(any(debug_assertions, feature = "handle-validation"))]
+#[derive(Clone, Copy, PartialEq, Eq)]
+struct Tag(u64);
+
+#[cfg(any(debug_assertions, feature = "handle-validation"))]
+impl Tag {
+    const MAGIC: u64 = 0x5054_5948 << 32; // "PTYH"
+
+    fn of<T: HandleType>() -> Self {
+        Tag(Self::MAGIC | u64::from(T::ID))
+    }
+
+    unsafe fn check<T: HandleType>(ptr: *mut Tagged<T>) -> bool {
+        unsafe { (&raw const (*ptr).tag).read_volatile() == Self::of::<T>() }
+    }
+
+    unsafe fn clear<T>(ptr: *mut Tagged<T>) {
+        unsafe { (&raw mut (*ptr).tag).write_volatile(Tag(0)) }
+    }
+}
+
+#[cfg(not(any(debug_assertions, feature = "handle-validation")))]
+#[derive(Clone, Copy)]
+struct Tag;
+
+#[cfg(not(any(debug_assertions, feature = "handle-validation")))]
+impl Tag {
+    fn of<T: HandleType>() -> Self {
+        Tag
+    }
+
+    unsafe fn check<T: HandleType>(_ptr: *mut Tagged<T>) -> bool {
+        true
+    }
+
+    unsafe fn clear<T>(_ptr: *mut Tagged<T>) {}
+}
+
+/// Moves `value` to the heap and returns it as an opaque handle.
+pub(crate) fn into_handle<T: HandleType>(value: T) -> *mut Tagged<T> {
+    Box::into_raw(Box::new(Tagged {
+        tag: Tag::of::<T>(),
+        value,
+    }))
+}
+
+unsafe fn check<T: HandleType>(ptr: *mut Tagged<T>) -> PtyResult<()> {
+    if ptr.is_null() {
+        return Err(PtyError::new("Null pointer provided"));
+    }
+    if !unsafe { Tag::check(ptr) } {
+        return Err(PtyError::new(format!(
+            "Invalid handle: expected a live {} (freed, or a different handle type?)",
+            T::NAME
+        )));
+    }
+    Ok(())
+}
+
+/// Borrows the value behind a handle.
+pub(crate) unsafe fn handle_mut<'a, T: HandleType>(ptr: *mut Tagged<T>) -> PtyResult<&'a mut T> {
+    unsafe {
+        check(ptr)?;
+        Ok(&mut (*ptr).value)
+    }
+}
+
+/// Takes the value out of a handle, invalidating the handle.
+pub(crate) unsafe fn take_handle<T: HandleType>(ptr: *mut Tagged<T>) -> PtyResult<T> {
+    unsafe {
+        check(ptr)?;
+        Tag::clear(ptr);
+        Ok(Box::from_raw(ptr).value)
+    }
+}
+
+/// Frees a handle. Null is ignored, and so is a handle that fails validation, so that a
+/// double free is caught rather than corrupting the heap.
+pub(crate) unsafe fn free_handle<T: HandleType>(ptr: *mut Tagged<T>) {
+    if let Ok(value) = unsafe { take_handle(ptr) } {
+        drop(value);
+    }
+}
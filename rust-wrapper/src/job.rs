@@ -0,0 +1,94 @@
+//! Job control: which process group owns the PTY's terminal.
+
+use crate::MasterHandle;
+use crate::error::{PtyError, ffi_call};
+use crate::handle::handle_mut;
+use crate::out_ref;
+
+/// Get the process group currently in the foreground of the PTY (Unix only).
+/// Returns 0 on success, -1 on error, -2 on other platforms; sets out_err_msg (caller frees via pty_free_err_msg).
+///
+/// `out_pgid` receives the group `tcgetpgrp` reports for the terminal: the one that gets
+/// SIGINT on Ctrl-C and SIGTSTP on Ctrl-Z. A job-control shell moves its jobs in and out of
+/// the foreground, so the value changes as the user runs commands. It is 0 while no group
+/// is in the foreground, such as after the session leader exited.
+///
+/// # Safety
+///
+/// Caller must ensure:
+/// - `master` is a valid, non-null handle obtained from `pty_open_and_spawn` or `pty_open`.
+/// - `out_pgid` and `out_err_msg` are valid, non-null pointers to mutable memory.
+/// - The caller must free any error message using `pty_free_err_msg`.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn pty_foreground_pgrp(
+    master: MasterHandle,
+    out_pgid: *mut i32,
+    out_err_msg: *mut *mut libc::c_char,
+) -> i32 {
+    ffi_call("pty_foreground_pgrp", out_err_msg, || unsafe {
+        let master_struct = handle_mut(master)?;
+        let out_pgid = out_ref(out_pgid)?;
+        #[cfg(unix)]
+        {
+            let pgid = libc::tcgetpgrp(master_struct.fd()?);
+            if pgid == -1 {
+                return Err(std::io::Error::last_os_error().into());
+            }
+            *out_pgid = pgid;
+            Ok(0)
+        }
+        #[cfg(not(unix))]
+        {
+            let _ = (master_struct, out_pgid);
+            Err(PtyError::unsupported("pty_foreground_pgrp"))
+        }
+    })
+}
+
+/// Move process group `pgid` to the foreground of the PTY (Unix only).
+/// Returns 0 on success, -1 on error, -2 on other platforms; sets out_err_msg (caller frees via pty_free_err_msg).
+///
+/// Calls `tcsetpgrp`, so the kernel's job-control rules apply: the calling process must
+/// have the PTY as its controlling terminal, and `pgid` must belong to the same session.
+/// Children spawned here lead their own session, so the process that loaded this library
+/// normally gets an error; the call is meant for code running in that session, such as a
+/// shell built on this library that runs inside the PTY. A caller in a background group
+/// should block or ignore SIGTTOU first, or the call stops it.
+///
+/// # Safety
+///
+/// Caller must ensure:
+/// - `master` is a valid, non-null handle obtained from `pty_open_and_spawn` or `pty_open`.
+/// - `out_err_msg` is a valid, non-null pointer to mutable memory.
+/// - The caller must free any error message using `pty_free_err_msg`.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn pty_set_foreground_pgrp(
+    master: MasterHandle,
+    pgid: i32,
+    out_err_msg: *mut *mut libc::c_char,
+) -> i32 {
+    ffi_call("pty_set_foreground_pgrp", out_err_msg, || unsafe {
+        let master_struct = handle_mut(master)?;
+        #[cfg(unix)]
+        {
+            if libc::tcsetpgrp(master_struct.fd()?, pgid) == -1 {
+                let err = std::io::Error::last_os_error();
+                return Err(match err.raw_os_error() {
+                    Some(libc::ENOTTY) => {
+                        PtyError::new("The PTY is not the calling process's controlling terminal")
+                    }
+                    Some(libc::EPERM) => {
+                        PtyError::new(format!("Process group {pgid} is not in the PTY's session"))
+                    }
+                    _ => err.into(),
+                });
+            }
+            Ok(0)
+        }
+        #[cfg(not(unix))]
+        {
+            let _ = (master_struct, pgid);
+            Err(PtyError::unsupported("pty_set_foreground_pgrp"))
+        }
+    })
+}
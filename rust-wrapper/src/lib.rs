@@ -4,38 +4,406 @@
 use portable_pty::{native_pty_system, CommandBuilder, MasterPty, PtySize, SlavePty};
 use std::ffi::CStr;
 use std::ffi::CString;
-use std::io::{Read, Write};
-use std::panic::{catch_unwind, AssertUnwindSafe};
+use std::io::Write;
+
+mod ansi;
+mod argmax;
+mod backend;
+mod builder;
+mod channel;
+mod error;
+mod expect;
+mod fd;
+mod follow;
+mod handle;
+mod job;
+#[cfg(target_os = "linux")]
+mod namespace;
+mod proc;
+mod queue;
+mod reader;
+mod resize;
+mod resolve;
+mod run;
+mod script;
+mod session;
+mod shutdown;
+#[cfg(unix)]
+mod spawn;
+mod termios;
+mod vt;
+
+use builder::Builder;
+use channel::Channel;
+use error::{ffi_call, PtyError, PtyResult};
+use handle::{free_handle, handle_mut, into_handle, HandleType, Tagged};
+use reader::Reader;
+use session::Session;
+use std::sync::Arc;
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::time::{Duration, Instant};
+use vt::Vt;
 
 // Opaque structs for FFI
 struct Master {
     inner: Box<dyn MasterPty + Send>,
+    session: Arc<Session>,
+    /// Writer already taken from `inner` (by `pty_open_spawn_write`), handed out by `pty_get_writer`.
+    writer: Option<Box<dyn Write + Send>>,
+    /// Set by `pty_on_resize`.
+    resize_watch: Option<resize::ResizeWatch>,
+    /// Name given with `pty_master_set_label`; empty by default.
+    label: String,
+    /// Pump copying output to the builder's output log; readers share it.
+    log_pump: Option<Arc<reader::Pump>>,
+    /// Set by `pty_open_ex`: readers drain the PTY into a large buffer in the background.
+    large_buffers: bool,
+    /// Read end of the child's stderr pipe, if the builder split stderr off the PTY.
+    #[cfg(unix)]
+    stderr: Option<std::os::fd::OwnedFd>,
+    /// The pseudoconsole's slave side, kept by spawns so `pty_set_title` can run a helper
+    /// attached to the console.
+    #[cfg(windows)]
+    console: Option<Box<dyn SlavePty + Send>>,
 }
 struct Slave {
     inner: Box<dyn SlavePty + Send>,
+    /// Device number of the slave, if it could be determined.
+    tty_dev: Option<u64>,
 }
 struct Child {
     inner: Box<dyn portable_pty::Child + Send + Sync>,
-}
-struct Reader {
-    inner: Box<dyn Read + Send>,
+    /// Set by `pty_child_detach`: reap the child in the background once the handle is freed.
+    detached: bool,
+    /// Exit status, once a wait has observed it.
+    status: Option<portable_pty::ExitStatus>,
+    /// Signal that stopped the child, while `pty_child_status_ex` last saw it stopped.
+    stop_signal: Option<i32>,
+    /// Device number of the PTY the child was spawned on, if known.
+    tty_dev: Option<u64>,
+    /// Id of the child in the registry `pty_shutdown_all` works through.
+    shutdown_id: u64,
 }
 struct Writer {
     inner: Box<dyn Write + Send>,
+    /// Duplicate of the master fd, used to check whether a write would block.
+    #[cfg(unix)]
+    poll_fd: Option<std::os::fd::OwnedFd>,
+}
+
+impl Writer {
+    /// Writes `buf` as given, shared by `pty_write` and its alias `pty_write_raw`.
+    fn write(&mut self, buf: &[u8]) -> PtyResult<isize> {
+        Ok(self.inner.write(buf).map_err(write_error)? as isize)
+    }
+}
+
+/// Device number of the slave side of `master`, for telling which terminal a process has.
+fn slave_device(master: &dyn MasterPty) -> Option<u64> {
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::MetadataExt;
+        let path = master.tty_name()?;
+        std::fs::metadata(path).ok().map(|metadata| metadata.rdev())
+    }
+    #[cfg(not(unix))]
+    {
+        let _ = master;
+        None
+    }
+}
+
+impl Master {
+    fn new(inner: Box<dyn MasterPty + Send>) -> Self {
+        let session = Session::new();
+        session.register();
+        Master {
+            inner,
+            session,
+            writer: None,
+            resize_watch: None,
+            label: String::new(),
+            log_pump: None,
+            large_buffers: false,
+            #[cfg(unix)]
+            stderr: None,
+            #[cfg(windows)]
+            console: None,
+        }
+    }
+
+    /// The master's file descriptor.
+    #[cfg(unix)]
+    fn fd(&self) -> PtyResult<libc::c_int> {
+        self.inner
+            .as_raw_fd()
+            .ok_or_else(|| PtyError::new("The master has no file descriptor"))
+    }
+}
+
+impl Drop for Master {
+    fn drop(&mut self) {
+        self.session.unregister();
+        #[cfg(unix)]
+        if let Some(fd) = self.inner.as_raw_fd() {
+            follow::unfollow(fd);
+        }
+    }
+}
+
+impl Child {
+    fn new(inner: Box<dyn portable_pty::Child + Send + Sync>, tty_dev: Option<u64>) -> Self {
+        let shutdown_id = shutdown::register(&*inner);
+        Child {
+            inner,
+            detached: false,
+            status: None,
+            stop_signal: None,
+            tty_dev,
+            shutdown_id,
+        }
+    }
+
+    /// Blocks until the child exits. A signal interrupting the wait (EINTR) does not end
+    /// it early; the wait is simply resumed. Once the child has exited, the status is
+    /// remembered and returned by every later wait.
+    fn wait(&mut self) -> std::io::Result<portable_pty::ExitStatus> {
+        if let Some(status) = &self.status {
+            return Ok(status.clone());
+        }
+        let status = loop {
+            match self.inner.wait() {
+                Err(e) if e.kind() == std::io::ErrorKind::Interrupted => continue,
+                result => break result?,
+            }
+        };
+        self.status = Some(status.clone());
+        Ok(status)
+    }
+
+    /// The exit status if the child has exited, without blocking.
+    fn try_wait(&mut self) -> std::io::Result<Option<portable_pty::ExitStatus>> {
+        if self.status.is_none() {
+            self.status = self.inner.try_wait()?;
+        }
+        Ok(self.status.clone())
+    }
+
+    /// OS process id of the child.
+    fn pid(&self) -> PtyResult<u32> {
+        self.inner
+            .process_id()
+            .ok_or_else(|| "Child process id is unavailable".into())
+    }
+}
+
+impl Drop for Child {
+    fn drop(&mut self) {
+        shutdown::unregister(self.shutdown_id);
+        // Collect the exit status whenever the child exits so it does not linger as a zombie.
+        // Windows has no zombies; closing the process handle is enough.
+        #[cfg(unix)]
+        if self.detached
+            && self.try_wait().ok().flatten().is_none()
+            && let Some(pid) = self.inner.process_id()
+        {
+            std::thread::spawn(move || {
+                let mut status = 0;
+                while unsafe { libc::waitpid(pid as libc::pid_t, &mut status, 0) } == -1
+                    && std::io::Error::last_os_error().kind() == std::io::ErrorKind::Interrupted
+                {}
+            });
+        }
+    }
+}
+
+impl HandleType for Master {
+    const ID: u32 = 1;
+    const NAME: &'static str = "MasterHandle";
+}
+impl HandleType for Slave {
+    const ID: u32 = 2;
+    const NAME: &'static str = "SlaveHandle";
+}
+impl HandleType for Child {
+    const ID: u32 = 3;
+    const NAME: &'static str = "ChildHandle";
+}
+impl HandleType for Reader {
+    const ID: u32 = 4;
+    const NAME: &'static str = "ReaderHandle";
+}
+impl HandleType for Writer {
+    const ID: u32 = 5;
+    const NAME: &'static str = "WriterHandle";
+}
+impl HandleType for Builder {
+    const ID: u32 = 6;
+    const NAME: &'static str = "BuilderHandle";
+}
+impl HandleType for Channel {
+    const ID: u32 = 7;
+    const NAME: &'static str = "ChannelHandle";
+}
+impl HandleType for Vt {
+    const ID: u32 = 8;
+    const NAME: &'static str = "VtHandle";
 }
 
 // Opaque handles for FFI
-type MasterHandle = *mut Master;
-type SlaveHandle = *mut Slave;
-type ChildHandle = *mut Child;
-type ReaderHandle = *mut Reader;
-type WriterHandle = *mut Writer;
+type MasterHandle = *mut Tagged<Master>;
+type SlaveHandle = *mut Tagged<Slave>;
+type ChildHandle = *mut Tagged<Child>;
+type ReaderHandle = *mut Tagged<Reader>;
+type WriterHandle = *mut Tagged<Writer>;
+type BuilderHandle = *mut Tagged<Builder>;
+type ChannelHandle = *mut Tagged<Channel>;
+type VtHandle = *mut Tagged<Vt>;
+
+/// Stores `msg` in `out_err_msg` (if non-null) as a C string the caller frees via `pty_free_err_msg`.
+unsafe fn set_err_msg(out_err_msg: *mut *mut libc::c_char, msg: &str) {
+    if out_err_msg.is_null() {
+        return;
+    }
+    let err_str = CString::new(msg).unwrap_or_else(|_| CString::new("Unknown error").unwrap());
+    unsafe {
+        *out_err_msg = err_str.into_raw();
+    }
+}
+
+/// Stores `value` in `out` as a C string the caller frees via `pty_free_string`, or null for `None`.
+unsafe fn set_string_out(out: &mut *mut libc::c_char, value: Option<&str>) -> PtyResult<()> {
+    *out = match value {
+        Some(value) => CString::new(value)
+            .map_err(|_| PtyError::new("String contains a NUL byte"))?
+            .into_raw(),
+        None => std::ptr::null_mut(),
+    };
+    Ok(())
+}
+
+/// Copies `value` into a caller-provided buffer of `buf_len` bytes with a terminating NUL
+/// and stores its length (without the NUL) in `out_len`. The length is stored even when
+/// the buffer is too small, so the caller can retry with `out_len + 1` bytes.
+unsafe fn copy_str_out(
+    value: &str,
+    buf: *mut u8,
+    buf_len: usize,
+    out_len: *mut usize,
+) -> PtyResult<()> {
+    let out_len = unsafe { out_ref(out_len) }?;
+    *out_len = value.len();
+    if buf_len <= value.len() {
+        return Err(PtyError::new(format!(
+            "Buffer too small: {} bytes needed",
+            value.len() + 1
+        )));
+    }
+    let buf = unsafe { buf_mut(buf, buf_len) }?;
+    buf[..value.len()].copy_from_slice(value.as_bytes());
+    buf[value.len()] = 0;
+    Ok(())
+}
+
+/// Turns a caller-provided out-pointer into a reference, rejecting null.
+unsafe fn out_ref<'a, T>(ptr: *mut T) -> PtyResult<&'a mut T> {
+    unsafe { ptr.as_mut() }.ok_or_else(|| PtyError::new("Null pointer provided"))
+}
+
+/// Borrows a caller-provided buffer of `len` bytes for writing, rejecting null.
+unsafe fn buf_mut<'a>(ptr: *mut u8, len: usize) -> PtyResult<&'a mut [u8]> {
+    if ptr.is_null() {
+        return Err("Null pointer provided".into());
+    }
+    Ok(unsafe { std::slice::from_raw_parts_mut(ptr, len) })
+}
+
+/// Borrows a caller-provided buffer of `len` bytes for reading, rejecting null.
+unsafe fn buf_ref<'a>(ptr: *const u8, len: usize) -> PtyResult<&'a [u8]> {
+    if ptr.is_null() {
+        return Err("Null pointer provided".into());
+    }
+    Ok(unsafe { std::slice::from_raw_parts(ptr, len) })
+}
+
+/// Reads a caller-provided C string, rejecting null.
+unsafe fn str_from_ptr(ptr: *const libc::c_char) -> PtyResult<String> {
+    if ptr.is_null() {
+        return Err("Null pointer provided".into());
+    }
+    Ok(unsafe { CStr::from_ptr(ptr) }.to_string_lossy().into_owned())
+}
+
+/// Collects an `argc`-long C argv array into strings. Null entries are skipped.
+unsafe fn strings_from_argv(argv: *const *const libc::c_char, argc: usize) -> Vec<String> {
+    if argv.is_null() || argc == 0 {
+        return Vec::new();
+    }
+    let args_slice = unsafe { std::slice::from_raw_parts(argv, argc) };
+    args_slice
+        .iter()
+        .filter(|arg_ptr| !arg_ptr.is_null())
+        .map(|&arg_ptr| unsafe { CStr::from_ptr(arg_ptr) }.to_string_lossy().into_owned())
+        .collect()
+}
+
+/// Builds a `CommandBuilder` from a C program name and an `argc`-long argv array.
+unsafe fn command_from_argv(
+    prog: *const libc::c_char,
+    argv: *const *const libc::c_char,
+    argc: usize,
+) -> PtyResult<CommandBuilder> {
+    let mut builder = CommandBuilder::new(unsafe { str_from_ptr(prog) }?);
+    builder.args(unsafe { strings_from_argv(argv, argc) });
+    Ok(builder)
+}
+
+/// Opens a PTY of the given size and spawns `cmd` on it.
+fn spawn_pty(size: PtySize, cmd: CommandBuilder) -> PtyResult<(Master, Child)> {
+    argmax::check(&cmd)?;
+    let pair = native_pty_system().openpty(size)?;
+    // On failure, dropping the pair frees master and slave.
+    let child = pair.slave.spawn_command(cmd)?;
+    let tty_dev = slave_device(&*pair.master);
+    let master = Master::new(pair.master);
+    // Always drop the pair.slave handle in the parent process after spawning, otherwise, the pipe may not close properly.
+    #[cfg(unix)]
+    drop(pair.slave);
+    // A ConPTY slave only shares the pseudoconsole with the master, so keeping it holds
+    // nothing open longer than the master does.
+    #[cfg(windows)]
+    let master = {
+        let mut master = master;
+        master.console = Some(pair.slave);
+        master
+    };
+    Ok((master, Child::new(child, tty_dev)))
+}
+
+/// Maps a failed write to the child-gone error when it means the other side of the PTY is
+/// closed: EIO or EPIPE on Unix, a broken pipe on Windows.
+fn write_error(e: std::io::Error) -> PtyError {
+    #[cfg(unix)]
+    if e.raw_os_error() == Some(libc::EIO) {
+        return PtyError::child_gone();
+    }
+    if e.kind() == std::io::ErrorKind::BrokenPipe {
+        return PtyError::child_gone();
+    }
+    e.into()
+}
+
+/// Exit code of a finished child as reported to callers. Every wait entry point goes
+/// through this, so they all report the same code for the same child.
+fn exit_code(status: &portable_pty::ExitStatus) -> i32 {
+    status.exit_code() as i32
+}
 
 /// Combined function: opens PTY, spawns command, returns master and child.
 /// Returns 0 on success, -1 on error; sets out_err_msg (caller frees via pty_free_err_msg).
 ///
 /// # Safety
-/// 
+///
 /// Caller must ensure:
 /// - `prog` is a valid, non-null pointer to a null-terminated C string.
 /// - If `argc` > 0, `argv` is a valid, non-null pointer to an array of `argc` pointers, each pointing to a null-terminated C string or null.
@@ -54,259 +422,945 @@ pub unsafe extern "C" fn pty_open_and_spawn(
     child_out: *mut ChildHandle,
     out_err_msg: *mut *mut libc::c_char,
 ) -> i32 {
-    if master_out.is_null() || child_out.is_null() || prog.is_null() {
-        let err_str = CString::new("Null pointer provided").unwrap();
-        unsafe { *out_err_msg = err_str.into_raw(); }
-        return -1;
-    }
+    ffi_call("pty_open_and_spawn", out_err_msg, || unsafe {
+        let master_out = out_ref(master_out)?;
+        let child_out = out_ref(child_out)?;
+        let size = PtySize {
+            rows,
+            cols,
+            pixel_width: 0,
+            pixel_height: 0,
+        };
+        let (master, child) = spawn_pty(size, command_from_argv(prog, argv, argc)?)?;
+        *master_out = into_handle(master);
+        *child_out = into_handle(child);
+        Ok(0)
+    })
+}
+
+/// Like `pty_open_and_spawn`, but the child may have at most `max_fds` file descriptors open (Unix only).
+/// Returns 0 on success, -1 on error, -2 on other platforms; sets out_err_msg (caller frees via pty_free_err_msg).
+///
+/// A testing aid for catching descriptor leaks: opening a descriptor numbered `max_fds`
+/// or above fails with EMFILE, so a leaking program fails visibly instead of slowly
+/// exhausting the system limit. See `pty_builder_max_fds`, which this is shorthand for,
+/// for how low the limit can go.
+///
+/// # Safety
+///
+/// Caller must ensure:
+/// - `prog` is a valid, non-null pointer to a null-terminated C string.
+/// - If `argc` > 0, `argv` is a valid, non-null pointer to an array of `argc` pointers, each pointing to a null-terminated C string or null.
+/// - `master_out`, `child_out`, and `out_err_msg` are valid, non-null pointers to mutable memory.
+/// - The caller must free any error message returned in `out_err_msg` using `pty_free_err_msg`.
+/// - Handles returned in `master_out` and `child_out` must be freed using `pty_free_master` and `pty_free_child` respectively.
+#[unsafe(no_mangle)]
+#[allow(clippy::too_many_arguments)]
+pub unsafe extern "C" fn pty_open_and_spawn_fd_limited(
+    rows: u16,
+    cols: u16,
+    prog: *const libc::c_char,
+    argv: *const *const libc::c_char,
+    argc: usize,
+    max_fds: u64,
+    master_out: *mut MasterHandle,
+    child_out: *mut ChildHandle,
+    out_err_msg: *mut *mut libc::c_char,
+) -> i32 {
+    ffi_call("pty_open_and_spawn_fd_limited", out_err_msg, || unsafe {
+        let master_out = out_ref(master_out)?;
+        let child_out = out_ref(child_out)?;
+        #[cfg(unix)]
+        {
+            let size = PtySize {
+                rows,
+                cols,
+                pixel_width: 0,
+                pixel_height: 0,
+            };
+            let builder = Builder {
+                max_fds: Some(max_fds),
+                ..Builder::new(command_from_argv(prog, argv, argc)?, size)
+            };
+            let (master, child) = builder.spawn()?;
+            *master_out = into_handle(master);
+            *child_out = into_handle(child);
+            Ok(0)
+        }
+        #[cfg(not(unix))]
+        {
+            let _ = (rows, cols, prog, argv, argc, max_fds, master_out, child_out);
+            Err(PtyError::unsupported("pty_open_and_spawn_fd_limited"))
+        }
+    })
+}
+
+/// Like `pty_open_and_spawn`, but with the executable and the name it sees given separately (Unix only).
+/// Returns 0 on success, -3 if `prog_path` does not exist, -1 on other errors, -2 on other platforms; sets out_err_msg (caller frees via pty_free_err_msg).
+///
+/// `prog_path` is the file executed and `arg0` becomes the program's `argv[0]`, followed
+/// by `argv`. Multi-call binaries such as busybox or toybox pick the applet from `arg0`,
+/// and a shell started with an `arg0` beginning with `-` (e.g. `-bash`) runs as a login
+/// shell. `prog_path` is resolved and checked before anything is started, like
+/// `pty_resolve_executable`: a path with a `/` is taken relative to this process's working
+/// directory, a bare name is searched in `PATH`. A missing program fails with -3, one that
+/// exists but cannot be executed with -1, as do failures of the spawn itself. Symlinks
+/// are followed by exec as usual, so the program sees `arg0`, not the link's name.
+///
+/// # Safety
+///
+/// Caller must ensure:
+/// - `prog_path` and `arg0` are valid, non-null pointers to null-terminated C strings.
+/// - If `argc` > 0, `argv` is a valid, non-null pointer to an array of `argc` pointers, each pointing to a null-terminated C string or null.
+/// - `master_out`, `child_out`, and `out_err_msg` are valid, non-null pointers to mutable memory.
+/// - The caller must free any error message returned in `out_err_msg` using `pty_free_err_msg`.
+/// - Handles returned in `master_out` and `child_out` must be freed using `pty_free_master` and `pty_free_child` respectively.
+#[unsafe(no_mangle)]
+#[allow(clippy::too_many_arguments)]
+pub unsafe extern "C" fn pty_open_and_spawn_full(
+    rows: u16,
+    cols: u16,
+    prog_path: *const libc::c_char,
+    arg0: *const libc::c_char,
+    argv: *const *const libc::c_char,
+    argc: usize,
+    master_out: *mut MasterHandle,
+    child_out: *mut ChildHandle,
+    out_err_msg: *mut *mut libc::c_char,
+) -> i32 {
+    ffi_call("pty_open_and_spawn_full", out_err_msg, || unsafe {
+        let master_out = out_ref(master_out)?;
+        let child_out = out_ref(child_out)?;
+        let prog_path = str_from_ptr(prog_path)?;
+        let arg0 = str_from_ptr(arg0)?;
+        #[cfg(unix)]
+        {
+            let path = std::env::var_os("PATH");
+            let program = resolve::resolve_program(
+                std::ffi::OsStr::new(&prog_path),
+                &std::env::current_dir()?,
+                path.as_deref(),
+            )?;
+            let mut cmd = CommandBuilder::new(program);
+            cmd.args(strings_from_argv(argv, argc));
+            let size = PtySize {
+                rows,
+                cols,
+                pixel_width: 0,
+                pixel_height: 0,
+            };
+            let builder = Builder {
+                arg0: Some(arg0),
+                ..Builder::new(cmd, size)
+            };
+            let (master, child) = builder.spawn()?;
+            *master_out = into_handle(master);
+            *child_out = into_handle(child);
+            Ok(0)
+        }
+        #[cfg(not(unix))]
+        {
+            let _ = (
+                rows, cols, prog_path, arg0, argv, argc, master_out, child_out,
+            );
+            Err(PtyError::unsupported("pty_open_and_spawn_full"))
+        }
+    })
+}
+
+/// Like `pty_open_and_spawn`, but runs a shell script in the child before the program (Unix only).
+/// Returns 0 on success, -1 on error, -2 on other platforms; sets out_err_msg (caller frees via pty_free_err_msg).
+///
+/// For setup that has to happen in the child's own context, such as `ulimit`, `cd`,
+/// `umask` or `export`, without an option for each. The child is
+/// `/bin/sh -c <code> sh <prog> <argv...>`, where `<code>` is `script`, a newline and
+/// `exec "$@"`: the shell runs `script`, then replaces itself with the program, so the
+/// program keeps the shell's pid and inherits everything the script changed. A program without a `/` is looked up in
+/// the PATH as the script left it.
+///
+/// `script` is shell code, inserted verbatim; `prog` and `argv` are passed as separate
+/// arguments and need no quoting, but any value placed inside `script` must be quoted for
+/// `sh` by the caller. The program runs even if a command in the script fails; end the
+/// script with `|| exit 1` or start it with `set -e` to stop instead. A script that calls
+/// `exit` or `exec` itself never reaches the program. Requires `/bin/sh`.
+///
+/// # Safety
+///
+/// Caller must ensure:
+/// - `script` and `prog` are valid, non-null pointers to null-terminated C strings.
+/// - If `argc` > 0, `argv` is a valid, non-null pointer to an array of `argc` pointers, each pointing to a null-terminated C string or null.
+/// - `master_out`, `child_out`, and `out_err_msg` are valid, non-null pointers to mutable memory.
+/// - The caller must free any error message returned in `out_err_msg` using `pty_free_err_msg`.
+/// - Handles returned in `master_out` and `child_out` must be freed using `pty_free_master` and `pty_free_child` respectively.
+#[unsafe(no_mangle)]
+#[allow(clippy::too_many_arguments)]
+pub unsafe extern "C" fn pty_open_and_spawn_preexec(
+    rows: u16,
+    cols: u16,
+    script: *const libc::c_char,
+    prog: *const libc::c_char,
+    argv: *const *const libc::c_char,
+    argc: usize,
+    master_out: *mut MasterHandle,
+    child_out: *mut ChildHandle,
+    out_err_msg: *mut *mut libc::c_char,
+) -> i32 {
+    ffi_call("pty_open_and_spawn_preexec", out_err_msg, || unsafe {
+        let master_out = out_ref(master_out)?;
+        let child_out = out_ref(child_out)?;
+        let script = str_from_ptr(script)?;
+        let prog = str_from_ptr(prog)?;
+        #[cfg(unix)]
+        {
+            let mut cmd = CommandBuilder::new("/bin/sh");
+            // A newline rather than `;` so a script ending in a comment or `&` still works.
+            cmd.args(["-c", &format!("{script}\nexec \"$@\""), "sh", &prog]);
+            cmd.args(strings_from_argv(argv, argc));
+            let size = PtySize {
+                rows,
+                cols,
+                pixel_width: 0,
+                pixel_height: 0,
+            };
+            let (master, child) = spawn_pty(size, cmd)?;
+            *master_out = into_handle(master);
+            *child_out = into_handle(child);
+            Ok(0)
+        }
+        #[cfg(not(unix))]
+        {
+            let _ = (rows, cols, script, prog, argv, argc, master_out, child_out);
+            Err(PtyError::unsupported("pty_open_and_spawn_preexec"))
+        }
+    })
+}
 
-    let result = catch_unwind(AssertUnwindSafe(|| unsafe {
-        let pty_system = native_pty_system();
-        let pair = match pty_system.openpty(PtySize {
+/// Open a PTY without spawning, returning both the master and the slave.
+/// Returns 0 on success, -1 on error; sets out_err_msg (caller frees via pty_free_err_msg).
+///
+/// The slave stays open for as long as its handle lives, so terminal state (size, termios)
+/// persists across children spawned with `pty_slave_respawn`. Because the parent keeps the
+/// slave open, readers do not see EOF when a child exits; use the child handle to detect exit.
+///
+/// # Safety
+///
+/// Caller must ensure:
+/// - `master_out`, `slave_out`, and `out_err_msg` are valid, non-null pointers to mutable memory.
+/// - The caller must free any error message returned in `out_err_msg` using `pty_free_err_msg`.
+/// - Handles returned in `master_out` and `slave_out` must be freed using `pty_free_master` and `pty_free_slave` respectively.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn pty_open(
+    rows: u16,
+    cols: u16,
+    master_out: *mut MasterHandle,
+    slave_out: *mut SlaveHandle,
+    out_err_msg: *mut *mut libc::c_char,
+) -> i32 {
+    ffi_call("pty_open", out_err_msg, || unsafe {
+        let master_out = out_ref(master_out)?;
+        let slave_out = out_ref(slave_out)?;
+        let pair = native_pty_system().openpty(PtySize {
             rows,
             cols,
             pixel_width: 0,
             pixel_height: 0,
-        }) {
-            Ok(p) => p,
-            Err(e) => {
-                let err_str = CString::new(e.to_string())
-                    .unwrap_or_else(|_| CString::new("Failed to open PTY").unwrap());
-                *out_err_msg = err_str.into_raw();
-                return -1;
-            }
+        })?;
+        let slave = Slave {
+            tty_dev: slave_device(&*pair.master),
+            inner: pair.slave,
         };
+        *master_out = into_handle(Master::new(pair.master));
+        *slave_out = into_handle(slave);
+        Ok(0)
+    })
+}
 
-        let prog_cstr = CStr::from_ptr(prog);
-        let prog_str = prog_cstr.to_string_lossy().into_owned();
-        let mut builder = CommandBuilder::new(prog_str);
-
-        // Parse arguments
-        if !argv.is_null() && argc > 0 {
-            let args_slice: &[*const libc::c_char] =
-                std::slice::from_raw_parts(argv, argc);
-            for &arg_ptr in args_slice {
-                if arg_ptr.is_null() {
-                    continue;
+/// Open a PTY without spawning, like `pty_open`, optionally in non-blocking mode from the start or tuned for bulk data.
+/// Returns 0 on success, -1 on error, -2 if `nonblocking` is set on other platforms; sets out_err_msg (caller frees via pty_free_err_msg).
+///
+/// With `nonblocking`, O_NONBLOCK is set on the master right after it is opened, so there
+/// is no window in which it blocks, for event loops that wait for readiness themselves.
+/// `pty_read` and `pty_read_clearing` on its readers then return -7 instead of waiting
+/// when there is no output, and the caller reads again once its event loop reports
+/// output. A write that finds the PTY's input queue full can fail with
+/// -7 in the same way, possibly after part of the data went through.
+///
+/// The timed reads (`pty_read_bounded`, `pty_read_min`, `pty_read_until_prompt` and the
+/// like) are unaffected: they still wait for output up to their timeout and report
+/// running out of it as usual, never -7. So do the functions that read until something
+/// happens, such as `pty_read_loop` and channels. Output those have already buffered is
+/// returned by `pty_read` first, without -7.
+///
+/// `large_buffers` is for piping bulk data through a program, such as a compressor. The
+/// PTY's own buffers are fixed by the kernel and cannot be enlarged: Linux holds about
+/// 64 KiB of unread output and hands it over at most 4 KiB per read, and macOS, the BSDs
+/// and ConPTY have similar fixed limits, so a program writing faster than the caller
+/// reads keeps blocking. With the option, every reader taken from the master with
+/// `pty_get_reader` reads the PTY on a background thread from the start and buffers up
+/// to 4 MiB of output the caller has not read, instead of the usual 64 KiB, so the
+/// program runs on while the caller is busy. The buffer only fills as far as the caller
+/// falls behind. There are no auxiliary pipes whose size could be raised.
+///
+/// # Safety
+///
+/// Caller must ensure:
+/// - `master_out`, `slave_out`, and `out_err_msg` are valid, non-null pointers to mutable memory.
+/// - The caller must free any error message returned in `out_err_msg` using `pty_free_err_msg`.
+/// - Handles returned in `master_out` and `slave_out` must be freed using `pty_free_master` and `pty_free_slave` respectively.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn pty_open_ex(
+    rows: u16,
+    cols: u16,
+    nonblocking: bool,
+    large_buffers: bool,
+    master_out: *mut MasterHandle,
+    slave_out: *mut SlaveHandle,
+    out_err_msg: *mut *mut libc::c_char,
+) -> i32 {
+    ffi_call("pty_open_ex", out_err_msg, || unsafe {
+        let master_out = out_ref(master_out)?;
+        let slave_out = out_ref(slave_out)?;
+        let pair = native_pty_system().openpty(PtySize {
+            rows,
+            cols,
+            pixel_width: 0,
+            pixel_height: 0,
+        })?;
+        if nonblocking {
+            #[cfg(unix)]
+            {
+                let fd = pair
+                    .master
+                    .as_raw_fd()
+                    .ok_or("The master has no file descriptor")?;
+                let flags = libc::fcntl(fd, libc::F_GETFL);
+                if flags == -1 || libc::fcntl(fd, libc::F_SETFL, flags | libc::O_NONBLOCK) == -1 {
+                    return Err(std::io::Error::last_os_error().into());
                 }
-                let arg_cstr = CStr::from_ptr(arg_ptr);
-                let arg_str = arg_cstr.to_string_lossy().into_owned();
-                builder.arg(arg_str);
             }
+            #[cfg(not(unix))]
+            return Err(PtyError::unsupported("Non-blocking mode in pty_open_ex"));
+        }
+        let slave = Slave {
+            tty_dev: slave_device(&*pair.master),
+            inner: pair.slave,
+        };
+        let mut master = Master::new(pair.master);
+        master.large_buffers = large_buffers;
+        *master_out = into_handle(master);
+        *slave_out = into_handle(slave);
+        Ok(0)
+    })
+}
+
+/// Open a PTY without spawning and return the path of its slave device (Unix only).
+/// Returns 0 on success, -1 on error, -2 on other platforms; sets out_err_msg (caller frees via pty_free_err_msg).
+///
+/// For handing the PTY to a process started elsewhere, which connects by opening the
+/// path (e.g. `/dev/pts/3`). The path is copied into `out_path_buf` with a terminating NUL;
+/// a buffer of 64 bytes is plenty on the usual systems. The library keeps no slave open,
+/// so reading the master reports end of output until the other process opens the path;
+/// start reading once it has. Opening the path does not make the PTY that process's
+/// controlling terminal; it has to call `setsid` and `ioctl(TIOCSCTTY)` itself for Ctrl-C
+/// and job control to reach it.
+///
+/// # Safety
+///
+/// Caller must ensure:
+/// - `out_path_buf` is a valid, non-null pointer to mutable memory of at least `path_buf_len` bytes.
+/// - `out_master` and `out_err_msg` are valid, non-null pointers to mutable memory.
+/// - The caller must free any error message returned in `out_err_msg` using `pty_free_err_msg`.
+/// - The handle returned in `out_master` must be freed using `pty_free_master`.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn pty_open_slave_path(
+    rows: u16,
+    cols: u16,
+    out_master: *mut MasterHandle,
+    out_path_buf: *mut u8,
+    path_buf_len: usize,
+    out_err_msg: *mut *mut libc::c_char,
+) -> i32 {
+    ffi_call("pty_open_slave_path", out_err_msg, || unsafe {
+        let out_master = out_ref(out_master)?;
+        #[cfg(unix)]
+        {
+            let pair = native_pty_system().openpty(PtySize {
+                rows,
+                cols,
+                pixel_width: 0,
+                pixel_height: 0,
+            })?;
+            let path = pair
+                .master
+                .tty_name()
+                .ok_or("Unable to determine the slave device path")?;
+            let mut path_len = 0;
+            copy_str_out(
+                &path.to_string_lossy(),
+                out_path_buf,
+                path_buf_len,
+                &mut path_len,
+            )?;
+            *out_master = into_handle(Master::new(pair.master));
+            Ok(0)
+        }
+        #[cfg(not(unix))]
+        {
+            let _ = (rows, cols, out_master, out_path_buf, path_buf_len);
+            Err(PtyError::unsupported("pty_open_slave_path"))
+        }
+    })
+}
+
+/// Get the number of the PTY, the `<n>` in `/dev/pts/<n>` (Linux only).
+/// Returns 0 on success, -1 on error, -2 on other platforms; sets out_err_msg (caller frees via pty_free_err_msg).
+///
+/// Read from the master with the `TIOCGPTN` ioctl. Useful for matching a session to the
+/// `/dev/pts` entry, the TTY column of `ps` or the kernel's logs while debugging. Numbers
+/// are reused once a PTY is closed.
+///
+/// # Safety
+///
+/// Caller must ensure:
+/// - `master` is a valid, non-null handle obtained from `pty_open_and_spawn` or `pty_open`.
+/// - `out_ptn` and `out_err_msg` are valid, non-null pointers to mutable memory.
+/// - The caller must free any error message using `pty_free_err_msg`.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn pty_master_ptn(
+    master: MasterHandle,
+    out_ptn: *mut u32,
+    out_err_msg: *mut *mut libc::c_char,
+) -> i32 {
+    ffi_call("pty_master_ptn", out_err_msg, || unsafe {
+        let master_struct = handle_mut(master)?;
+        let out_ptn = out_ref(out_ptn)?;
+        #[cfg(target_os = "linux")]
+        {
+            *out_ptn =
+                fd::pts_index(master_struct.fd()?)?.ok_or("The master's fd is not a PTY master")?;
+            Ok(0)
+        }
+        #[cfg(not(target_os = "linux"))]
+        {
+            let _ = (master_struct, out_ptn);
+            Err(PtyError::unsupported("pty_master_ptn"))
+        }
+    })
+}
+
+/// Spawn a new child on an already open slave, reusing the same PTY.
+/// Returns 0 on success, -1 on error; sets out_err_msg (caller frees via pty_free_err_msg).
+///
+/// Intended for shells that run commands one after another in the same window: call it
+/// once for the first child and again after the previous child exited. Size and termios
+/// settings live on the PTY itself, so they carry over to the new child.
+///
+/// # Safety
+///
+/// Caller must ensure:
+/// - `slave` is a valid, non-null handle obtained from `pty_open`.
+/// - `prog` is a valid, non-null pointer to a null-terminated C string.
+/// - If `argc` > 0, `argv` is a valid, non-null pointer to an array of `argc` pointers, each pointing to a null-terminated C string or null.
+/// - `child_out` and `out_err_msg` are valid, non-null pointers to mutable memory.
+/// - The previous child spawned on this slave (if any) has exited.
+/// - The handle returned in `child_out` must be freed using `pty_free_child`.
+/// - The caller must free any error message using `pty_free_err_msg`.
+/// - No concurrent access to the slave handle.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn pty_slave_respawn(
+    slave: SlaveHandle,
+    prog: *const libc::c_char,
+    argv: *const *const libc::c_char,
+    argc: usize,
+    child_out: *mut ChildHandle,
+    out_err_msg: *mut *mut libc::c_char,
+) -> i32 {
+    ffi_call("pty_slave_respawn", out_err_msg, || unsafe {
+        let slave_struct = handle_mut(slave)?;
+        let child_out = out_ref(child_out)?;
+        let cmd = command_from_argv(prog, argv, argc)?;
+        argmax::check(&cmd)?;
+        let child = slave_struct.inner.spawn_command(cmd)?;
+        *child_out = into_handle(Child::new(child, slave_struct.tty_dev));
+        Ok(0)
+    })
+}
+
+/// Get a cloned reader from master
+/// Returns 0 on success, -1 on error; sets out_err_msg to error string (caller must free) or null
+///
+/// # Safety
+///
+/// Caller must ensure:
+/// - `master` is a valid, non-null handle obtained from `pty_open_and_spawn`.
+/// - `out_reader` and `out_err_msg` are valid, non-null pointers to mutable memory.
+/// - The caller must free the reader handle using `pty_free_reader`.
+/// - The caller must free any error message using `pty_free_err_msg`.
+/// - No concurrent mutation or invalidation of the master handle.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn pty_get_reader(
+    master: MasterHandle,
+    out_reader: *mut ReaderHandle,
+    out_err_msg: *mut *mut libc::c_char,
+) -> i32 {
+    ffi_call("pty_get_reader", out_err_msg, || unsafe {
+        let master_struct = handle_mut(master)?;
+        let out_reader = out_ref(out_reader)?;
+        #[allow(unused_mut)]
+        let mut reader = match &master_struct.log_pump {
+            Some(pump) => Reader::shared(pump.clone(), master_struct.session.clone()),
+            None => Reader::new(
+                master_struct.inner.try_clone_reader()?,
+                master_struct.session.clone(),
+            ),
+        };
+        #[cfg(unix)]
+        if let Some(fd) = master_struct.inner.as_raw_fd() {
+            let fd = std::os::fd::BorrowedFd::borrow_raw(fd);
+            reader.set_poll_fd(fd.try_clone_to_owned()?)?;
+        }
+        if master_struct.large_buffers {
+            reader.start_pump(reader::LARGE_PUMP_CAPACITY);
+        }
+        *out_reader = into_handle(reader);
+        Ok(0)
+    })
+}
+
+/// Get a reader for the child's stderr, split off the PTY by the builder (Unix only).
+/// Returns 0 on success, -1 on error, -2 on other platforms; sets out_err_msg (caller frees via pty_free_err_msg).
+///
+/// Requires a master spawned by a builder with `pty_builder_stdio(builder, 2, 3, -1)`,
+/// which sends the child's stderr to a pipe instead of the PTY; for any other master this
+/// fails. The result is an ordinary reader handle, so every `pty_read*` function, the
+/// timeouts, line and marker reads and the reader settings work on it the same as on the
+/// PTY's output. It reaches end of file once the child and any processes it started have
+/// closed their stderr. Like `pty_get_reader` this can be called more than once, but
+/// readers of the same pipe compete for its output. Output is kept in the pipe until read,
+/// and a child writing more than it holds blocks until it is.
+///
+/// # Safety
+///
+/// Caller must ensure:
+/// - `master` is a valid, non-null handle obtained from `pty_builder_spawn`.
+/// - `out_reader` and `out_err_msg` are valid, non-null pointers to mutable memory.
+/// - The caller must free the reader handle using `pty_free_reader`.
+/// - The caller must free any error message using `pty_free_err_msg`.
+/// - No concurrent mutation or invalidation of the master handle.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn pty_get_stderr_reader(
+    master: MasterHandle,
+    out_reader: *mut ReaderHandle,
+    out_err_msg: *mut *mut libc::c_char,
+) -> i32 {
+    ffi_call("pty_get_stderr_reader", out_err_msg, || unsafe {
+        let master_struct = handle_mut(master)?;
+        let out_reader = out_ref(out_reader)?;
+        #[cfg(unix)]
+        {
+            let pipe = master_struct
+                .stderr
+                .as_ref()
+                .ok_or("The child's stderr goes to the PTY, not a pipe")?;
+            let mut reader = Reader::new(
+                Box::new(std::fs::File::from(pipe.try_clone()?)),
+                master_struct.session.clone(),
+            );
+            reader.set_poll_fd(pipe.try_clone()?)?;
+            *out_reader = into_handle(reader);
+            Ok(0)
+        }
+        #[cfg(not(unix))]
+        {
+            let _ = (master_struct, out_reader);
+            Err(PtyError::unsupported("pty_get_stderr_reader"))
+        }
+    })
+}
+
+/// Get the writer from master (can only be called once)
+/// Returns 0 on success, -1 on error; sets out_err_msg to error string (caller must free) or null
+///
+/// # Safety
+///
+/// Caller must ensure:
+/// - `master` is a valid, non-null handle obtained from `pty_open_and_spawn`.
+/// - `out_writer` and `out_err_msg` are valid, non-null pointers to mutable memory.
+/// - This function is called at most once per master handle.
+/// - The caller must free the writer handle using `pty_free_writer`.
+/// - The caller must free any error message using `pty_free_err_msg`.
+/// - No concurrent mutation or invalidation of the master handle.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn pty_get_writer(
+    master: MasterHandle,
+    out_writer: *mut WriterHandle,
+    out_err_msg: *mut *mut libc::c_char,
+) -> i32 {
+    ffi_call("pty_get_writer", out_err_msg, || unsafe {
+        let master_struct = handle_mut(master)?;
+        let out_writer = out_ref(out_writer)?;
+        let writer = match master_struct.writer.take() {
+            Some(writer) => writer,
+            None => master_struct.inner.take_writer()?,
+        };
+        #[cfg(unix)]
+        let poll_fd = match master_struct.inner.as_raw_fd() {
+            Some(fd) => Some(std::os::fd::BorrowedFd::borrow_raw(fd).try_clone_to_owned()?),
+            None => None,
+        };
+        *out_writer = into_handle(Writer {
+            inner: writer,
+            #[cfg(unix)]
+            poll_fd,
+        });
+        Ok(0)
+    })
+}
+
+/// Read from reader handle
+/// Returns number of bytes read, -1 on error, -7 if the PTY is non-blocking and has no output yet; sets out_err_msg to error string (caller must free) or null
+///
+/// # Safety
+///
+/// Caller must ensure:
+/// - `reader` is a valid, non-null handle obtained from `pty_get_reader`.
+/// - `buf` is a valid, non-null pointer to mutable memory of at least `len` bytes.
+/// - `out_err_msg` is a valid, non-null pointer to mutable memory.
+/// - The caller must free any error message using `pty_free_err_msg`.
+/// - No concurrent access to the reader handle. Writing through the writer handle from
+///   another thread at the same time is fine.
+/// - `len` does not cause overflow or exceed system limits.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn pty_read(
+    reader: ReaderHandle,
+    buf: *mut u8,
+    len: usize,
+    out_err_msg: *mut *mut libc::c_char,
+) -> isize {
+    ffi_call("pty_read", out_err_msg, || unsafe {
+        let reader_struct = handle_mut(reader)?;
+        let slice = buf_mut(buf, len)?;
+        Ok(reader_struct.read_ready(slice)? as isize)
+    })
+}
+
+/// Read from reader handle, zeroing the rest of the buffer
+/// Returns number of bytes read, -1 on error, -7 if the PTY is non-blocking and has no output yet; sets out_err_msg to error string (caller must free) or null
+///
+/// Same as `pty_read`, but every byte of `buf` past the ones read is set to 0, so a buffer
+/// reused across reads never holds leftovers from a longer earlier read, and a caller
+/// treating it as a C string finds the terminator right after the data (provided the
+/// read did not fill the buffer). At EOF or on error the whole buffer is zeroed.
+///
+/// # Safety
+///
+/// Caller must ensure:
+/// - `reader` is a valid, non-null handle obtained from `pty_get_reader`.
+/// - `buf` is a valid, non-null pointer to mutable memory of at least `len` bytes.
+/// - `out_err_msg` is a valid, non-null pointer to mutable memory.
+/// - The caller must free any error message using `pty_free_err_msg`.
+/// - No concurrent access to the reader handle.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn pty_read_clearing(
+    reader: ReaderHandle,
+    buf: *mut u8,
+    len: usize,
+    out_err_msg: *mut *mut libc::c_char,
+) -> isize {
+    ffi_call("pty_read_clearing", out_err_msg, || unsafe {
+        let reader_struct = handle_mut(reader)?;
+        let slice = buf_mut(buf, len)?;
+        let result = reader_struct.read_ready(slice);
+        let n = *result.as_ref().unwrap_or(&0);
+        slice[n..].fill(0);
+        Ok(result? as isize)
+    })
+}
+
+/// Write to writer handle
+/// Returns number of bytes written, -1 on error, -4 if the child has exited; sets out_err_msg to error string (caller must free) or null
+///
+/// The child-gone code covers the errors a write fails with once the other side of the PTY
+/// is closed. Linux accepts writes to a PTY for a while after the child exited, so use
+/// `pty_write_checked` to be told reliably.
+///
+/// # Safety
+///
+/// Caller must ensure:
+/// - `writer` is a valid, non-null handle obtained from `pty_get_writer`.
+/// - `buf` is a valid, non-null pointer to immutable memory of at least `len` bytes.
+/// - `out_err_msg` is a valid, non-null pointer to mutable memory.
+/// - The caller must free any error message using `pty_free_err_msg`.
+/// - No concurrent access to the writer handle. Reading through a reader handle from
+///   another thread at the same time is fine.
+/// - `len` does not cause overflow or exceed system limits.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn pty_write(
+    writer: WriterHandle,
+    buf: *const u8,
+    len: usize,
+    out_err_msg: *mut *mut libc::c_char,
+) -> isize {
+    ffi_call("pty_write", out_err_msg, || unsafe {
+        let writer_struct = handle_mut(writer)?;
+        let slice = buf_ref(buf, len)?;
+        writer_struct.write(slice)
+    })
+}
+
+/// Write to writer handle after checking that the child is still running
+/// Returns number of bytes written, -1 on error, -4 if the child has exited; sets out_err_msg to error string (caller must free) or null
+///
+/// Gives a portable answer to whether input can still be delivered: an exited child is
+/// reported with -4 and nothing is written, as is a write failing because the PTY was
+/// closed. Without the check, writes after exit succeed on Linux until the PTY's input
+/// buffer is full. A child that exits right after the check can still miss the input.
+///
+/// # Safety
+///
+/// Caller must ensure:
+/// - `writer` is a valid, non-null handle obtained from `pty_get_writer`.
+/// - `child` is a valid, non-null handle obtained from `pty_open_and_spawn`, for the child on the writer's PTY.
+/// - `buf` is a valid, non-null pointer to immutable memory of at least `len` bytes.
+/// - `out_err_msg` is a valid, non-null pointer to mutable memory.
+/// - The caller must free any error message using `pty_free_err_msg`.
+/// - No concurrent access to the writer or child handle.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn pty_write_checked(
+    writer: WriterHandle,
+    child: ChildHandle,
+    buf: *const u8,
+    len: usize,
+    out_err_msg: *mut *mut libc::c_char,
+) -> isize {
+    ffi_call("pty_write_checked", out_err_msg, || unsafe {
+        let writer_struct = handle_mut(writer)?;
+        let child_struct = handle_mut(child)?;
+        let slice = buf_ref(buf, len)?;
+        if child_struct.try_wait()?.is_some() {
+            return Err(PtyError::child_gone());
         }
+        writer_struct.write(slice)
+    })
+}
 
-        let child = match pair.slave.spawn_command(builder) {
-            Ok(c) => c,
-            Err(e) => {
-                let err_str = CString::new(e.to_string())
-                    .unwrap_or_else(|_| CString::new("Failed to spawn command").unwrap());
-                *out_err_msg = err_str.into_raw();
-                // Rollback: drop pair (frees master/slave implicitly)
-                drop(pair);
-                return -1;
-            }
-        };
-
-        drop(pair.slave); // Always drop the pair.slave handle in the parent process after spawning, otherwise, the pipe may not close properly.
-
-        *master_out = Box::into_raw(Box::new(Master { inner: pair.master }));
-        *child_out = Box::into_raw(Box::new(Child { inner: child }));
-        0
-    }));
-
-    match result {
-        Ok(code) => code,
-        Err(_) => unsafe {
-            let err_str = CString::new("Panic during PTY open/spawn").unwrap();
-            *out_err_msg = err_str.into_raw();
-            -1
-        }
-    }
+/// Write to writer handle, bytes exactly as given
+/// Returns number of bytes written, -1 on error, -4 if the child has exited; sets out_err_msg to error string (caller must free) or null
+///
+/// Alias of `pty_write`, kept for callers that want to say the bytes are sent unchanged,
+/// e.g. escape sequences and binary data. Writers apply no transforms (encoding, newline
+/// translation), so both calls do the same. The terminal's own input processing (e.g.
+/// ICRNL turning CR into LF) is part of the PTY, not the writer, and still applies; switch
+/// the terminal to raw mode to avoid it.
+///
+/// # Safety
+///
+/// Caller must ensure:
+/// - `writer` is a valid, non-null handle obtained from `pty_get_writer`.
+/// - `buf` is a valid, non-null pointer to immutable memory of at least `len` bytes.
+/// - `out_err_msg` is a valid, non-null pointer to mutable memory.
+/// - The caller must free any error message using `pty_free_err_msg`.
+/// - No concurrent access to the writer handle.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn pty_write_raw(
+    writer: WriterHandle,
+    buf: *const u8,
+    len: usize,
+    out_err_msg: *mut *mut libc::c_char,
+) -> isize {
+    ffi_call("pty_write_raw", out_err_msg, || unsafe {
+        let writer_struct = handle_mut(writer)?;
+        let slice = buf_ref(buf, len)?;
+        writer_struct.write(slice)
+    })
 }
 
-/// Get a cloned reader from master
+/// Resize via master
 /// Returns 0 on success, -1 on error; sets out_err_msg to error string (caller must free) or null
 ///
 /// # Safety
 ///
 /// Caller must ensure:
 /// - `master` is a valid, non-null handle obtained from `pty_open_and_spawn`.
-/// - `out_reader` and `out_err_msg` are valid, non-null pointers to mutable memory.
-/// - The caller must free the reader handle using `pty_free_reader`.
+/// - `out_err_msg` is a valid, non-null pointer to mutable memory.
 /// - The caller must free any error message using `pty_free_err_msg`.
 /// - No concurrent mutation or invalidation of the master handle.
 #[unsafe(no_mangle)]
-pub unsafe extern "C" fn pty_get_reader(
+pub unsafe extern "C" fn pty_resize(
     master: MasterHandle,
-    out_reader: *mut ReaderHandle,
+    rows: u16,
+    cols: u16,
     out_err_msg: *mut *mut libc::c_char,
 ) -> i32 {
-    if master.is_null() || out_reader.is_null() {
-        return -1;
-    }
-    let result = catch_unwind(AssertUnwindSafe(|| unsafe {
-        let master_struct = &mut *master;
-        match master_struct.inner.try_clone_reader() {
-            Ok(reader) => {
-                *out_reader = Box::into_raw(Box::new(Reader { inner: reader }));
-                0
-            }
-            Err(e) => {
-                let err_str = CString::new(e.to_string())
-                    .unwrap_or_else(|_| CString::new("Unknown error").unwrap());
-                *out_err_msg = err_str.into_raw();
-                -1
-            }
-        }
-    }));
-    match result {
-        Ok(code) => code,
-        Err(_) => unsafe {
-            let err_str = CString::new("something is wrong in pty_get_reader").unwrap();
-            *out_err_msg = err_str.into_raw();
-            -1
-        }
-    }
+    ffi_call("pty_resize", out_err_msg, || unsafe {
+        let master_struct = handle_mut(master)?;
+        master_struct.inner.resize(PtySize {
+            rows,
+            cols,
+            pixel_width: 0,
+            pixel_height: 0,
+        })?;
+        Ok(0)
+    })
 }
 
-/// Get the writer from master (can only be called once)
+/// Resize via master, then make the child redraw even if the size did not change
 /// Returns 0 on success, -1 on error; sets out_err_msg to error string (caller must free) or null
 ///
+/// The kernel only signals a resize when the size actually changes. This additionally
+/// sends SIGWINCH to the PTY's foreground process group on Unix; on Windows, where there
+/// are no signals, it briefly resizes one column off and back so ConPTY reports a resize
+/// event. Programs that only redraw on a key press (Ctrl-L in most shells and editors)
+/// can be sent one through the writer.
+///
 /// # Safety
 ///
 /// Caller must ensure:
 /// - `master` is a valid, non-null handle obtained from `pty_open_and_spawn`.
-/// - `out_writer` and `out_err_msg` are valid, non-null pointers to mutable memory.
-/// - This function is called at most once per master handle.
-/// - The caller must free the writer handle using `pty_free_writer`.
+/// - `out_err_msg` is a valid, non-null pointer to mutable memory.
 /// - The caller must free any error message using `pty_free_err_msg`.
 /// - No concurrent mutation or invalidation of the master handle.
 #[unsafe(no_mangle)]
-pub unsafe extern "C" fn pty_get_writer(
+pub unsafe extern "C" fn pty_resize_redraw(
     master: MasterHandle,
-    out_writer: *mut WriterHandle,
+    rows: u16,
+    cols: u16,
     out_err_msg: *mut *mut libc::c_char,
 ) -> i32 {
-    if master.is_null() || out_writer.is_null() {
-        return -1;
-    }
-    let result = catch_unwind(AssertUnwindSafe(|| unsafe {
-        let master_struct = &mut *master;
-        match master_struct.inner.take_writer() {
-            Ok(writer) => {
-                *out_writer = Box::into_raw(Box::new(Writer { inner: writer }));
-                0
-            }
-            Err(e) => {
-                let err_str = CString::new(e.to_string())
-                    .unwrap_or_else(|_| CString::new("Unknown error").unwrap());
-                *out_err_msg = err_str.into_raw();
-                -1
-            }
-        }
-    }));
-    match result {
-        Ok(code) => code,
-        Err(_) => unsafe {
-            let err_str = CString::new("something is wrong in pty_get_writer").unwrap();
-            *out_err_msg = err_str.into_raw();
-            -1
+    ffi_call("pty_resize_redraw", out_err_msg, || unsafe {
+        let master_struct = handle_mut(master)?;
+        let size = PtySize {
+            rows,
+            cols,
+            pixel_width: 0,
+            pixel_height: 0,
+        };
+        #[cfg(windows)]
+        master_struct.inner.resize(PtySize {
+            cols: if cols > 1 { cols - 1 } else { cols + 1 },
+            ..size
+        })?;
+        master_struct.inner.resize(size)?;
+        #[cfg(unix)]
+        if let Some(pgrp) = master_struct.inner.process_group_leader() {
+            // Fails only if the group exited meanwhile, in which case nobody needs redrawing.
+            libc::killpg(pgrp, libc::SIGWINCH);
         }
-    }
+        Ok(0)
+    })
 }
 
-/// Read from reader handle
-/// Returns number of bytes read, -1 on error; sets out_err_msg to error string (caller must free) or null
+/// `pty_resize_and_wait_output` result: the child wrote output after the resize.
+const PTY_RESIZE_ANSWERED: i32 = 0;
+/// `pty_resize_and_wait_output` result: the timeout elapsed first.
+const PTY_RESIZE_TIMEOUT: i32 = 1;
+
+/// Resize via master, then wait until the child writes output in reaction.
+/// Returns 0 if output arrived, 1 on timeout, -1 on error; sets out_err_msg (caller frees via pty_free_err_msg).
+///
+/// A best-effort way to let a full-screen program settle after a resize, e.g. before
+/// taking a screenshot: programs that handle SIGWINCH usually redraw, and that redraw is
+/// the only sign of it. After resizing like `pty_resize`, this waits up to `timeout_ms`
+/// for the first new output on `reader` and leaves it unread. It does not wait for the
+/// redraw to finish; follow up with `pty_read_idle_framed` for that.
+///
+/// Any output counts, so output the child wrote before the resize that had not been read
+/// yet is mistaken for a reaction; read everything available first. A program that does
+/// not redraw, or whose size did not change, times out. Output ending before anything
+/// arrives is an error.
 ///
 /// # Safety
 ///
 /// Caller must ensure:
-/// - `reader` is a valid, non-null handle obtained from `pty_get_reader`.
-/// - `buf` is a valid, non-null pointer to mutable memory of at least `len` bytes.
+/// - `master` is a valid, non-null handle obtained from `pty_open_and_spawn`.
+/// - `reader` is a valid, non-null handle obtained from `pty_get_reader` for the same PTY.
 /// - `out_err_msg` is a valid, non-null pointer to mutable memory.
 /// - The caller must free any error message using `pty_free_err_msg`.
-/// - No concurrent access to the reader handle.
-/// - `len` does not cause overflow or exceed system limits.
+/// - No concurrent access to the master or reader handle.
 #[unsafe(no_mangle)]
-pub unsafe extern "C" fn pty_read(
+pub unsafe extern "C" fn pty_resize_and_wait_output(
+    master: MasterHandle,
     reader: ReaderHandle,
-    buf: *mut u8,
-    len: usize,
+    rows: u16,
+    cols: u16,
+    timeout_ms: u32,
     out_err_msg: *mut *mut libc::c_char,
-) -> isize {
-    if reader.is_null() || buf.is_null() {
-        return -1;
-    }
-    let result = catch_unwind(AssertUnwindSafe(|| unsafe {
-        let reader_struct = &mut *reader;
-        let slice = std::slice::from_raw_parts_mut(buf, len);
-        match reader_struct.inner.read(slice) {
-            Ok(bytes) => bytes as isize,
-            Err(e) => {
-                let err_str = CString::new(e.to_string())
-                    .unwrap_or_else(|_| CString::new("Unknown error").unwrap());
-                *out_err_msg = err_str.into_raw();
-                -1
-            }
-        }
-    }));
-    match result {
-        Ok(res) => res,
-        Err(_) => unsafe {
-            let err_str = CString::new("something is wrong in pty_read").unwrap();
-            *out_err_msg = err_str.into_raw();
-            -1
+) -> i32 {
+    ffi_call("pty_resize_and_wait_output", out_err_msg, || unsafe {
+        let master_struct = handle_mut(master)?;
+        let reader_struct = handle_mut(reader)?;
+        master_struct.inner.resize(PtySize {
+            rows,
+            cols,
+            pixel_width: 0,
+            pixel_height: 0,
+        })?;
+        let deadline = Instant::now() + Duration::from_millis(u64::from(timeout_ms));
+        match reader_struct.wait_for_more(deadline) {
+            Ok(true) => Ok(PTY_RESIZE_ANSWERED),
+            Ok(false) => Ok(PTY_RESIZE_TIMEOUT),
+            Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => Err(PtyError::new(
+                "Output ended before the child reacted to the resize",
+            )),
+            Err(e) => Err(e.into()),
         }
+    })
+}
+
+/// Sends `signal` to the PTY's foreground process group.
+#[cfg(unix)]
+fn signal_foreground(master: &Master, signal: libc::c_int) -> PtyResult<()> {
+    let pgrp = master
+        .inner
+        .process_group_leader()
+        .ok_or("The PTY has no foreground process group")?;
+    if unsafe { libc::killpg(pgrp, signal) } == -1 {
+        return Err(std::io::Error::last_os_error().into());
     }
+    Ok(())
 }
 
-/// Write to writer handle
-/// Returns number of bytes written, -1 on error; sets out_err_msg to error string (caller must free) or null
+/// Pause the programs writing to the PTY (Unix only).
+/// Returns 0 on success, -1 on error, -2 on other platforms; sets out_err_msg (caller frees via pty_free_err_msg).
+///
+/// Sends SIGSTOP to the PTY's foreground process group, which stops it at once instead of
+/// when the PTY buffer fills up from not reading. Output already written stays readable.
+/// Resume with `pty_resume_output`. Limits: only the foreground group is stopped, so
+/// background jobs keep running; a job-control shell that started the group notices the
+/// stop and takes the terminal back, as if Ctrl-Z had been pressed; and the stopped
+/// programs' timers keep running, so timeouts may fire on resume.
 ///
 /// # Safety
 ///
 /// Caller must ensure:
-/// - `writer` is a valid, non-null handle obtained from `pty_get_writer`.
-/// - `buf` is a valid, non-null pointer to immutable memory of at least `len` bytes.
+/// - `master` is a valid, non-null handle obtained from `pty_open_and_spawn`.
 /// - `out_err_msg` is a valid, non-null pointer to mutable memory.
 /// - The caller must free any error message using `pty_free_err_msg`.
-/// - No concurrent access to the writer handle.
-/// - `len` does not cause overflow or exceed system limits.
+/// - No concurrent mutation or invalidation of the master handle.
 #[unsafe(no_mangle)]
-pub unsafe extern "C" fn pty_write(
-    writer: WriterHandle,
-    buf: *const u8,
-    len: usize,
+pub unsafe extern "C" fn pty_pause_output(
+    master: MasterHandle,
     out_err_msg: *mut *mut libc::c_char,
-) -> isize {
-    if writer.is_null() || buf.is_null() {
-        return -1;
-    }
-    let result = catch_unwind(AssertUnwindSafe(|| unsafe {
-        let writer_struct = &mut *writer;
-        let slice = std::slice::from_raw_parts(buf, len);
-        match writer_struct.inner.write(slice) {
-            Ok(bytes) => bytes as isize,
-            Err(e) => {
-                let err_str = CString::new(e.to_string())
-                    .unwrap_or_else(|_| CString::new("Unknown error").unwrap());
-                *out_err_msg = err_str.into_raw();
-                -1
-            }
+) -> i32 {
+    ffi_call("pty_pause_output", out_err_msg, || unsafe {
+        let master_struct = handle_mut(master)?;
+        #[cfg(unix)]
+        {
+            signal_foreground(master_struct, libc::SIGSTOP)?;
+            Ok(0)
         }
-    }));
-    match result {
-        Ok(res) => res,
-        Err(_) => unsafe {
-            let err_str = CString::new("something is wrong in pty_write").unwrap();
-            *out_err_msg = err_str.into_raw();
-            -1
+        #[cfg(not(unix))]
+        {
+            let _ = master_struct;
+            Err(PtyError::unsupported("pty_pause_output"))
         }
-    }
+    })
 }
 
-/// Resize via master
-/// Returns 0 on success, -1 on error; sets out_err_msg to error string (caller must free) or null
+/// Resume programs paused with `pty_pause_output` (Unix only).
+/// Returns 0 on success, -1 on error, -2 on other platforms; sets out_err_msg (caller frees via pty_free_err_msg).
+///
+/// Sends SIGCONT to the PTY's foreground process group.
 ///
 /// # Safety
 ///
@@ -316,40 +1370,23 @@ pub unsafe extern "C" fn pty_write(
 /// - The caller must free any error message using `pty_free_err_msg`.
 /// - No concurrent mutation or invalidation of the master handle.
 #[unsafe(no_mangle)]
-pub unsafe extern "C" fn pty_resize(
+pub unsafe extern "C" fn pty_resume_output(
     master: MasterHandle,
-    rows: u16,
-    cols: u16,
     out_err_msg: *mut *mut libc::c_char,
 ) -> i32 {
-    if master.is_null() {
-        return -1;
-    }
-    let result = catch_unwind(AssertUnwindSafe(|| unsafe {
-        let master_struct = &mut *master;
-        match master_struct.inner.resize(PtySize {
-            rows,
-            cols,
-            pixel_width: 0,
-            pixel_height: 0,
-        }) {
-            Ok(_) => 0,
-            Err(e) => {
-                let err_str = CString::new(e.to_string())
-                    .unwrap_or_else(|_| CString::new("Unknown error").unwrap());
-                *out_err_msg = err_str.into_raw();
-                -1
-            }
+    ffi_call("pty_resume_output", out_err_msg, || unsafe {
+        let master_struct = handle_mut(master)?;
+        #[cfg(unix)]
+        {
+            signal_foreground(master_struct, libc::SIGCONT)?;
+            Ok(0)
         }
-    }));
-    match result {
-        Ok(code) => code,
-        Err(_) => unsafe {
-            let err_str = CString::new("something is wrong in pty_resize").unwrap();
-            *out_err_msg = err_str.into_raw();
-            -1
+        #[cfg(not(unix))]
+        {
+            let _ = master_struct;
+            Err(PtyError::unsupported("pty_resume_output"))
         }
-    }
+    })
 }
 
 /// Free the master handle.
@@ -362,11 +1399,20 @@ pub unsafe extern "C" fn pty_resize(
 /// - No double-free (call at most once per handle).
 #[unsafe(no_mangle)]
 pub unsafe extern "C" fn pty_free_master(master: MasterHandle) {
-    if !master.is_null() {
-        unsafe {
-            drop(Box::from_raw(master));
-        }
-    }
+    unsafe { free_handle(master) }
+}
+
+/// Free the slave handle.
+///
+/// # Safety
+///
+/// Caller must ensure:
+/// - `slave` is either null or a valid handle obtained from `pty_open`.
+/// - The handle is not used after freeing.
+/// - No double-free (call at most once per handle).
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn pty_free_slave(slave: SlaveHandle) {
+    unsafe { free_handle(slave) }
 }
 
 /// Free the child handle.
@@ -379,11 +1425,7 @@ pub unsafe extern "C" fn pty_free_master(master: MasterHandle) {
 /// - No double-free (call at most once per handle).
 #[unsafe(no_mangle)]
 pub unsafe extern "C" fn pty_free_child(child: ChildHandle) {
-    if !child.is_null() {
-        unsafe {
-            drop(Box::from_raw(child));
-        }
-    }
+    unsafe { free_handle(child) }
 }
 
 /// Free the reader handle.
@@ -396,11 +1438,7 @@ pub unsafe extern "C" fn pty_free_child(child: ChildHandle) {
 /// - No double-free (call at most once per handle).
 #[unsafe(no_mangle)]
 pub unsafe extern "C" fn pty_free_reader(reader: ReaderHandle) {
-    if !reader.is_null() {
-        unsafe {
-            drop(Box::from_raw(reader));
-        }
-    }
+    unsafe { free_handle(reader) }
 }
 
 /// Free the writer handle.
@@ -413,11 +1451,7 @@ pub unsafe extern "C" fn pty_free_reader(reader: ReaderHandle) {
 /// - No double-free (call at most once per handle).
 #[unsafe(no_mangle)]
 pub unsafe extern "C" fn pty_free_writer(writer: WriterHandle) {
-    if !writer.is_null() {
-        unsafe {
-            drop(Box::from_raw(writer));
-        }
-    }
+    unsafe { free_handle(writer) }
 }
 
 /// Free the error message string.
@@ -437,15 +1471,50 @@ pub unsafe extern "C" fn pty_free_err_msg(ptr: *mut libc::c_char) {
     }
 }
 
+/// Get the length in bytes of an error message, excluding the terminating NUL.
+///
+/// Lets a binding size its own buffer exactly, copy the message into it, and release the
+/// original with `pty_free_err_msg` right away. Returns 0 for null.
+///
+/// # Safety
+///
+/// Caller must ensure:
+/// - `ptr` is either null or a valid pointer obtained from an `out_err_msg` parameter and not yet freed.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn pty_err_msg_len(ptr: *const libc::c_char) -> usize {
+    if ptr.is_null() {
+        return 0;
+    }
+    unsafe { libc::strlen(ptr) }
+}
+
+/// Free a string returned by a function other than through `out_err_msg`.
+///
+/// # Safety
+///
+/// Caller must ensure:
+/// - `ptr` is either null or a valid pointer obtained from a string out-parameter.
+/// - The pointer is not used after freeing.
+/// - No double-free (call at most once per string).
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn pty_free_string(ptr: *mut libc::c_char) {
+    unsafe { pty_free_err_msg(ptr) }
+}
+
 /// Wait for the child process to exit (blocking).
-/// Consumes the child handle.
+///
+/// `exit_code_out` receives the child's exit code, e.g. 42 for `exit 42`.
+///
+/// Signals delivered to the calling process while waiting do not abort the wait. The
+/// handle stays valid: once the child has exited, later calls to this function and to
+/// `pty_child_try_wait` return the same status immediately, and the handle must still be
+/// freed with `pty_free_child`.
 ///
 /// # Safety
 ///
 /// Caller must ensure:
 /// - `child` is a valid, non-null handle obtained from `pty_open_and_spawn`.
 /// - `exit_code_out`, `signal_out`, and `out_err_msg` are valid, non-null pointers to mutable memory.
-/// - The handle is not used after this call (consumed).
 /// - The caller must free any error message using `pty_free_err_msg`.
 /// - No concurrent access to the child handle.
 #[unsafe(no_mangle)]
@@ -455,26 +1524,195 @@ pub unsafe extern "C" fn pty_child_wait(
     signal_out: *mut i32,
     out_err_msg: *mut *mut libc::c_char,
 ) -> i32 {
-    if child.is_null() || exit_code_out.is_null() || signal_out.is_null() {
-        return -1;
-    }
-    let result = catch_unwind(AssertUnwindSafe(|| unsafe {
-        let mut child_struct = Box::from_raw(child); // Take ownership, consumes the handle
-        match child_struct.inner.wait() {
-            Ok(status) => {
-                *exit_code_out = if status.success() { 0 } else { 1 };
-                *signal_out = 0;
-                0
-            }
-            Err(e) => {
-                let err_str = CString::new(e.to_string())
-                    .unwrap_or_else(|_| CString::new("Unknown error").unwrap());
-                *out_err_msg = err_str.into_raw();
-                -1
-            }
+    ffi_call("pty_child_wait", out_err_msg, || unsafe {
+        let exit_code_out = out_ref(exit_code_out)?;
+        let signal_out = out_ref(signal_out)?;
+        let status = handle_mut(child)?.wait()?;
+        *exit_code_out = exit_code(&status);
+        *signal_out = 0;
+        Ok(0)
+    })
+}
+
+/// Receives the milliseconds elapsed since `pty_child_wait_progress` began waiting.
+pub type PtyProgressCallback = extern "C" fn(elapsed_ms: u64, user_data: *mut libc::c_void);
+
+/// Wait for the child process to exit, reporting progress every `interval_ms`.
+/// Returns 0 on success, -1 on error; sets out_err_msg (caller frees via pty_free_err_msg).
+///
+/// Blocks like `pty_child_wait`, but invokes `cb(elapsed_ms, user_data)` every
+/// `interval_ms` while the child is still running, so a progress display needs no timer
+/// of its own. `cb` runs on the calling thread, never after the child has been seen to
+/// exit, and never after this function returns; the wait itself happens on a helper thread
+/// that is joined before returning. A callback that takes longer than the interval delays
+/// the next one rather than causing a burst. `exit_code_out` receives the child's exit
+/// code, the same one `pty_child_wait` reports.
+///
+/// # Safety
+///
+/// Caller must ensure:
+/// - `child` is a valid, non-null handle obtained from `pty_open_and_spawn`.
+/// - `cb` is non-null and safe to call with `user_data` until this function returns.
+/// - `exit_code_out` and `out_err_msg` are valid, non-null pointers to mutable memory.
+/// - The caller must free any error message using `pty_free_err_msg`.
+/// - No concurrent access to the child handle.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn pty_child_wait_progress(
+    child: ChildHandle,
+    interval_ms: u32,
+    cb: Option<PtyProgressCallback>,
+    user_data: *mut libc::c_void,
+    exit_code_out: *mut i32,
+    out_err_msg: *mut *mut libc::c_char,
+) -> i32 {
+    ffi_call("pty_child_wait_progress", out_err_msg, || unsafe {
+        let child_struct = handle_mut(child)?;
+        let exit_code_out = out_ref(exit_code_out)?;
+        let cb = cb.ok_or("Null pointer provided")?;
+        if interval_ms == 0 {
+            return Err(PtyError::new("interval_ms must be at least 1"));
         }
-    }));
-    result.unwrap_or(-1)
+        let interval = Duration::from_millis(u64::from(interval_ms));
+        let start = Instant::now();
+        // Closed by the waiter as soon as the child is reaped.
+        let (done, finished) = mpsc::channel::<()>();
+        let status = std::thread::scope(|scope| {
+            let waiter = scope.spawn(move || {
+                let status = child_struct.wait();
+                drop(done);
+                status
+            });
+            let mut next = start + interval;
+            while let Err(RecvTimeoutError::Timeout) =
+                finished.recv_timeout(next.saturating_duration_since(Instant::now()))
+            {
+                cb(start.elapsed().as_millis() as u64, user_data);
+                next = (next + interval).max(Instant::now());
+            }
+            waiter.join()
+        })
+        .map_err(|_| "wait thread panicked")?;
+        *exit_code_out = exit_code(&status?);
+        Ok(0)
+    })
+}
+
+/// How long `pty_child_wait_with_tail` waits for output before checking whether the child
+/// has exited, and, once it has, for output still on its way.
+const TAIL_POLL: Duration = Duration::from_millis(50);
+
+/// Wait for the child process to exit while draining its output, keeping only the end.
+/// Returns 0 on success, -1 on error; sets out_err_msg (caller frees via pty_free_err_msg).
+///
+/// For error messages about a failed command: everything `reader` produces is read and
+/// dropped except the last `tail_cap` bytes, which are copied into `tail_buf` with their
+/// length in `out_tail_len`, and `exit_code_out` receives the child's exit code, the same
+/// one `pty_child_wait` reports. Reading continues while the child runs, so a child
+/// blocked on a full PTY cannot deadlock the wait; the wait itself happens on a helper
+/// thread that is joined before returning.
+///
+/// Output stops being collected at EOF, or once the child has exited and no more output
+/// arrives for 50 ms. Output of the child's own children written after that stays unread.
+/// The tail is raw bytes and may begin in the middle of a multibyte character or an
+/// escape sequence. If reading fails, the child is killed, since nothing drains its output
+/// any more, and waited for before the error is returned; the tail collected so far is
+/// stored.
+///
+/// # Safety
+///
+/// Caller must ensure:
+/// - `child` is a valid, non-null handle obtained from `pty_open_and_spawn`.
+/// - `reader` is a valid, non-null handle obtained from `pty_get_reader` for the child's PTY.
+/// - `tail_buf` is a valid, non-null pointer to mutable memory of at least `tail_cap` bytes.
+/// - `out_tail_len`, `exit_code_out` and `out_err_msg` are valid, non-null pointers to mutable memory.
+/// - The caller must free any error message using `pty_free_err_msg`.
+/// - No concurrent access to the child or reader handle.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn pty_child_wait_with_tail(
+    child: ChildHandle,
+    reader: ReaderHandle,
+    tail_buf: *mut u8,
+    tail_cap: usize,
+    out_tail_len: *mut usize,
+    exit_code_out: *mut i32,
+    out_err_msg: *mut *mut libc::c_char,
+) -> i32 {
+    ffi_call("pty_child_wait_with_tail", out_err_msg, || unsafe {
+        let child_struct = handle_mut(child)?;
+        let reader_struct = handle_mut(reader)?;
+        let tail_buf = buf_mut(tail_buf, tail_cap)?;
+        let out_tail_len = out_ref(out_tail_len)?;
+        let exit_code_out = out_ref(exit_code_out)?;
+        let mut tail = std::collections::VecDeque::with_capacity(tail_cap);
+        let mut killer = child_struct.inner.clone_killer();
+        // Closed by the waiter as soon as the child is reaped.
+        let (done, finished) = mpsc::channel::<()>();
+        let (status, read_result) = std::thread::scope(|scope| {
+            let waiter = scope.spawn(move || {
+                let status = child_struct.wait();
+                drop(done);
+                status
+            });
+            let mut chunk = [0u8; 4096];
+            let mut exited = false;
+            let read_result = loop {
+                exited = exited || finished.try_recv() == Err(mpsc::TryRecvError::Disconnected);
+                let (n, stop) = reader_struct.read_bounded(&mut chunk, 1, TAIL_POLL);
+                tail.extend(&chunk[..n]);
+                if tail.len() > tail_cap {
+                    tail.drain(..tail.len() - tail_cap);
+                }
+                match stop {
+                    reader::Stop::Filled => {}
+                    reader::Stop::Timeout if !exited => {}
+                    reader::Stop::Timeout | reader::Stop::Eof => break Ok(()),
+                    reader::Stop::Error(e) => {
+                        // Otherwise a child blocked on the full PTY would never exit.
+                        let _ = killer.kill();
+                        break Err(e);
+                    }
+                }
+            };
+            (waiter.join(), read_result)
+        });
+        let (front, back) = tail.as_slices();
+        tail_buf[..front.len()].copy_from_slice(front);
+        tail_buf[front.len()..tail.len()].copy_from_slice(back);
+        *out_tail_len = tail.len();
+        let status = status.map_err(|_| "wait thread panicked")??;
+        read_result?;
+        *exit_code_out = exit_code(&status);
+        Ok(0)
+    })
+}
+
+/// Detach the child so it keeps running, and is reaped, after its handles are freed.
+/// Returns 0 on success, -1 on error; sets out_err_msg to error string (caller must free) or null
+///
+/// Freeing a detached child handle starts a background thread that collects the exit
+/// status whenever the child exits, so it never lingers as a zombie. The handle stays
+/// usable until it is freed.
+///
+/// The child still shares the PTY. Closing the master ends output capture, and the
+/// child's further terminal writes fail. On Unix the child leads its own session, but if
+/// the PTY is its controlling terminal it also gets SIGHUP when the master closes, so a
+/// child meant to outlive the session should ignore SIGHUP or be spawned without a
+/// controlling terminal. On Windows, closing the pseudoconsole sends attached console
+/// programs a close event, which ends most of them.
+///
+/// # Safety
+///
+/// Caller must ensure:
+/// - `child` is a valid, non-null handle obtained from `pty_open_and_spawn`.
+/// - `out_err_msg` is a valid, non-null pointer to mutable memory.
+/// - The caller must free any error message using `pty_free_err_msg`.
+/// - No concurrent access to the child handle.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn pty_child_detach(child: ChildHandle, out_err_msg: *mut *mut libc::c_char) -> i32 {
+    ffi_call("pty_child_detach", out_err_msg, || unsafe {
+        handle_mut(child)?.detached = true;
+        Ok(0)
+    })
 }
 
 /// Kill the child process.
@@ -488,22 +1726,58 @@ pub unsafe extern "C" fn pty_child_wait(
 /// - No concurrent access to the child handle.
 #[unsafe(no_mangle)]
 pub unsafe extern "C" fn pty_child_kill(child: ChildHandle, out_err_msg: *mut *mut libc::c_char) -> i32 {
-    if child.is_null() {
-        return -1;
-    }
-    let result = catch_unwind(AssertUnwindSafe(|| unsafe {
-        let child_struct = &mut *child;
-        match child_struct.inner.kill() {
-            Ok(_) => 0,
-            Err(e) => {
-                let err_str = CString::new(e.to_string())
-                    .unwrap_or_else(|_| CString::new("Unknown error").unwrap());
-                *out_err_msg = err_str.into_raw();
-                -1
+    ffi_call("pty_child_kill", out_err_msg, || unsafe {
+        handle_mut(child)?.inner.kill()?;
+        Ok(0)
+    })
+}
+
+/// Send `signal` to the child, unless it has already exited (Unix only).
+/// Returns 0 on success, -1 on error, -2 on other platforms, -4 if the child has exited; sets out_err_msg (caller frees via pty_free_err_msg).
+///
+/// Checks first whether the child has exited, collecting its status if so, and only then
+/// signals it. That closes the race where the child exits, is reaped, and the OS hands its
+/// pid to an unrelated process that then receives the signal: an exited child that has not
+/// been reaped keeps its pid reserved, and only this handle reaps it. If the host sets
+/// SIGCHLD to SIG_IGN the kernel reaps children itself, and that guarantee is lost.
+/// `signal` 0 sends nothing and only checks that the child can be signaled.
+///
+/// # Safety
+///
+/// Caller must ensure:
+/// - `child` is a valid, non-null handle obtained from `pty_open_and_spawn`.
+/// - `out_err_msg` is a valid, non-null pointer to mutable memory.
+/// - The caller must free any error message using `pty_free_err_msg`.
+/// - No concurrent access to the child handle.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn pty_child_signal_safe(
+    child: ChildHandle,
+    signal: i32,
+    out_err_msg: *mut *mut libc::c_char,
+) -> i32 {
+    ffi_call("pty_child_signal_safe", out_err_msg, || unsafe {
+        let child_struct = handle_mut(child)?;
+        #[cfg(unix)]
+        {
+            if child_struct.try_wait()?.is_some() {
+                return Err(PtyError::with_code(
+                    error::PTY_ERR_CHILD_GONE,
+                    "The child has already exited; no signal was sent",
+                ));
             }
+            // Not reaped, so the pid still belongs to the child even if it exits meanwhile.
+            let pid = child_struct.pid()?;
+            if libc::kill(pid as libc::pid_t, signal) == -1 {
+                return Err(std::io::Error::last_os_error().into());
+            }
+            Ok(0)
+        }
+        #[cfg(not(unix))]
+        {
+            let _ = (child_struct, signal);
+            Err(PtyError::unsupported("pty_child_signal_safe"))
         }
-    }));
-    result.unwrap_or(-1)
+    })
 }
 
 /// Check if the child process is alive.
@@ -515,22 +1789,19 @@ pub unsafe extern "C" fn pty_child_kill(child: ChildHandle, out_err_msg: *mut *m
 /// - No concurrent access to the child handle.
 #[unsafe(no_mangle)]
 pub unsafe extern "C" fn pty_child_is_alive(child: ChildHandle) -> i32 {
-    if child.is_null() {
-        return -1;
-    }
-    let result = catch_unwind(AssertUnwindSafe(|| unsafe {
-        let child_struct = &mut *child;
-        match child_struct.inner.try_wait() {
-            Ok(Some(_)) => 0, // not alive
-            Ok(None) => 1,    // alive
-            Err(_) => -1,
+    ffi_call("pty_child_is_alive", std::ptr::null_mut(), || unsafe {
+        match handle_mut(child)?.try_wait()? {
+            Some(_) => Ok(0), // not alive
+            None => Ok(1),    // alive
         }
-    }));
-    result.unwrap_or(-1)
+    })
 }
 
 /// Try to wait for the child process to exit (non-blocking).
 ///
+/// Returns 0 with the child's exit code in `exit_code_out` once it has exited, the same
+/// code `pty_child_wait` reports, or 1 while it is still running.
+///
 /// # Safety
 ///
 /// Caller must ensure:
@@ -545,25 +1816,48 @@ pub unsafe extern "C" fn pty_child_try_wait(
     signal_out: *mut i32,
     out_err_msg: *mut *mut libc::c_char,
 ) -> i32 {
-    if child.is_null() || exit_code_out.is_null() || signal_out.is_null() {
-        return -1;
-    }
-    let result = catch_unwind(AssertUnwindSafe(|| unsafe {
-        let child_struct = &mut *child;
-        match child_struct.inner.try_wait() {
-            Ok(Some(status)) => {
-                *exit_code_out = if status.success() { 0 } else { 1 };
+    ffi_call("pty_child_try_wait", out_err_msg, || unsafe {
+        let child_struct = handle_mut(child)?;
+        let exit_code_out = out_ref(exit_code_out)?;
+        let signal_out = out_ref(signal_out)?;
+        match child_struct.try_wait()? {
+            Some(status) => {
+                *exit_code_out = exit_code(&status);
                 *signal_out = 0;
-                0 // Exited
-            }
-            Ok(None) => 1, // Still running
-            Err(e) => {
-                let err_str = CString::new(e.to_string())
-                    .unwrap_or_else(|_| CString::new("Unknown error").unwrap());
-                *out_err_msg = err_str.into_raw();
-                -1
+                Ok(0) // Exited
             }
+            None => Ok(1), // Still running
         }
-    }));
-    result.unwrap_or(-1)
-}
\ No newline at end of file
+    })
+}
+
+/// Forget the exit status cached on the child handle.
+/// Returns 0 on success, -1 on error.
+///
+/// Once a wait has seen the child exit, the handle answers every later wait and status
+/// query from that cached status. After this call the next one asks the process again, and
+/// caches what it finds. Also forgets that `pty_child_status_ex` last saw the child
+/// stopped. Use it when whatever drives the handle starts over with a new process; a
+/// child spawned with `pty_slave_respawn` gets a handle of its own and needs no reset.
+///
+/// Valid while the child is still running, where it changes nothing, and after its
+/// status was collected by `pty_child_wait` or `pty_child_try_wait`: the process object
+/// keeps that status, so the next wait returns it again. On Unix a status collected by
+/// `pty_child_status_ex` exists nowhere else, because the child was reaped directly;
+/// after a reset the next wait on such a child fails, since there is nothing left to
+/// wait for.
+///
+/// # Safety
+///
+/// Caller must ensure:
+/// - `child` is a valid, non-null handle obtained from `pty_open_and_spawn`.
+/// - No concurrent access to the child handle.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn pty_child_reset_status(child: ChildHandle) -> i32 {
+    ffi_call("pty_child_reset_status", std::ptr::null_mut(), || unsafe {
+        let child_struct = handle_mut(child)?;
+        child_struct.status = None;
+        child_struct.stop_signal = None;
+        Ok(0)
+    })
+}
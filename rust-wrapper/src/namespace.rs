@@ -0,0 +1,221 @@
+//! Linux namespaces for builders: a lightweight sandbox entered between fork and exec.
+//!
+//! Everything the child needs is prepared in the parent, because only async-signal-safe
+//! calls are allowed after fork. A new PID namespace only applies to children of the
+//! process that creates it, so for one the child forks again: the grandchild becomes PID 1
+//! of the namespace and runs the command, while the child stays outside as its
+//! supervisor, relaying signals and the exit status.
+
+use crate::error::PtyError;
+use crate::follow::SigInfoHandler;
+use std::io;
+use std::sync::atomic::{AtomicI32, Ordering};
+
+/// `pty_builder_namespaces` flag: a new user namespace.
+pub(crate) const PTY_NS_USER: u32 = 1;
+/// `pty_builder_namespaces` flag: a new PID namespace.
+pub(crate) const PTY_NS_PID: u32 = 2;
+/// `pty_builder_namespaces` flag: a new network namespace.
+pub(crate) const PTY_NS_NET: u32 = 4;
+/// `pty_builder_namespaces` flag: a new mount namespace.
+pub(crate) const PTY_NS_MOUNT: u32 = 8;
+pub(crate) const PTY_NS_ALL: u32 = PTY_NS_USER | PTY_NS_PID | PTY_NS_NET | PTY_NS_MOUNT;
+
+/// Signals the supervisor passes on to the command.
+const FORWARDED: [libc::c_int; 6] = [
+    libc::SIGHUP,
+    libc::SIGINT,
+    libc::SIGQUIT,
+    libc::SIGTERM,
+    libc::SIGUSR1,
+    libc::SIGUSR2,
+];
+
+/// Set in the errno of failures from `Namespaces::enter`. A `pre_exec` error reaches the
+/// parent only as its raw errno, so this is how `spawn_error` tells them from the exec's.
+const ENTER_FAILED: i32 = 1 << 30;
+
+/// Pid of the command, for the supervisor's signal handler.
+static COMMAND_PID: AtomicI32 = AtomicI32::new(0);
+
+/// The namespaces a child enters, ready to use after fork.
+pub(crate) struct Namespaces {
+    flags: libc::c_int,
+    user: bool,
+    pid: bool,
+    mount: bool,
+    /// Contents of `uid_map` and `gid_map`: the caller's ids mapped to themselves.
+    uid_map: Vec<u8>,
+    gid_map: Vec<u8>,
+}
+
+impl Namespaces {
+    /// Prepares the namespaces selected by `PTY_NS_*` flags; `None` if there are none.
+    pub(crate) fn new(selected: u32) -> Option<Self> {
+        if selected == 0 {
+            return None;
+        }
+        let mut flags = 0;
+        for (flag, clone_flag) in [
+            (PTY_NS_USER, libc::CLONE_NEWUSER),
+            (PTY_NS_PID, libc::CLONE_NEWPID),
+            (PTY_NS_NET, libc::CLONE_NEWNET),
+            (PTY_NS_MOUNT, libc::CLONE_NEWNS),
+        ] {
+            if selected & flag != 0 {
+                flags |= clone_flag;
+            }
+        }
+        let (uid, gid) = unsafe { (libc::getuid(), libc::getgid()) };
+        Some(Namespaces {
+            flags,
+            user: selected & PTY_NS_USER != 0,
+            pid: selected & PTY_NS_PID != 0,
+            mount: selected & PTY_NS_MOUNT != 0,
+            uid_map: format!("{uid} {uid} 1").into_bytes(),
+            gid_map: format!("{gid} {gid} 1").into_bytes(),
+        })
+    }
+
+    /// Moves the calling process, a freshly forked child, into the namespaces. With a PID
+    /// namespace this only returns in the grandchild; the child supervises it until it
+    /// exits. Errors are tagged with `ENTER_FAILED`.
+    pub(crate) fn enter(&self) -> io::Result<()> {
+        self.try_enter().map_err(|e| {
+            io::Error::from_raw_os_error(e.raw_os_error().unwrap_or(libc::EINVAL) | ENTER_FAILED)
+        })
+    }
+
+    fn try_enter(&self) -> io::Result<()> {
+        unsafe {
+            if libc::unshare(self.flags) == -1 {
+                return Err(io::Error::last_os_error());
+            }
+            if self.user {
+                // Writing gid_map unprivileged requires setgroups to be denied first.
+                match write_proc(c"/proc/self/setgroups", b"deny") {
+                    Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(e),
+                    _ => {}
+                }
+                write_proc(c"/proc/self/uid_map", &self.uid_map)?;
+                write_proc(c"/proc/self/gid_map", &self.gid_map)?;
+            }
+            // Keep mounts made inside from propagating to the parent's namespace.
+            if self.mount
+                && libc::mount(
+                    std::ptr::null(),
+                    c"/".as_ptr(),
+                    std::ptr::null(),
+                    libc::MS_REC | libc::MS_PRIVATE,
+                    std::ptr::null(),
+                ) == -1
+            {
+                return Err(io::Error::last_os_error());
+            }
+            if !self.pid {
+                return Ok(());
+            }
+            match libc::fork() {
+                -1 => Err(io::Error::last_os_error()),
+                0 => {
+                    libc::prctl(libc::PR_SET_PDEATHSIG, libc::SIGKILL);
+                    if self.mount
+                        && libc::mount(
+                            c"proc".as_ptr(),
+                            c"/proc".as_ptr(),
+                            c"proc".as_ptr(),
+                            libc::MS_NOSUID | libc::MS_NODEV | libc::MS_NOEXEC,
+                            std::ptr::null(),
+                        ) == -1
+                    {
+                        return Err(io::Error::last_os_error());
+                    }
+                    Ok(())
+                }
+                command => supervise(command),
+            }
+        }
+    }
+}
+
+/// Writes `data` to a file under /proc without allocating.
+unsafe fn write_proc(path: &std::ffi::CStr, data: &[u8]) -> io::Result<()> {
+    unsafe {
+        let fd = libc::open(path.as_ptr(), libc::O_WRONLY | libc::O_CLOEXEC);
+        if fd == -1 {
+            return Err(io::Error::last_os_error());
+        }
+        let written = libc::write(fd, data.as_ptr().cast(), data.len());
+        let result = if written == -1 {
+            Err(io::Error::last_os_error())
+        } else {
+            Ok(())
+        };
+        libc::close(fd);
+        result
+    }
+}
+
+/// Passes a signal sent to the supervisor on to the command. Signals the terminal
+/// generates, like Ctrl-C, reach the whole process group already and are not repeated.
+extern "C" fn forward(signo: libc::c_int, info: *mut libc::siginfo_t, _: *mut libc::c_void) {
+    if unsafe { (*info).si_code } <= 0 {
+        unsafe { libc::kill(COMMAND_PID.load(Ordering::Relaxed), signo) };
+    }
+}
+
+/// Runs in the child outside the PID namespace: relays signals to `command` and exits the
+/// way it does.
+fn supervise(command: libc::pid_t) -> ! {
+    unsafe {
+        COMMAND_PID.store(command, Ordering::Relaxed);
+        // Including the pipe std uses to report exec failures, which must close when the
+        // command execs, and the slave, which must not be held open after it exits.
+        let mut limit: libc::rlimit = std::mem::zeroed();
+        libc::getrlimit(libc::RLIMIT_NOFILE, &mut limit);
+        for fd in 0..limit.rlim_cur.min(65536) as libc::c_int {
+            libc::close(fd);
+        }
+        let mut action: libc::sigaction = std::mem::zeroed();
+        action.sa_sigaction = forward as SigInfoHandler as usize;
+        action.sa_flags = libc::SA_SIGINFO | libc::SA_RESTART;
+        libc::sigemptyset(&mut action.sa_mask);
+        for signo in FORWARDED {
+            libc::sigaction(signo, &action, std::ptr::null_mut());
+        }
+        let mut status = 0;
+        while libc::waitpid(command, &mut status, 0) == -1 {
+            if io::Error::last_os_error().kind() != io::ErrorKind::Interrupted {
+                libc::_exit(1);
+            }
+        }
+        if libc::WIFSIGNALED(status) {
+            let signo = libc::WTERMSIG(status);
+            libc::signal(signo, libc::SIG_DFL);
+            let mut set: libc::sigset_t = std::mem::zeroed();
+            libc::sigaddset(&mut set, signo);
+            libc::sigprocmask(libc::SIG_UNBLOCK, &set, std::ptr::null_mut());
+            libc::raise(signo);
+            libc::_exit(128 + signo);
+        }
+        libc::_exit(libc::WEXITSTATUS(status))
+    }
+}
+
+/// Explains a spawn that failed while namespaces were requested, if entering them is what
+/// failed; the errno alone does not say which step refused.
+pub(crate) fn spawn_error(e: io::Error) -> PtyError {
+    let Some(code) = e.raw_os_error().filter(|code| code & ENTER_FAILED != 0) else {
+        return e.into();
+    };
+    let e = io::Error::from_raw_os_error(code & !ENTER_FAILED);
+    match code & !ENTER_FAILED {
+        libc::EPERM | libc::EACCES | libc::ENOSPC | libc::EUSERS | libc::EINVAL => {
+            PtyError::new(format!(
+                "Unable to create the requested namespaces: {e}. Without CAP_SYS_ADMIN, \
+                 include PTY_NS_USER; the system must also allow unprivileged user namespaces"
+            ))
+        }
+        _ => PtyError::new(format!("Unable to create the requested namespaces: {e}")),
+    }
+}
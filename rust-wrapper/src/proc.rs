@@ -0,0 +1,456 @@
+//! Introspection of children and of the system's PTY pool. Usage statistics are read from
+//! procfs on Linux.
+
+use crate::error::{PtyError, PtyResult, ffi_call};
+use crate::handle::handle_mut;
+#[cfg(target_os = "linux")]
+use crate::copy_str_out;
+use crate::{Child, ChildHandle, out_ref};
+
+/// Snapshot of a child's `/proc/<pid>/stat` entry.
+#[repr(C)]
+#[derive(Clone, Copy, Default)]
+pub struct PtyProcStats {
+    /// State letter as in `ps`: b'R' running, b'S' sleeping, b'Z' zombie, b'T' stopped, ...
+    pub state: u8,
+    /// Resident set size in KiB.
+    pub rss_kb: u64,
+    /// CPU time spent in user mode, in milliseconds.
+    pub utime_ms: u64,
+    /// CPU time spent in kernel mode, in milliseconds.
+    pub stime_ms: u64,
+}
+
+/// `pty_child_status_ex` state: the child is running.
+const PTY_CHILD_RUNNING: i32 = 0;
+/// `pty_child_status_ex` state: the child exited normally.
+const PTY_CHILD_EXITED: i32 = 1;
+/// `pty_child_status_ex` state: the child was killed by a signal.
+const PTY_CHILD_SIGNALED: i32 = 2;
+/// `pty_child_status_ex` state: the child is stopped by a signal.
+const PTY_CHILD_STOPPED: i32 = 3;
+/// `pty_child_status_ex` state: the child was resumed since the previous call.
+const PTY_CHILD_CONTINUED: i32 = 4;
+
+/// State, exit code and signal number reported for a finished child.
+fn finished_state(status: &portable_pty::ExitStatus) -> (i32, i32, i32) {
+    match status.signal() {
+        Some(name) => (PTY_CHILD_SIGNALED, 0, signal_number(name)),
+        None => (PTY_CHILD_EXITED, crate::exit_code(status), 0),
+    }
+}
+
+/// Recovers the signal number from the description portable-pty stores, which it takes
+/// from `strsignal`. Returns 0 if no signal matches.
+fn signal_number(name: &str) -> i32 {
+    #[cfg(unix)]
+    for signo in 1..=64 {
+        let description = unsafe { libc::strsignal(signo) };
+        if !description.is_null()
+            && unsafe { std::ffi::CStr::from_ptr(description) }.to_string_lossy() == name
+        {
+            return signo;
+        }
+    }
+    name.strip_prefix("Signal ")
+        .and_then(|n| n.parse().ok())
+        .unwrap_or(0)
+}
+
+/// Checks the child without blocking, including whether it is stopped or was resumed.
+#[cfg(unix)]
+fn child_state(child: &mut Child) -> PtyResult<(i32, i32, i32)> {
+    use std::os::unix::process::ExitStatusExt;
+
+    if let Some(status) = &child.status {
+        return Ok(finished_state(status));
+    }
+    let pid = child.pid()? as libc::pid_t;
+    let mut raw = 0;
+    let flags = libc::WNOHANG | libc::WUNTRACED | libc::WCONTINUED;
+    let reaped = loop {
+        match unsafe { libc::waitpid(pid, &mut raw, flags) } {
+            -1 if std::io::Error::last_os_error().kind() == std::io::ErrorKind::Interrupted => {}
+            -1 => return Err(std::io::Error::last_os_error().into()),
+            n => break n,
+        }
+    };
+    if reaped == 0 {
+        return Ok(match child.stop_signal {
+            Some(signo) => (PTY_CHILD_STOPPED, 0, signo),
+            None => (PTY_CHILD_RUNNING, 0, 0),
+        });
+    }
+    if libc::WIFSTOPPED(raw) {
+        let signo = libc::WSTOPSIG(raw);
+        child.stop_signal = Some(signo);
+        return Ok((PTY_CHILD_STOPPED, 0, signo));
+    }
+    child.stop_signal = None;
+    if libc::WIFCONTINUED(raw) {
+        return Ok((PTY_CHILD_CONTINUED, 0, 0));
+    }
+    // The child is reaped now, so later waits must be answered from the cached status.
+    let status = portable_pty::ExitStatus::from(std::process::ExitStatus::from_raw(raw));
+    let state = finished_state(&status);
+    child.status = Some(status);
+    Ok(state)
+}
+
+/// Checks the child without blocking.
+#[cfg(not(unix))]
+fn child_state(child: &mut Child) -> PtyResult<(i32, i32, i32)> {
+    Ok(match child.try_wait()? {
+        Some(status) => finished_state(&status),
+        None => (PTY_CHILD_RUNNING, 0, 0),
+    })
+}
+
+/// Get the child's state without blocking, telling stopped and resumed children apart from running ones.
+/// Returns 0 on success, -1 on error; sets out_err_msg (caller frees via pty_free_err_msg).
+///
+/// `out_state` receives 0 = running, 1 = exited, 2 = killed by a signal, 3 = stopped,
+/// 4 = resumed since the previous call. `out_code` receives the exit code for state 1,
+/// and `out_signal` the signal number for states 2 and 3; both are 0 otherwise. A stopped
+/// child keeps reporting 3 until it is resumed, which is reported once as 4 and then as
+/// running again. Stops and resumes are only noticed by this function, so call it rather
+/// than `pty_child_try_wait` to track them. On Windows, where processes are not stopped
+/// by signals, only states 0 and 1 occur.
+///
+/// # Safety
+///
+/// Caller must ensure:
+/// - `child` is a valid, non-null handle obtained from `pty_open_and_spawn`.
+/// - `out_state`, `out_code`, `out_signal`, and `out_err_msg` are valid, non-null pointers to mutable memory.
+/// - The caller must free any error message using `pty_free_err_msg`.
+/// - No concurrent access to the child handle.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn pty_child_status_ex(
+    child: ChildHandle,
+    out_state: *mut i32,
+    out_code: *mut i32,
+    out_signal: *mut i32,
+    out_err_msg: *mut *mut libc::c_char,
+) -> i32 {
+    ffi_call("pty_child_status_ex", out_err_msg, || unsafe {
+        let child_struct = handle_mut(child)?;
+        let out_state = out_ref(out_state)?;
+        let out_code = out_ref(out_code)?;
+        let out_signal = out_ref(out_signal)?;
+        (*out_state, *out_code, *out_signal) = child_state(child_struct)?;
+        Ok(0)
+    })
+}
+
+/// Splits the contents of `/proc/<pid>/stat` into the fields after the command name.
+#[cfg(target_os = "linux")]
+fn stat_fields(stat: &str) -> PtyResult<Vec<&str>> {
+    // The command name is parenthesized and may itself contain spaces or parentheses, so
+    // the remaining fields start after the last ')'.
+    let rest = stat
+        .rfind(')')
+        .map(|end| &stat[end + 1..])
+        .ok_or("Malformed /proc stat entry")?;
+    Ok(rest.split_whitespace().collect())
+}
+
+/// Reads `/proc/<pid>/stat`. A process that no longer exists, reaped children included,
+/// is reported as not found.
+#[cfg(target_os = "linux")]
+fn read_stat(pid: u32) -> PtyResult<String> {
+    std::fs::read_to_string(format!("/proc/{pid}/stat")).map_err(|e| {
+        let message = format!("Unable to read /proc/{pid}/stat: {e}");
+        if e.kind() == std::io::ErrorKind::NotFound {
+            PtyError::not_found(message)
+        } else {
+            PtyError::new(message)
+        }
+    })
+}
+
+/// Parses the contents of `/proc/<pid>/stat`.
+#[cfg(target_os = "linux")]
+fn parse_stat(stat: &str) -> PtyResult<PtyProcStats> {
+    let fields = stat_fields(stat)?;
+    // Index 0 is field 3 (state) in proc(5) numbering.
+    let field = |n: usize| -> PtyResult<u64> {
+        fields
+            .get(n - 3)
+            .and_then(|f| f.parse().ok())
+            .ok_or_else(|| PtyError::new(format!("Malformed /proc stat field {n}")))
+    };
+    let ticks_per_sec = match unsafe { libc::sysconf(libc::_SC_CLK_TCK) } {
+        n if n > 0 => n as u64,
+        _ => 100,
+    };
+    let page_kb = match unsafe { libc::sysconf(libc::_SC_PAGESIZE) } {
+        n if n > 0 => n as u64 / 1024,
+        _ => 4,
+    };
+    Ok(PtyProcStats {
+        state: fields
+            .first()
+            .and_then(|s| s.bytes().next())
+            .unwrap_or(b'?'),
+        utime_ms: field(14)? * 1000 / ticks_per_sec,
+        stime_ms: field(15)? * 1000 / ticks_per_sec,
+        rss_kb: field(24)? * page_kb,
+    })
+}
+
+/// Read the child's state, memory and CPU usage from `/proc/<pid>/stat` (Linux only).
+/// Returns 0 on success, -1 on error, -2 on other platforms, -3 if the child is gone; sets out_err_msg (caller frees via pty_free_err_msg).
+///
+/// Fails once the child has been reaped; an exited child that has not been waited for
+/// yet reports state 'Z'.
+///
+/// # Safety
+///
+/// Caller must ensure:
+/// - `child` is a valid, non-null handle obtained from `pty_open_and_spawn`.
+/// - `out` and `out_err_msg` are valid, non-null pointers to mutable memory.
+/// - The caller must free any error message using `pty_free_err_msg`.
+/// - No concurrent access to the child handle.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn pty_child_proc_stats(
+    child: ChildHandle,
+    out: *mut PtyProcStats,
+    out_err_msg: *mut *mut libc::c_char,
+) -> i32 {
+    ffi_call("pty_child_proc_stats", out_err_msg, || unsafe {
+        let child_struct = handle_mut(child)?;
+        let out = out_ref(out)?;
+        #[cfg(target_os = "linux")]
+        {
+            *out = parse_stat(&read_stat(child_struct.pid()?)?)?;
+            Ok(0)
+        }
+        #[cfg(not(target_os = "linux"))]
+        {
+            let _ = (child_struct, out);
+            Err(PtyError::unsupported("pty_child_proc_stats"))
+        }
+    })
+}
+
+/// Milliseconds `clock` shows.
+#[cfg(target_os = "linux")]
+fn clock_millis(clock: libc::clockid_t) -> PtyResult<u64> {
+    let mut ts = libc::timespec {
+        tv_sec: 0,
+        tv_nsec: 0,
+    };
+    if unsafe { libc::clock_gettime(clock, &mut ts) } == -1 {
+        return Err(std::io::Error::last_os_error().into());
+    }
+    Ok(ts.tv_sec as u64 * 1000 + ts.tv_nsec as u64 / 1_000_000)
+}
+
+/// Get the time the child process started, in milliseconds since the Unix epoch (Linux only).
+/// Returns 0 on success, -1 on error, -2 on other platforms, -3 if the child is gone; sets out_err_msg (caller frees via pty_free_err_msg).
+///
+/// The kernel's record of when the process was created, read from the `starttime` field
+/// of `/proc/<pid>/stat`, so it stays accurate whenever the handle was made and after
+/// `pty_child_detach`; subtract it from the current time for the uptime. The field counts
+/// clock ticks since boot, usually 10 ms each, and is converted with the boot time,
+/// derived from the system clock. Setting the system clock afterwards shifts the result
+/// by as much. A child that `exec`s keeps its start time. Fails once the child has been
+/// reaped.
+///
+/// # Safety
+///
+/// Caller must ensure:
+/// - `child` is a valid, non-null handle obtained from `pty_open_and_spawn`.
+/// - `out_unix_millis` and `out_err_msg` are valid, non-null pointers to mutable memory.
+/// - The caller must free any error message using `pty_free_err_msg`.
+/// - No concurrent access to the child handle.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn pty_child_start_time(
+    child: ChildHandle,
+    out_unix_millis: *mut u64,
+    out_err_msg: *mut *mut libc::c_char,
+) -> i32 {
+    ffi_call("pty_child_start_time", out_err_msg, || unsafe {
+        let child_struct = handle_mut(child)?;
+        let out_unix_millis = out_ref(out_unix_millis)?;
+        #[cfg(target_os = "linux")]
+        {
+            let stat = read_stat(child_struct.pid()?)?;
+            // Field 22 in proc(5) numbering.
+            let start_ticks: u64 = stat_fields(&stat)?
+                .get(19)
+                .and_then(|f| f.parse().ok())
+                .ok_or("Malformed /proc stat field 22")?;
+            let ticks_per_sec = match libc::sysconf(libc::_SC_CLK_TCK) {
+                n if n > 0 => n as u64,
+                _ => 100,
+            };
+            // Ticks count from boot, including time spent suspended, like CLOCK_BOOTTIME.
+            let boot = clock_millis(libc::CLOCK_REALTIME)?
+                .saturating_sub(clock_millis(libc::CLOCK_BOOTTIME)?);
+            *out_unix_millis = boot + start_ticks * 1000 / ticks_per_sec;
+            Ok(0)
+        }
+        #[cfg(not(target_os = "linux"))]
+        {
+            let _ = (child_struct, out_unix_millis);
+            Err(PtyError::unsupported("pty_child_start_time"))
+        }
+    })
+}
+
+/// Check whether the child has the PTY as its controlling terminal (Linux only).
+/// Returns 0 on success, -1 on error, -2 on other platforms, -3 if the child is gone, -4 if the child has exited; sets out_err_msg (caller frees via pty_free_err_msg).
+///
+/// Job control depends on it: without the PTY as controlling terminal, Ctrl-C, Ctrl-Z and
+/// resizes send the program no signals, `/dev/tty` cannot be opened and shells report
+/// "no job control". Compares the `tty_nr` field of `/proc/<pid>/stat` with the device
+/// number of the PTY the child was spawned on, and stores 1 in `out_has` if they match,
+/// 0 if the child has another controlling terminal or none. Acquiring it can fail
+/// silently, e.g. for a child spawned without one or one that called `setsid` itself,
+/// and this tells those setups apart. The child must still be running: once it has
+/// exited there is no controlling terminal left to check.
+///
+/// # Safety
+///
+/// Caller must ensure:
+/// - `child` is a valid, non-null handle obtained from `pty_open_and_spawn`.
+/// - `out_has` and `out_err_msg` are valid, non-null pointers to mutable memory.
+/// - The caller must free any error message using `pty_free_err_msg`.
+/// - No concurrent access to the child handle.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn pty_child_has_ctty(
+    child: ChildHandle,
+    out_has: *mut i32,
+    out_err_msg: *mut *mut libc::c_char,
+) -> i32 {
+    ffi_call("pty_child_has_ctty", out_err_msg, || unsafe {
+        let child_struct = handle_mut(child)?;
+        let out_has = out_ref(out_has)?;
+        #[cfg(target_os = "linux")]
+        {
+            if child_struct.try_wait()?.is_some() {
+                return Err(PtyError::child_gone());
+            }
+            let tty_dev = child_struct
+                .tty_dev
+                .ok_or("The device of the PTY the child runs on is unknown")?;
+            let stat = read_stat(child_struct.pid()?)?;
+            // Field 7 in proc(5) numbering, encoded with the minor number split around
+            // the major.
+            let tty_nr: u32 = stat_fields(&stat)?
+                .get(4)
+                .and_then(|f| f.parse().ok())
+                .ok_or("Malformed /proc stat field 7")?;
+            let major = (tty_nr >> 8) & 0xfff;
+            let minor = (tty_nr & 0xff) | ((tty_nr >> 12) & 0xfff00);
+            *out_has = i32::from(
+                tty_nr != 0 && major == libc::major(tty_dev) && minor == libc::minor(tty_dev),
+            );
+            Ok(0)
+        }
+        #[cfg(not(target_os = "linux"))]
+        {
+            let _ = (child_struct, out_has);
+            Err(PtyError::unsupported("pty_child_has_ctty"))
+        }
+    })
+}
+
+/// Get the path of the executable the child is running, from `/proc/<pid>/exe` (Linux only).
+/// Returns 0 on success, -1 on error, -2 on other platforms, -3 if the child is gone; sets out_err_msg (caller frees via pty_free_err_msg).
+///
+/// This is the binary the kernel actually loaded, after PATH lookup, symlinks and any
+/// `exec` the program did since, so it can differ from the requested command (a shell
+/// that `exec`s a program reports the program). The kernel appends " (deleted)" if the file
+/// was removed or replaced after it started. Copied into `buf` with a terminating NUL;
+/// `out_len` receives the length even if `buf_len` is too small, so the caller can retry
+/// with `out_len + 1` bytes. Fails once the child has been reaped.
+///
+/// # Safety
+///
+/// Caller must ensure:
+/// - `child` is a valid, non-null handle obtained from `pty_open_and_spawn`.
+/// - `buf` is a valid, non-null pointer to mutable memory of at least `buf_len` bytes.
+/// - `out_len` and `out_err_msg` are valid, non-null pointers to mutable memory.
+/// - The caller must free any error message using `pty_free_err_msg`.
+/// - No concurrent access to the child handle.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn pty_child_exe_path(
+    child: ChildHandle,
+    buf: *mut u8,
+    buf_len: usize,
+    out_len: *mut usize,
+    out_err_msg: *mut *mut libc::c_char,
+) -> i32 {
+    ffi_call("pty_child_exe_path", out_err_msg, || unsafe {
+        let child_struct = handle_mut(child)?;
+        #[cfg(target_os = "linux")]
+        {
+            let pid = child_struct.pid()?;
+            let path = std::fs::read_link(format!("/proc/{pid}/exe")).map_err(|e| {
+                let message = format!("Unable to read /proc/{pid}/exe: {e}");
+                if e.kind() == std::io::ErrorKind::NotFound {
+                    PtyError::not_found(message)
+                } else {
+                    PtyError::new(message)
+                }
+            })?;
+            copy_str_out(&path.to_string_lossy(), buf, buf_len, out_len)?;
+            Ok(0)
+        }
+        #[cfg(not(target_os = "linux"))]
+        {
+            let _ = (child_struct, buf, buf_len, out_len);
+            Err(PtyError::unsupported("pty_child_exe_path"))
+        }
+    })
+}
+
+/// Reads a single number from a procfs file.
+#[cfg(target_os = "linux")]
+fn read_proc_number(path: &str) -> PtyResult<u64> {
+    let text = std::fs::read_to_string(path)
+        .map_err(|e| PtyError::new(format!("Unable to read {path}: {e}")))?;
+    text.trim()
+        .parse()
+        .map_err(|_| PtyError::new(format!("Unexpected contents in {path}: {:?}", text.trim())))
+}
+
+/// Report how many more PTYs the system can allocate (Linux only).
+/// Returns 0 on success, -1 on error, -2 on other platforms; sets out_err_msg (caller frees via pty_free_err_msg).
+///
+/// `out_available` receives the number of PTYs that can still be opened and `out_max` the
+/// system-wide limit, from `/proc/sys/kernel/pty/nr` and `/proc/sys/kernel/pty/max`.
+/// Opening fails with ENOSPC once `out_available` reaches 0. Other processes allocate
+/// from the same pool, so the count can change at any moment.
+///
+/// # Safety
+///
+/// Caller must ensure:
+/// - `out_available`, `out_max`, and `out_err_msg` are valid, non-null pointers to mutable memory.
+/// - The caller must free any error message using `pty_free_err_msg`.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn pty_available_ptys(
+    out_available: *mut u64,
+    out_max: *mut u64,
+    out_err_msg: *mut *mut libc::c_char,
+) -> i32 {
+    ffi_call("pty_available_ptys", out_err_msg, || unsafe {
+        let out_available = out_ref(out_available)?;
+        let out_max = out_ref(out_max)?;
+        #[cfg(target_os = "linux")]
+        {
+            let open = read_proc_number("/proc/sys/kernel/pty/nr")?;
+            let max = read_proc_number("/proc/sys/kernel/pty/max")?;
+            *out_available = max.saturating_sub(open);
+            *out_max = max;
+            Ok(0)
+        }
+        #[cfg(not(target_os = "linux"))]
+        {
+            let _ = (out_available, out_max);
+            Err(PtyError::unsupported("pty_available_ptys"))
+        }
+    })
+}
@@ -0,0 +1,189 @@
+//! The queues between the master and the program: waiting for written data to go through
+//! and discarding data nobody has read yet.
+
+#[cfg(unix)]
+use crate::Master;
+#[cfg(unix)]
+use crate::error::PtyResult;
+use crate::error::{PtyError, ffi_call};
+use crate::handle::handle_mut;
+use crate::{MasterHandle, WriterHandle, out_ref};
+#[cfg(unix)]
+use std::io;
+#[cfg(unix)]
+use std::os::fd::AsRawFd;
+
+/// `pty_flush_queues` queue: input the program has not read yet (`TCIFLUSH`).
+const PTY_FLUSH_INPUT: i32 = 0;
+/// `pty_flush_queues` queue: output the caller has not read yet (`TCOFLUSH`).
+const PTY_FLUSH_OUTPUT: i32 = 1;
+/// `pty_flush_queues` queue: both (`TCIOFLUSH`).
+const PTY_FLUSH_BOTH: i32 = 2;
+
+/// Discards the program's unread input. That queue belongs to the slave, so it is flushed
+/// through a slave fd opened for the purpose; `tcflush` on the master does not reach it.
+#[cfg(unix)]
+fn flush_input(master: &Master) -> PtyResult<()> {
+    let slave = crate::fd::open_slave(&*master.inner)?;
+    if unsafe { libc::tcflush(slave.as_raw_fd(), libc::TCIFLUSH) } == -1 {
+        return Err(io::Error::last_os_error().into());
+    }
+    Ok(())
+}
+
+/// Discards output the program wrote that has not been read from the master yet.
+#[cfg(unix)]
+fn flush_output(master: &Master) -> PtyResult<()> {
+    if unsafe { libc::tcflush(master.fd()?, libc::TCIFLUSH) } == -1 {
+        return Err(io::Error::last_os_error().into());
+    }
+    Ok(())
+}
+
+/// Wait until input written to the PTY has been transmitted (Unix only).
+/// Returns 0 on success, -1 on error, -2 on other platforms; sets out_err_msg (caller frees via pty_free_err_msg).
+///
+/// Calls `tcdrain` on the master, giving a synchronization point between a write and what
+/// follows it, such as a `pty_resize` or a signal that must not overtake the input. For a
+/// PTY, "transmitted" means handed to the terminal's input queue, where the line discipline
+/// processes it and the program can read it. It does not mean the program has read it;
+/// wait for its output (e.g. with `pty_write_expect`) for that. On Linux writes to the
+/// master reach that queue before `pty_write` returns, so this returns at once. Works on
+/// the master because writers have no file descriptor of their own.
+///
+/// # Safety
+///
+/// Caller must ensure:
+/// - `master` is a valid, non-null handle obtained from `pty_open_and_spawn` or `pty_open`.
+/// - `out_err_msg` is a valid, non-null pointer to mutable memory.
+/// - The caller must free any error message using `pty_free_err_msg`.
+/// - No concurrent mutation or invalidation of the master handle.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn pty_drain(
+    master: MasterHandle,
+    out_err_msg: *mut *mut libc::c_char,
+) -> i32 {
+    ffi_call("pty_drain", out_err_msg, || unsafe {
+        let master_struct = handle_mut(master)?;
+        #[cfg(unix)]
+        {
+            let fd = master_struct.fd()?;
+            while libc::tcdrain(fd) == -1 {
+                let err = io::Error::last_os_error();
+                if err.kind() != io::ErrorKind::Interrupted {
+                    return Err(err.into());
+                }
+            }
+            Ok(0)
+        }
+        #[cfg(not(unix))]
+        {
+            let _ = master_struct;
+            Err(PtyError::unsupported("pty_drain"))
+        }
+    })
+}
+
+/// Check whether writing to the PTY right now would block, without writing (Unix only).
+/// Returns 0 on success, -1 on error, -2 on other platforms; sets out_err_msg (caller frees via pty_free_err_msg).
+///
+/// Polls the master for writability with a zero timeout and stores 1 in `out_would_block`
+/// if the program's input queue is full, because it is not reading, and 0 if a write
+/// would go through at once. Lets a caller queue input for a slow program instead of
+/// blocking in `pty_write`. The answer is only a snapshot, and a write larger than the
+/// free space still blocks partway. When the PTY is closed the answer is 0: the write
+/// fails at once instead of blocking.
+///
+/// # Safety
+///
+/// Caller must ensure:
+/// - `writer` is a valid, non-null handle obtained from `pty_get_writer`.
+/// - `out_would_block` and `out_err_msg` are valid, non-null pointers to mutable memory.
+/// - The caller must free any error message using `pty_free_err_msg`.
+/// - No concurrent mutation or invalidation of the writer handle.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn pty_writer_would_block(
+    writer: WriterHandle,
+    out_would_block: *mut i32,
+    out_err_msg: *mut *mut libc::c_char,
+) -> i32 {
+    ffi_call("pty_writer_would_block", out_err_msg, || unsafe {
+        let writer_struct = handle_mut(writer)?;
+        let out_would_block = out_ref(out_would_block)?;
+        #[cfg(unix)]
+        {
+            let fd = writer_struct
+                .poll_fd
+                .as_ref()
+                .ok_or("The writer has no file descriptor")?;
+            let mut pfd = libc::pollfd {
+                fd: fd.as_raw_fd(),
+                events: libc::POLLOUT,
+                revents: 0,
+            };
+            let ready = loop {
+                match libc::poll(&mut pfd, 1, 0) {
+                    -1 if io::Error::last_os_error().kind() == io::ErrorKind::Interrupted => {}
+                    -1 => return Err(io::Error::last_os_error().into()),
+                    n => break n > 0,
+                }
+            };
+            *out_would_block = i32::from(!ready);
+            Ok(0)
+        }
+        #[cfg(not(unix))]
+        {
+            let _ = (writer_struct, out_would_block);
+            Err(PtyError::unsupported("pty_writer_would_block"))
+        }
+    })
+}
+
+/// Discard data queued in the PTY that has not been read yet (Unix only).
+/// Returns 0 on success, -1 on error, -2 on other platforms; sets out_err_msg (caller frees via pty_free_err_msg).
+///
+/// `which` names the queues as the program sees them, like `tcflush`: `PTY_FLUSH_INPUT` (0)
+/// drops input written to the PTY that the program has not read, `PTY_FLUSH_OUTPUT` (1)
+/// drops output the program wrote that has not been read from the master, and
+/// `PTY_FLUSH_BOTH` (2) does both. Use it to abandon stale data after an error instead of
+/// processing it. Output a reader already pulled off the PTY is not affected; discard that
+/// with `pty_reader_flush_input`. Data written after the call is kept.
+///
+/// # Safety
+///
+/// Caller must ensure:
+/// - `master` is a valid, non-null handle obtained from `pty_open_and_spawn` or `pty_open`.
+/// - `out_err_msg` is a valid, non-null pointer to mutable memory.
+/// - The caller must free any error message using `pty_free_err_msg`.
+/// - No concurrent mutation or invalidation of the master handle.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn pty_flush_queues(
+    master: MasterHandle,
+    which: i32,
+    out_err_msg: *mut *mut libc::c_char,
+) -> i32 {
+    ffi_call("pty_flush_queues", out_err_msg, || unsafe {
+        let master_struct = handle_mut(master)?;
+        let (input, output) = match which {
+            PTY_FLUSH_INPUT => (true, false),
+            PTY_FLUSH_OUTPUT => (false, true),
+            PTY_FLUSH_BOTH => (true, true),
+            _ => return Err(PtyError::new(format!("Unknown queue selector: {which}"))),
+        };
+        #[cfg(unix)]
+        {
+            if input {
+                flush_input(master_struct)?;
+            }
+            if output {
+                flush_output(master_struct)?;
+            }
+            Ok(0)
+        }
+        #[cfg(not(unix))]
+        {
+            let _ = (master_struct, input, output);
+            Err(PtyError::unsupported("pty_flush_queues"))
+        }
+    })
+}
@@ -0,0 +1,2060 @@
+//! Buffered reader behind `ReaderHandle`.
+//!
+//! Plain reads go straight to the PTY. The first read that needs a deadline hands the
+//! underlying reader to a background pump thread, which copies output into a bounded
+//! queue; from then on every read (timed or not) is served from that queue. Pipes on
+//! Windows cannot be polled, so this keeps timeouts working the same on every platform.
+//!
+//! A builder with an output log starts the pump at spawn instead, owned by the master, and
+//! the pump copies everything it reads to the log. Readers of such a master share the
+//! master's pump rather than starting their own.
+//!
+//! A tap (`pty_reader_tap`) is a reader served by a pump without a thread: the reader it
+//! was made from copies its output into the tap's queue as it takes it from the PTY.
+//!
+//! On a PTY opened non-blocking (`pty_open_ex`) the reader waits for output with `poll`
+//! itself, so everything built on it keeps blocking; only `pty_read` and
+//! `pty_read_clearing` return `PTY_ERR_WOULD_BLOCK` when there is nothing to read.
+
+use crate::error::{PtyError, PtyResult, ffi_call};
+use crate::expect::find_after;
+use crate::handle::{handle_mut, into_handle};
+use crate::session::{OscTracker, PtyOscCallback, Session};
+use crate::{ReaderHandle, buf_mut, copy_str_out, out_ref, str_from_ptr};
+use encoding_rs::{Decoder, DecoderResult, Encoding, UTF_8};
+use regex::bytes::Regex;
+use std::collections::VecDeque;
+use std::fs::File;
+use std::io::{self, Read, Write};
+#[cfg(unix)]
+use std::os::fd::{AsRawFd, OwnedFd};
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+/// Bytes the pump thread buffers before it stops reading and lets the PTY apply backpressure.
+const PUMP_CAPACITY: usize = 64 * 1024;
+/// Pump capacity for readers of a PTY opened with `large_buffers`.
+pub(crate) const LARGE_PUMP_CAPACITY: usize = 4 * 1024 * 1024;
+const CHUNK_SIZE: usize = 4096;
+/// Bytes before the data in a `pty_reader_set_framed_log` record: timestamp and length.
+const FRAME_HEADER_SIZE: usize = 12;
+/// How long a discard waits for the pump to pick up output the PTY reports as ready.
+const DISCARD_GRACE: Duration = Duration::from_millis(20);
+
+// The first three double as `pty_read_min`, `pty_peek_line_len` and `pty_read_idle_framed`
+// return codes, where "filled" means `min_len` bytes were read, a line is available, or
+// the output went quiet.
+
+/// `pty_read_bounded` reason: the buffer was filled.
+const PTY_READ_FILLED: i32 = 0;
+/// `pty_read_bounded` reason: the timeout elapsed first.
+const PTY_READ_TIMEOUT: i32 = 1;
+/// `pty_read_bounded` reason: the PTY reached end of file.
+const PTY_READ_EOF: i32 = 2;
+/// `pty_read_bounded` reason: reading failed.
+const PTY_READ_ERROR: i32 = 3;
+
+/// `pty_read_until_prompt` result: the prompt was seen.
+const PTY_PROMPT_SEEN: i32 = 0;
+/// `pty_read_until_prompt` result: the timeout elapsed first.
+const PTY_PROMPT_TIMEOUT: i32 = 1;
+
+/// `pty_read_until_match` result: the pattern matched.
+const PTY_MATCH_SEEN: i32 = 0;
+/// `pty_read_until_match` result: the timeout elapsed first.
+const PTY_MATCH_TIMEOUT: i32 = 1;
+
+/// `pty_reader_set_nul_policy` policy: NUL bytes are passed through.
+const PTY_NUL_PASS: i32 = 0;
+/// `pty_reader_set_nul_policy` policy: NUL bytes are removed.
+const PTY_NUL_STRIP: i32 = 1;
+/// `pty_reader_set_nul_policy` policy: NUL bytes are replaced with spaces.
+const PTY_NUL_SPACE: i32 = 2;
+
+/// `pty_reader_set_invalid_utf8_policy` policy: malformed input becomes U+FFFD.
+const PTY_UTF8_REPLACE: i32 = 0;
+/// `pty_reader_set_invalid_utf8_policy` policy: malformed input is dropped and reported.
+const PTY_UTF8_ERROR: i32 = 1;
+
+/// `pty_read_loop` status: the PTY reached end of file.
+const PTY_LOOP_EOF: i32 = 0;
+/// `pty_read_loop` status: the callback asked to stop.
+const PTY_LOOP_STOPPED: i32 = 1;
+
+/// Receives a chunk of output and returns nonzero to stop reading. `data` is only valid
+/// for the duration of the call.
+pub type PtyReadCallback =
+    extern "C" fn(data: *const u8, len: usize, user_data: *mut libc::c_void) -> i32;
+
+/// Allocates `size` bytes for `pty_read_to_end_cb`, returning null on failure.
+pub type PtyAllocCallback = extern "C" fn(size: usize, user_data: *mut libc::c_void) -> *mut u8;
+/// Resizes a block from `PtyAllocCallback` to `size` bytes, keeping its contents, like
+/// `realloc`. Returns null on failure, leaving the old block intact.
+pub type PtyReallocCallback =
+    extern "C" fn(ptr: *mut u8, size: usize, user_data: *mut libc::c_void) -> *mut u8;
+/// Frees a block from `PtyAllocCallback` or `PtyReallocCallback`.
+pub type PtyFreeCallback = extern "C" fn(ptr: *mut u8, user_data: *mut libc::c_void);
+
+pub(crate) struct Reader {
+    source: Source,
+    /// Bytes pulled from the source but not yet handed to the caller.
+    pending: VecDeque<u8>,
+    /// Set by `pty_reader_set_encoding` to transcode output to UTF-8.
+    decoder: Option<Decoder>,
+    /// Records window title and directory changes in the session.
+    osc: OscTracker,
+    /// Duplicate of the master fd, used to check for output without reading it.
+    #[cfg(unix)]
+    poll_fd: Option<OwnedFd>,
+    /// The pump belongs to the master (see `Reader::shared`) and outlives this reader.
+    shared: bool,
+    /// Set by `pty_reader_set_prompt_pattern`: text the shell's prompt ends with.
+    prompt: Option<Vec<u8>>,
+    /// Set by `pty_reader_compile_pattern`: what `pty_read_until_match` waits for.
+    pattern: Option<Regex>,
+    /// Queues of the taps made from this reader, fed everything it takes from the PTY.
+    taps: Vec<Arc<Pump>>,
+    /// Set by `pty_reader_set_nul_policy`.
+    nul_policy: i32,
+    /// Set by `pty_reader_set_invalid_utf8_policy`.
+    invalid_utf8_policy: i32,
+    /// A policy was set, so UTF-8 output is decoded and checked rather than passed through.
+    checks_utf8: bool,
+    /// Malformed sequences the decoder has met, for `pty_reader_invalid_utf8_count`.
+    invalid_utf8: u64,
+    /// Malformed sequences dropped under `PTY_UTF8_ERROR` that no read has failed for yet.
+    unreported_invalid: u64,
+    /// The PTY is in non-blocking mode: `read_ready` fails instead of waiting.
+    nonblocking: bool,
+    /// Set by `pty_reader_set_framed_log`: receives a record for every chunk of raw output.
+    framed_log: Option<File>,
+}
+
+enum Source {
+    Direct(Box<dyn Read + Send>),
+    Pumped(Arc<Pump>),
+}
+
+pub(crate) struct Pump {
+    state: Mutex<PumpState>,
+    changed: Condvar,
+}
+
+struct PumpState {
+    buf: VecDeque<u8>,
+    /// Bytes `buf` holds at most.
+    capacity: usize,
+    /// Set once the reader drains: drop the oldest output when `buf` is full instead of
+    /// waiting for the consumer.
+    lossy: bool,
+    /// Output was dropped since `pty_reader_take_since` last reported it.
+    truncated: bool,
+    eof: bool,
+    error: Option<io::Error>,
+    /// Set when the reader handle is freed so the thread stops at its next wakeup.
+    closed: bool,
+    /// Why writing the output log failed; the log is abandoned from then on.
+    log_error: Option<String>,
+    /// Set by `pty_reader_set_rate_limit`: bytes per second the thread reads at most; 0 for
+    /// no limit.
+    rate: u64,
+    /// Most bytes `buf` has held, for `pty_reader_high_water`.
+    high_water: usize,
+}
+
+impl PumpState {
+    /// Appends `data` to `buf`, dropping the oldest output if it overflows.
+    fn push(&mut self, data: &[u8]) {
+        self.buf.extend(data);
+        if self.buf.len() > self.capacity {
+            let excess = self.buf.len() - self.capacity;
+            self.buf.drain(..excess);
+            self.truncated = true;
+        }
+        self.high_water = self.high_water.max(self.buf.len());
+    }
+}
+
+/// Why a bounded read stopped.
+pub(crate) enum Stop {
+    Filled,
+    Timeout,
+    Eof,
+    Error(io::Error),
+}
+
+impl Reader {
+    /// A reader of `source` with every setting at its default.
+    fn with_source(source: Source, session: Arc<Session>) -> Self {
+        Reader {
+            source,
+            pending: VecDeque::new(),
+            decoder: None,
+            osc: OscTracker::new(session),
+            #[cfg(unix)]
+            poll_fd: None,
+            shared: false,
+            prompt: None,
+            pattern: None,
+            taps: Vec::new(),
+            nul_policy: PTY_NUL_PASS,
+            invalid_utf8_policy: PTY_UTF8_REPLACE,
+            checks_utf8: false,
+            invalid_utf8: 0,
+            unreported_invalid: 0,
+            nonblocking: false,
+            framed_log: None,
+        }
+    }
+
+    pub(crate) fn new(inner: Box<dyn Read + Send>, session: Arc<Session>) -> Self {
+        Reader::with_source(Source::Direct(inner), session)
+    }
+
+    /// A reader served by a pump the master owns, which keeps running when it is freed.
+    pub(crate) fn shared(pump: Arc<Pump>, session: Arc<Session>) -> Self {
+        let mut reader = Reader::with_source(Source::Pumped(pump), session);
+        reader.shared = true;
+        reader
+    }
+
+    /// Creates a reader that receives a copy of all output this one takes from here on.
+    pub(crate) fn tap(&mut self) -> Reader {
+        let pump = Pump::new(PUMP_CAPACITY, true);
+        self.taps.push(pump.clone());
+        let mut tap = Reader::with_source(Source::Pumped(pump), self.osc.session().clone());
+        tap.nonblocking = self.nonblocking;
+        tap
+    }
+
+    /// Lets the reader check whether the PTY has output without blocking. If the PTY is in
+    /// non-blocking mode, reads from it wait for output on `fd` instead of failing.
+    #[cfg(unix)]
+    pub(crate) fn set_poll_fd(&mut self, fd: OwnedFd) -> io::Result<()> {
+        let flags = unsafe { libc::fcntl(fd.as_raw_fd(), libc::F_GETFL) };
+        if flags == -1 {
+            return Err(io::Error::last_os_error());
+        }
+        if flags & libc::O_NONBLOCK != 0 {
+            self.nonblocking = true;
+            if let Source::Direct(inner) = &mut self.source {
+                let inner = std::mem::replace(inner, Box::new(io::empty()));
+                let fd = fd.try_clone()?;
+                self.source = Source::Direct(Box::new(WaitForOutput { inner, fd }));
+            }
+        }
+        self.poll_fd = Some(fd);
+        Ok(())
+    }
+
+    /// Whether the PTY has unread output (or hung up). `None` if that cannot be checked.
+    fn source_ready(&self) -> Option<bool> {
+        #[cfg(unix)]
+        if let Some(fd) = &self.poll_fd {
+            let mut pfd = libc::pollfd {
+                fd: fd.as_raw_fd(),
+                events: libc::POLLIN,
+                revents: 0,
+            };
+            return match unsafe { libc::poll(&mut pfd, 1, 0) } {
+                n if n > 0 => Some(pfd.revents & libc::POLLIN != 0),
+                _ => Some(false),
+            };
+        }
+        None
+    }
+
+    /// Whether a read would return without waiting: output (or EOF, or an error) is
+    /// buffered or the PTY has something to report.
+    fn output_ready(&self) -> bool {
+        if !self.pending.is_empty() {
+            return true;
+        }
+        match &self.source {
+            Source::Pumped(pump) => {
+                let state = pump.state.lock().unwrap();
+                !state.buf.is_empty() || state.eof || state.error.is_some()
+            }
+            Source::Direct(_) => {
+                #[cfg(unix)]
+                if let Some(fd) = &self.poll_fd {
+                    let mut pfd = libc::pollfd {
+                        fd: fd.as_raw_fd(),
+                        events: libc::POLLIN,
+                        revents: 0,
+                    };
+                    // Hangup counts: the read reports EOF right away.
+                    return unsafe { libc::poll(&mut pfd, 1, 0) } != 0;
+                }
+                true
+            }
+        }
+    }
+
+    /// `read` for callers that asked for non-blocking mode: fails with `WouldBlock` instead
+    /// of waiting when the PTY is non-blocking and has no output.
+    pub(crate) fn read_ready(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.nonblocking && !buf.is_empty() && !self.output_ready() {
+            return Err(io::ErrorKind::WouldBlock.into());
+        }
+        self.read(buf)
+    }
+
+    /// Returns the pump, starting its thread on first use.
+    fn pump(&mut self) -> Arc<Pump> {
+        if let Source::Pumped(pump) = &self.source {
+            return pump.clone();
+        }
+        let placeholder = Source::Direct(Box::new(io::empty()));
+        let Source::Direct(inner) = std::mem::replace(&mut self.source, placeholder) else {
+            unreachable!()
+        };
+        let pump = Pump::start(inner, None);
+        self.source = Source::Pumped(pump.clone());
+        pump
+    }
+
+    /// Pulls newly available output into `pending`, waiting at most until `deadline`.
+    /// Returns the number of bytes added, `Ok(0)` at EOF, or `TimedOut` if the deadline passed.
+    /// Under `PTY_UTF8_ERROR`, fails with `InvalidData` once for output that had malformed
+    /// input dropped; the rest of that output stays in `pending`.
+    fn fill(&mut self, deadline: Option<Instant>) -> io::Result<usize> {
+        loop {
+            let before = self.pending.len();
+            let raw = self.fill_raw(deadline)?;
+            if raw.is_empty() {
+                self.finish_output();
+            } else {
+                self.push_output(&raw);
+            }
+            if self.unreported_invalid > 0 {
+                let dropped = std::mem::take(&mut self.unreported_invalid);
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("Output contained {dropped} malformed sequence(s), which were dropped"),
+                ));
+            }
+            // A chunk can end inside a multibyte sequence and decode to nothing yet.
+            if raw.is_empty() || self.pending.len() > before {
+                return Ok(self.pending.len() - before);
+            }
+        }
+    }
+
+    /// Returns the next chunk of raw PTY output, or an empty chunk at EOF.
+    fn fill_raw(&mut self, deadline: Option<Instant>) -> io::Result<Vec<u8>> {
+        if let (Source::Direct(inner), None) = (&mut self.source, deadline) {
+            let mut chunk = [0u8; CHUNK_SIZE];
+            loop {
+                match inner.read(&mut chunk) {
+                    Ok(n) => return Ok(chunk[..n].to_vec()),
+                    Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                    Err(e) => return Err(e),
+                }
+            }
+        }
+
+        let pump = self.pump();
+        let mut state = pump.state.lock().unwrap();
+        loop {
+            if !state.buf.is_empty() {
+                let raw = state.buf.drain(..).collect();
+                pump.changed.notify_all();
+                return Ok(raw);
+            }
+            if let Some(e) = state.error.take() {
+                return Err(e);
+            }
+            if state.eof {
+                return Ok(Vec::new());
+            }
+            state = match deadline {
+                None => pump.changed.wait(state).unwrap(),
+                Some(deadline) => {
+                    let now = Instant::now();
+                    if now >= deadline {
+                        return Err(io::ErrorKind::TimedOut.into());
+                    }
+                    pump.changed.wait_timeout(state, deadline - now).unwrap().0
+                }
+            };
+        }
+    }
+
+    /// Transforms raw output and appends the result to `pending`.
+    fn push_output(&mut self, raw: &[u8]) {
+        self.log_frame(raw);
+        let start = self.pending.len();
+        match &mut self.decoder {
+            Some(decoder) => {
+                let replace = self.invalid_utf8_policy == PTY_UTF8_REPLACE;
+                let malformed = decode_into(decoder, raw, false, replace, &mut self.pending);
+                self.count_invalid(malformed);
+            }
+            None => self.pending.extend(raw),
+        }
+        self.apply_nul_policy(start);
+        self.observe(start);
+    }
+
+    /// Emits what the decoder is still holding, e.g. a multibyte sequence cut off by the
+    /// child exiting, and drops the decoder.
+    fn flush_decoder(&mut self) {
+        let start = self.pending.len();
+        if let Some(mut decoder) = self.decoder.take() {
+            let replace = self.invalid_utf8_policy == PTY_UTF8_REPLACE;
+            let malformed = decode_into(&mut decoder, &[], true, replace, &mut self.pending);
+            self.count_invalid(malformed);
+        }
+        self.apply_nul_policy(start);
+        self.observe(start);
+    }
+
+    /// Flushes transform state at EOF and tells the taps and the framed log the output ended.
+    fn finish_output(&mut self) {
+        self.flush_decoder();
+        self.end_taps();
+        // The empty record marks the end of the output.
+        self.log_frame(&[]);
+        self.framed_log = None;
+    }
+
+    /// Records `malformed` sequences met by the decoder.
+    fn count_invalid(&mut self, malformed: u64) {
+        self.invalid_utf8 += malformed;
+        if self.invalid_utf8_policy == PTY_UTF8_ERROR {
+            self.unreported_invalid += malformed;
+        }
+    }
+
+    /// Appends a record of `raw` to the framed log. A failed write ends the log.
+    fn log_frame(&mut self, raw: &[u8]) {
+        let Some(log) = &mut self.framed_log else {
+            return;
+        };
+        let micros = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |since| since.as_micros() as u64);
+        let mut record = Vec::with_capacity(FRAME_HEADER_SIZE + raw.len());
+        record.extend(micros.to_le_bytes());
+        record.extend((raw.len() as u32).to_le_bytes());
+        record.extend(raw);
+        if log.write_all(&record).is_err() {
+            self.framed_log = None;
+        }
+    }
+
+    /// Handles NUL bytes in `pending` from `start` on as `pty_reader_set_nul_policy` says.
+    fn apply_nul_policy(&mut self, start: usize) {
+        match self.nul_policy {
+            PTY_NUL_STRIP => {
+                let kept: Vec<u8> = self.pending.drain(start..).filter(|&b| b != 0).collect();
+                self.pending.extend(kept);
+            }
+            PTY_NUL_SPACE => {
+                for b in self.pending.range_mut(start..).filter(|b| **b == 0) {
+                    *b = b' ';
+                }
+            }
+            _ => {}
+        }
+    }
+
+    /// Shows output added to `pending` since `start` to the observers and taps.
+    fn observe(&mut self, start: usize) {
+        let added = &self.pending.make_contiguous()[start..];
+        self.osc.feed(added);
+        self.taps.retain(|tap| tap.feed(added));
+    }
+
+    /// Reports end of file to the taps.
+    fn end_taps(&mut self) {
+        for tap in self.taps.drain(..) {
+            let mut state = tap.state.lock().unwrap();
+            state.eof = true;
+            tap.changed.notify_all();
+        }
+    }
+
+    /// Transcodes output from `label` (a WHATWG encoding label such as "shift_jis" or
+    /// "latin1") to UTF-8. UTF-8 labels restore the default byte-for-byte pass-through,
+    /// unless an invalid UTF-8 policy was set, which keeps applying.
+    pub(crate) fn set_encoding(&mut self, label: &str) -> PtyResult<()> {
+        let encoding = Encoding::for_label(label.trim().as_bytes())
+            .ok_or_else(|| PtyError::new(format!("Unknown encoding: {label}")))?;
+        // Emit whatever the previous decoder was holding before switching.
+        self.flush_decoder();
+        if encoding != UTF_8 || self.checks_utf8 {
+            self.decoder = Some(encoding.new_decoder_without_bom_handling());
+        }
+        Ok(())
+    }
+
+    /// Throws away all output that has already arrived without waiting for more, and returns
+    /// the number of bytes dropped.
+    pub(crate) fn discard_available(&mut self) -> io::Result<usize> {
+        loop {
+            match self.source_ready() {
+                // Readable right now, so a direct read does not block.
+                Some(true) if matches!(self.source, Source::Direct(_)) => {
+                    let raw = self.fill_raw(None)?;
+                    if raw.is_empty() {
+                        self.finish_output();
+                        break;
+                    }
+                    self.push_output(&raw);
+                }
+                // The pump is about to pick it up.
+                Some(true) => match self.fill(Some(Instant::now() + DISCARD_GRACE)) {
+                    Ok(0) => break,
+                    Ok(_) => {}
+                    Err(e) if e.kind() == io::ErrorKind::TimedOut => break,
+                    Err(e) => return Err(e),
+                },
+                // Nothing left in the PTY; take what the pump already holds, if any.
+                Some(false) if matches!(self.source, Source::Direct(_)) => break,
+                _ => match self.fill(Some(Instant::now())) {
+                    Ok(0) => break,
+                    Ok(_) => {}
+                    Err(e) if e.kind() == io::ErrorKind::TimedOut => break,
+                    Err(e) => return Err(e),
+                },
+            }
+        }
+        // A multibyte character cut off by the discard must not corrupt what follows.
+        if let Some(decoder) = &self.decoder {
+            self.decoder = Some(decoder.encoding().new_decoder_without_bom_handling());
+        }
+        let discarded = self.pending.len();
+        self.pending.clear();
+        self.unreported_invalid = 0;
+        Ok(discarded)
+    }
+
+    /// Reads the PTY on the pump thread from now on, buffering up to `capacity` bytes the
+    /// consumer has not taken yet before backpressure applies.
+    pub(crate) fn start_pump(&mut self, capacity: usize) {
+        let pump = self.pump();
+        pump.state.lock().unwrap().capacity = capacity;
+        pump.changed.notify_all();
+    }
+
+    /// Switches the pump to draining: from now on it reads output as fast as the child
+    /// writes it, keeping at most `capacity` bytes (the current capacity if `None`) and
+    /// dropping the oldest when that is exceeded.
+    pub(crate) fn start_drain(&mut self, capacity: Option<usize>) {
+        let pump = self.pump();
+        let mut state = pump.state.lock().unwrap();
+        state.lossy = true;
+        if let Some(capacity) = capacity {
+            state.capacity = capacity;
+        }
+        if state.buf.len() > state.capacity {
+            let excess = state.buf.len() - state.capacity;
+            state.buf.drain(..excess);
+            state.truncated = true;
+        }
+        pump.changed.notify_all();
+    }
+
+    /// Bytes taken from the PTY that have not been read yet: pending output plus the pump's
+    /// queue.
+    pub(crate) fn buffered_len(&self) -> usize {
+        let queued = match &self.source {
+            Source::Pumped(pump) => pump.state.lock().unwrap().buf.len(),
+            Source::Direct(_) => 0,
+        };
+        self.pending.len() + queued
+    }
+
+    /// Most output the pump's queue has held; 0 without a pump.
+    pub(crate) fn high_water(&self) -> usize {
+        match &self.source {
+            Source::Pumped(pump) => pump.state.lock().unwrap().high_water,
+            Source::Direct(_) => 0,
+        }
+    }
+
+    /// Limits how fast the pump reads from the PTY to `rate` bytes per second (0 for no
+    /// limit).
+    pub(crate) fn set_rate_limit(&mut self, rate: u64) {
+        let pump = self.pump();
+        pump.state.lock().unwrap().rate = rate;
+        pump.changed.notify_all();
+    }
+
+    /// Moves output that has already arrived into `buf` without waiting, draining from now
+    /// on. Returns the number of bytes stored, whether output was dropped since the last
+    /// call, and whether the output has ended and everything has been taken.
+    pub(crate) fn take_since(&mut self, buf: &mut [u8]) -> io::Result<(usize, bool, bool)> {
+        self.start_drain(None);
+        let mut filled = 0;
+        let mut eof = false;
+        loop {
+            filled += self.take_pending(&mut buf[filled..]);
+            if filled == buf.len() {
+                break;
+            }
+            match self.fill(Some(Instant::now())) {
+                Ok(0) => {
+                    eof = true;
+                    break;
+                }
+                Ok(_) => {}
+                Err(e) if e.kind() == io::ErrorKind::TimedOut => break,
+                Err(e) => return Err(e),
+            }
+        }
+        let truncated = std::mem::take(&mut self.pump().state.lock().unwrap().truncated);
+        Ok((filled, truncated, eof && self.pending.is_empty()))
+    }
+
+    /// Moves up to `buf.len()` pending bytes into `buf`.
+    fn take_pending(&mut self, buf: &mut [u8]) -> usize {
+        let n = buf.len().min(self.pending.len());
+        for (dst, src) in buf.iter_mut().zip(self.pending.drain(..n)) {
+            *dst = src;
+        }
+        n
+    }
+
+    /// Waits until `find` locates a match in the pending output, then consumes and returns
+    /// the output up to the end offset it reports. `find` receives all pending output and
+    /// how much of it earlier calls already searched. Returns `None` if `deadline` passes
+    /// first, leaving everything pending for the next read, and `UnexpectedEof` if the
+    /// output ends without a match.
+    pub(crate) fn read_until(
+        &mut self,
+        deadline: Option<Instant>,
+        find: impl FnMut(&[u8], usize) -> Option<usize>,
+    ) -> io::Result<Option<Vec<u8>>> {
+        Ok(self
+            .peek_until(deadline, find)?
+            .map(|end| self.pending.drain(..end).collect()))
+    }
+
+    /// Like `read_until`, but leaves the output pending and returns the match's end offset.
+    pub(crate) fn peek_until(
+        &mut self,
+        deadline: Option<Instant>,
+        mut find: impl FnMut(&[u8], usize) -> Option<usize>,
+    ) -> io::Result<Option<usize>> {
+        let mut searched = 0;
+        loop {
+            if let Some(end) = find(self.pending.make_contiguous(), searched) {
+                return Ok(Some(end));
+            }
+            searched = self.pending.len();
+            match self.fill(deadline) {
+                Ok(0) => return Err(io::ErrorKind::UnexpectedEof.into()),
+                Ok(_) => {}
+                Err(e) if e.kind() == io::ErrorKind::TimedOut => return Ok(None),
+                Err(e) => return Err(e),
+            }
+        }
+    }
+
+    /// Waits until more output arrives than is already pending, leaving all of it pending.
+    /// Returns false if `deadline` passes first, and `UnexpectedEof` if the output ends.
+    pub(crate) fn wait_for_more(&mut self, deadline: Instant) -> io::Result<bool> {
+        let before = self.pending.len();
+        let more = self.peek_until(Some(deadline), |pending, _| {
+            (pending.len() > before).then_some(0)
+        })?;
+        Ok(more.is_some())
+    }
+
+    /// Reads until at least `min` bytes are in `buf`, the PTY reaches EOF, or `timeout`
+    /// elapses. Once `min` is reached, output that has already arrived is added too, up to
+    /// `buf.len()`.
+    pub(crate) fn read_bounded(
+        &mut self,
+        buf: &mut [u8],
+        min: usize,
+        timeout: Duration,
+    ) -> (usize, Stop) {
+        let deadline = Instant::now() + timeout;
+        let mut filled = 0;
+        loop {
+            filled += self.take_pending(&mut buf[filled..]);
+            if filled >= min {
+                return (filled, Stop::Filled);
+            }
+            match self.fill(Some(deadline)) {
+                Ok(0) => return (filled, Stop::Eof),
+                Ok(_) => {}
+                Err(e) if e.kind() == io::ErrorKind::TimedOut => return (filled, Stop::Timeout),
+                Err(e) => return (filled, Stop::Error(e)),
+            }
+        }
+    }
+
+    /// Reads a burst of output: waits for the first byte, then keeps reading until no new
+    /// output arrives for `idle`. Stops early when `buf` is full, the PTY reaches EOF, or
+    /// `deadline` passes. `Filled` means the output went quiet or `buf` is full.
+    pub(crate) fn read_idle(
+        &mut self,
+        buf: &mut [u8],
+        idle: Duration,
+        deadline: Option<Instant>,
+    ) -> (usize, Stop) {
+        let mut filled = 0;
+        let mut quiet_at = None;
+        loop {
+            let taken = self.take_pending(&mut buf[filled..]);
+            filled += taken;
+            if filled == buf.len() {
+                return (filled, Stop::Filled);
+            }
+            if taken > 0 {
+                quiet_at = Some(Instant::now() + idle);
+            }
+            let idle_first = match (quiet_at, deadline) {
+                (Some(quiet_at), Some(deadline)) => quiet_at <= deadline,
+                (quiet_at, _) => quiet_at.is_some(),
+            };
+            let wait_until = if idle_first { quiet_at } else { deadline };
+            match self.fill(wait_until) {
+                Ok(0) => return (filled, Stop::Eof),
+                Ok(_) => {}
+                Err(e) if e.kind() == io::ErrorKind::TimedOut && idle_first => {
+                    return (filled, Stop::Filled);
+                }
+                Err(e) if e.kind() == io::ErrorKind::TimedOut => return (filled, Stop::Timeout),
+                Err(e) => return (filled, Stop::Error(e)),
+            }
+        }
+    }
+}
+
+impl Read for Reader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        if self.pending.is_empty() && self.fill(None)? == 0 {
+            return Ok(0);
+        }
+        Ok(self.take_pending(buf))
+    }
+}
+
+impl Pump {
+    fn new(capacity: usize, lossy: bool) -> Arc<Pump> {
+        Arc::new(Pump {
+            state: Mutex::new(PumpState {
+                buf: VecDeque::new(),
+                capacity,
+                lossy,
+                truncated: false,
+                eof: false,
+                error: None,
+                closed: false,
+                log_error: None,
+                rate: 0,
+                high_water: 0,
+            }),
+            changed: Condvar::new(),
+        })
+    }
+
+    /// Starts a pump thread reading from `inner`, copying everything it reads to `log`.
+    pub(crate) fn start(inner: Box<dyn Read + Send>, log: Option<File>) -> Arc<Pump> {
+        let pump = Pump::new(PUMP_CAPACITY, false);
+        let thread_pump = pump.clone();
+        thread::spawn(move || run_pump(thread_pump, inner, log));
+        pump
+    }
+
+    /// Appends `data` to a tap's queue, dropping the oldest output if it overflows. Returns
+    /// false once the tap is freed.
+    fn feed(&self, data: &[u8]) -> bool {
+        let mut state = self.state.lock().unwrap();
+        if state.closed {
+            return false;
+        }
+        state.push(data);
+        self.changed.notify_all();
+        true
+    }
+
+    /// Why writing the output log failed, if it did.
+    pub(crate) fn log_error(&self) -> Option<String> {
+        self.state.lock().unwrap().log_error.clone()
+    }
+}
+
+impl Drop for Reader {
+    fn drop(&mut self) {
+        self.end_taps();
+        if let Source::Pumped(pump) = &self.source
+            && !self.shared
+        {
+            let mut state = pump.state.lock().unwrap();
+            state.closed = true;
+            state.buf.clear();
+            pump.changed.notify_all();
+        }
+    }
+}
+
+/// Reads a non-blocking PTY as if it were blocking, waiting in `poll` while it has no output.
+#[cfg(unix)]
+struct WaitForOutput {
+    inner: Box<dyn Read + Send>,
+    fd: OwnedFd,
+}
+
+#[cfg(unix)]
+impl Read for WaitForOutput {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        loop {
+            match self.inner.read(buf) {
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => {
+                    let mut pfd = libc::pollfd {
+                        fd: self.fd.as_raw_fd(),
+                        events: libc::POLLIN,
+                        revents: 0,
+                    };
+                    if unsafe { libc::poll(&mut pfd, 1, -1) } == -1 {
+                        let err = io::Error::last_os_error();
+                        if err.kind() != io::ErrorKind::Interrupted {
+                            return Err(err);
+                        }
+                    }
+                }
+                result => return result,
+            }
+        }
+    }
+}
+
+/// Decodes `raw` with `decoder` and appends the UTF-8 output to `out`. Each malformed
+/// sequence becomes U+FFFD if `replace` is set and is dropped otherwise; partial sequences
+/// stay buffered in the decoder until `last`. Returns the number of malformed sequences.
+fn decode_into(
+    decoder: &mut Decoder,
+    mut raw: &[u8],
+    last: bool,
+    replace: bool,
+    out: &mut VecDeque<u8>,
+) -> u64 {
+    let capacity = |decoder: &Decoder, len: usize| {
+        decoder
+            .max_utf8_buffer_length_without_replacement(len)
+            .unwrap_or(len * 3 + 16)
+    };
+    let mut decoded = String::with_capacity(capacity(decoder, raw.len()));
+    let mut malformed = 0;
+    loop {
+        let (result, read) = decoder.decode_to_string_without_replacement(raw, &mut decoded, last);
+        raw = &raw[read..];
+        match result {
+            DecoderResult::InputEmpty => break,
+            DecoderResult::OutputFull => decoded.reserve(capacity(decoder, raw.len())),
+            DecoderResult::Malformed(..) => {
+                malformed += 1;
+                if replace {
+                    decoded.push(char::REPLACEMENT_CHARACTER);
+                }
+            }
+        }
+    }
+    out.extend(decoded.as_bytes());
+    malformed
+}
+
+/// Body of the pump thread. It exits at EOF, on error, or once the reader is freed; a thread
+/// blocked in `read` notices the latter only when the PTY next produces output or closes.
+/// Output goes to `log` as soon as it is read; a failed log write only ends the logging.
+fn run_pump(pump: Arc<Pump>, mut inner: Box<dyn Read + Send>, mut log: Option<File>) {
+    let mut chunk = [0u8; CHUNK_SIZE];
+    let mut last_read = (Instant::now(), 0);
+    loop {
+        let Some(size) = pace(&pump, last_read) else {
+            return;
+        };
+        let result = inner.read(&mut chunk[..size]);
+        if let Ok(n) = &result {
+            last_read = (Instant::now(), *n);
+        }
+        let mut log_error = None;
+        if let (Ok(n), Some(file)) = (&result, &mut log)
+            && let Err(e) = file.write_all(&chunk[..*n])
+        {
+            log_error = Some(format!("Writing the output log failed: {e}"));
+            log = None;
+        }
+        let mut state = pump.state.lock().unwrap();
+        if log_error.is_some() {
+            state.log_error = log_error;
+        }
+        if state.closed {
+            return;
+        }
+        match result {
+            Ok(0) => {
+                state.eof = true;
+                pump.changed.notify_all();
+                return;
+            }
+            Ok(n) => {
+                while state.buf.len() >= state.capacity && !state.lossy && !state.closed {
+                    state = pump.changed.wait(state).unwrap();
+                }
+                if state.closed {
+                    return;
+                }
+                state.push(&chunk[..n]);
+                pump.changed.notify_all();
+            }
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+            Err(e) => {
+                state.error = Some(e);
+                state.eof = true;
+                pump.changed.notify_all();
+                return;
+            }
+        }
+    }
+}
+
+/// Holds the pump thread back to the rate limit: waits until the `(time, bytes)` of its
+/// last read are paid for, then returns how much the next read may take. Time spent idle
+/// earns no credit for a later burst. Returns `None` once the reader is freed.
+fn pace(pump: &Pump, (read_at, read): (Instant, usize)) -> Option<usize> {
+    let mut state = pump.state.lock().unwrap();
+    loop {
+        if state.closed {
+            return None;
+        }
+        if state.rate == 0 {
+            return Some(CHUNK_SIZE);
+        }
+        let due = read_at + Duration::from_secs_f64(read as f64 / state.rate as f64);
+        let now = Instant::now();
+        if now >= due {
+            return Some(usize::try_from(state.rate).map_or(CHUNK_SIZE, |r| r.min(CHUNK_SIZE)));
+        }
+        state = pump.changed.wait_timeout(state, due - now).unwrap().0;
+    }
+}
+
+/// Read up to `len` bytes or until `timeout_ms` elapses, whichever comes first.
+/// Returns 0 on success, -1 on error; sets out_err_msg to error string (caller must free) or null
+///
+/// `out_len` receives the number of bytes stored in `buf` and `out_reason` why the read
+/// stopped: 0 = buffer filled, 1 = timeout, 2 = EOF, 3 = error. Both are set on every
+/// return, so bytes read before a timeout or error are never lost. A `timeout_ms` of 0
+/// returns whatever output has already arrived.
+///
+/// # Safety
+///
+/// Caller must ensure:
+/// - `reader` is a valid, non-null handle obtained from `pty_get_reader`.
+/// - `buf` is a valid, non-null pointer to mutable memory of at least `len` bytes.
+/// - `out_len`, `out_reason`, and `out_err_msg` are valid, non-null pointers to mutable memory.
+/// - The caller must free any error message using `pty_free_err_msg`.
+/// - No concurrent access to the reader handle.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn pty_read_bounded(
+    reader: ReaderHandle,
+    buf: *mut u8,
+    len: usize,
+    timeout_ms: u32,
+    out_len: *mut usize,
+    out_reason: *mut i32,
+    out_err_msg: *mut *mut libc::c_char,
+) -> i32 {
+    ffi_call("pty_read_bounded", out_err_msg, || unsafe {
+        let reader_struct = handle_mut(reader)?;
+        let slice = buf_mut(buf, len)?;
+        let out_len = out_ref(out_len)?;
+        let out_reason = out_ref(out_reason)?;
+        let (n, stop) =
+            reader_struct.read_bounded(slice, len, Duration::from_millis(u64::from(timeout_ms)));
+        *out_len = n;
+        match stop {
+            Stop::Filled => *out_reason = PTY_READ_FILLED,
+            Stop::Timeout => *out_reason = PTY_READ_TIMEOUT,
+            Stop::Eof => *out_reason = PTY_READ_EOF,
+            Stop::Error(e) => {
+                *out_reason = PTY_READ_ERROR;
+                return Err(e.into());
+            }
+        }
+        Ok(0)
+    })
+}
+
+/// Read at least `min_len` and at most `max_len` bytes, waiting at most `timeout_ms`.
+/// Returns 0 once `min_len` bytes were read, 1 on timeout, 2 at EOF, -1 on error; sets out_err_msg (caller frees via pty_free_err_msg).
+///
+/// Keeps reading until `min_len` bytes have accumulated in `buf`; past that, output that
+/// has already arrived is included as well, up to `max_len`, without waiting for more.
+/// `out_len` receives the number of bytes stored in `buf` on every return, so bytes read
+/// before a timeout, EOF or error are never lost. A `min_len` of 0 returns whatever has
+/// already arrived.
+///
+/// # Safety
+///
+/// Caller must ensure:
+/// - `reader` is a valid, non-null handle obtained from `pty_get_reader`.
+/// - `buf` is a valid, non-null pointer to mutable memory of at least `max_len` bytes.
+/// - `out_len` and `out_err_msg` are valid, non-null pointers to mutable memory.
+/// - The caller must free any error message using `pty_free_err_msg`.
+/// - No concurrent access to the reader handle.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn pty_read_min(
+    reader: ReaderHandle,
+    buf: *mut u8,
+    max_len: usize,
+    min_len: usize,
+    timeout_ms: u32,
+    out_len: *mut usize,
+    out_err_msg: *mut *mut libc::c_char,
+) -> i32 {
+    ffi_call("pty_read_min", out_err_msg, || unsafe {
+        let reader_struct = handle_mut(reader)?;
+        let slice = buf_mut(buf, max_len)?;
+        let out_len = out_ref(out_len)?;
+        *out_len = 0;
+        if min_len > max_len {
+            return Err(PtyError::new(format!(
+                "min_len ({min_len}) exceeds max_len ({max_len})"
+            )));
+        }
+        let (n, stop) = reader_struct.read_bounded(
+            slice,
+            min_len,
+            Duration::from_millis(u64::from(timeout_ms)),
+        );
+        *out_len = n;
+        match stop {
+            Stop::Filled => Ok(PTY_READ_FILLED),
+            Stop::Timeout => Ok(PTY_READ_TIMEOUT),
+            Stop::Eof => Ok(PTY_READ_EOF),
+            Stop::Error(e) => Err(e.into()),
+        }
+    })
+}
+
+/// Read one burst of output: everything up to the first `idle_ms` of silence.
+/// Returns 0 once the output went quiet or `buf` is full, 1 if `overall_timeout_ms` elapsed first, 2 at EOF, -1 on error; sets out_err_msg (caller frees via pty_free_err_msg).
+///
+/// For programs whose responses carry no delimiter to wait for: after sending a command,
+/// this captures its response as one unit. Waits for the first byte, then reads until no
+/// new output has arrived for `idle_ms`. `overall_timeout_ms` bounds the whole call,
+/// including the wait for the first byte; 0 means no overall limit. `out_len` receives the
+/// number of bytes stored in `buf` on every return, and equals `len` if it filled up.
+///
+/// This is a heuristic. A program that pauses longer than `idle_ms` mid-response (on a
+/// slow computation, disk or network access, or simply a loaded machine) has its response
+/// split across calls, and a program that never goes quiet, such as one printing progress,
+/// only stops at the overall timeout. A larger `idle_ms` is safer but adds that much delay
+/// to every call. Where the program's output can be made to end with a marker, wait for
+/// the marker instead.
+///
+/// # Safety
+///
+/// Caller must ensure:
+/// - `reader` is a valid, non-null handle obtained from `pty_get_reader`.
+/// - `buf` is a valid, non-null pointer to mutable memory of at least `len` bytes.
+/// - `out_len` and `out_err_msg` are valid, non-null pointers to mutable memory.
+/// - The caller must free any error message using `pty_free_err_msg`.
+/// - No concurrent access to the reader handle.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn pty_read_idle_framed(
+    reader: ReaderHandle,
+    buf: *mut u8,
+    len: usize,
+    idle_ms: u32,
+    overall_timeout_ms: u32,
+    out_len: *mut usize,
+    out_err_msg: *mut *mut libc::c_char,
+) -> i32 {
+    ffi_call("pty_read_idle_framed", out_err_msg, || unsafe {
+        let reader_struct = handle_mut(reader)?;
+        let slice = buf_mut(buf, len)?;
+        let out_len = out_ref(out_len)?;
+        let deadline = (overall_timeout_ms > 0)
+            .then(|| Instant::now() + Duration::from_millis(u64::from(overall_timeout_ms)));
+        let (n, stop) =
+            reader_struct.read_idle(slice, Duration::from_millis(u64::from(idle_ms)), deadline);
+        *out_len = n;
+        match stop {
+            Stop::Filled => Ok(PTY_READ_FILLED),
+            Stop::Timeout => Ok(PTY_READ_TIMEOUT),
+            Stop::Eof => Ok(PTY_READ_EOF),
+            Stop::Error(e) => Err(e.into()),
+        }
+    })
+}
+
+/// Set the text that marks a shell prompt for `pty_read_until_prompt`.
+/// Returns 0 on success, -1 on error; sets out_err_msg (caller frees via pty_free_err_msg).
+///
+/// `pattern` is matched literally, byte for byte; use `pty_reader_compile_pattern` and
+/// `pty_read_until_match` for regular expressions. Pick the end of the prompt as the shell
+/// prints it, e.g. "$ " or "> ", including any escape sequences the shell emits after it.
+/// Setting `PS1` to something distinctive makes the match far more reliable than a shell's
+/// default prompt. A null `pattern` clears it.
+///
+/// # Safety
+///
+/// Caller must ensure:
+/// - `reader` is a valid, non-null handle obtained from `pty_get_reader`.
+/// - `pattern` is null or a valid pointer to a null-terminated C string.
+/// - `out_err_msg` is a valid, non-null pointer to mutable memory.
+/// - The caller must free any error message using `pty_free_err_msg`.
+/// - No concurrent access to the reader handle.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn pty_reader_set_prompt_pattern(
+    reader: ReaderHandle,
+    pattern: *const libc::c_char,
+    out_err_msg: *mut *mut libc::c_char,
+) -> i32 {
+    ffi_call("pty_reader_set_prompt_pattern", out_err_msg, || unsafe {
+        let reader_struct = handle_mut(reader)?;
+        if pattern.is_null() {
+            reader_struct.prompt = None;
+            return Ok(0);
+        }
+        let pattern = str_from_ptr(pattern)?;
+        if pattern.is_empty() {
+            return Err(PtyError::new("The prompt pattern is empty"));
+        }
+        reader_struct.prompt = Some(pattern.into_bytes());
+        Ok(0)
+    })
+}
+
+/// Read until the shell prints its prompt.
+/// Returns 0 once the prompt was seen, 1 on timeout, -1 on error; sets out_err_msg (caller frees via pty_free_err_msg).
+///
+/// Waits until the output that has arrived ends with the pattern set by
+/// `pty_reader_set_prompt_pattern`, then moves it, prompt included, into `buf` and stores
+/// its length in `out_len`. The pattern may arrive split across any number of reads. Use it
+/// to sequence commands: send one, wait for the prompt, send the next.
+///
+/// Only a pattern at the very end of the output counts, which is where a shell waiting
+/// for input leaves it; the same text followed by more output is not a prompt. This is a
+/// heuristic: a command printing text that ends with the pattern and then pausing also
+/// matches. On timeout nothing is consumed and `out_len` is 0. If the output up to the
+/// prompt does not fit in `cap` bytes, nothing is consumed and the call fails with
+/// `out_len` set to the size needed. Output ending before the prompt appears is an error.
+/// A `timeout_ms` of 0 only checks output that has already arrived.
+///
+/// # Safety
+///
+/// Caller must ensure:
+/// - `reader` is a valid, non-null handle obtained from `pty_get_reader`.
+/// - `buf` is a valid, non-null pointer to mutable memory of at least `cap` bytes.
+/// - `out_len` and `out_err_msg` are valid, non-null pointers to mutable memory.
+/// - The caller must free any error message using `pty_free_err_msg`.
+/// - No concurrent access to the reader handle.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn pty_read_until_prompt(
+    reader: ReaderHandle,
+    buf: *mut u8,
+    cap: usize,
+    out_len: *mut usize,
+    timeout_ms: u32,
+    out_err_msg: *mut *mut libc::c_char,
+) -> i32 {
+    ffi_call("pty_read_until_prompt", out_err_msg, || unsafe {
+        let reader_struct = handle_mut(reader)?;
+        let slice = buf_mut(buf, cap)?;
+        let out_len = out_ref(out_len)?;
+        *out_len = 0;
+        let prompt = reader_struct
+            .prompt
+            .clone()
+            .ok_or_else(|| PtyError::new("No prompt pattern is set"))?;
+        let deadline = Instant::now() + Duration::from_millis(u64::from(timeout_ms));
+        let end = match reader_struct.peek_until(Some(deadline), |pending, _| {
+            pending.ends_with(&prompt).then_some(pending.len())
+        }) {
+            Ok(Some(end)) => end,
+            Ok(None) => return Ok(PTY_PROMPT_TIMEOUT),
+            Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => {
+                return Err(PtyError::new("Output ended before the prompt appeared"));
+            }
+            Err(e) => return Err(e.into()),
+        };
+        if end > cap {
+            *out_len = end;
+            return Err(PtyError::new(format!(
+                "Output up to the prompt is {end} bytes, more than the buffer holds ({cap})"
+            )));
+        }
+        *out_len = reader_struct.take_pending(&mut slice[..end]);
+        Ok(PTY_PROMPT_SEEN)
+    })
+}
+
+/// Compile the regular expression `pty_read_until_match` waits for.
+/// Returns 0 on success, -1 on error; sets out_err_msg (caller frees via pty_free_err_msg).
+///
+/// `regex` uses the syntax of Rust's `regex` crate, which is close to Perl's and RE2's
+/// without backreferences or lookaround; an invalid pattern fails here with the parser's
+/// explanation. It is matched against raw output bytes, so `.` and classes still work on
+/// output that is not valid UTF-8, while Unicode-aware matching applies where it is.
+/// Flags go inline, e.g. `(?i)password:` or `(?m)^\$ $`. The pattern is compiled once
+/// and replaces any earlier one; a null `regex` clears it.
+///
+/// # Safety
+///
+/// Caller must ensure:
+/// - `reader` is a valid, non-null handle obtained from `pty_get_reader`.
+/// - `regex` is null or a valid pointer to a null-terminated C string.
+/// - `out_err_msg` is a valid, non-null pointer to mutable memory.
+/// - The caller must free any error message using `pty_free_err_msg`.
+/// - No concurrent access to the reader handle.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn pty_reader_compile_pattern(
+    reader: ReaderHandle,
+    regex: *const libc::c_char,
+    out_err_msg: *mut *mut libc::c_char,
+) -> i32 {
+    ffi_call("pty_reader_compile_pattern", out_err_msg, || unsafe {
+        let reader_struct = handle_mut(reader)?;
+        if regex.is_null() {
+            reader_struct.pattern = None;
+            return Ok(0);
+        }
+        let regex = str_from_ptr(regex)?;
+        let pattern =
+            Regex::new(&regex).map_err(|e| PtyError::new(format!("Invalid pattern: {e}")))?;
+        reader_struct.pattern = Some(pattern);
+        Ok(0)
+    })
+}
+
+/// Read until the pattern compiled by `pty_reader_compile_pattern` matches.
+/// Returns 0 once it matched, 1 on timeout, -1 on error; sets out_err_msg (caller frees via pty_free_err_msg).
+///
+/// Waits until the pattern matches anywhere in the output that has arrived, then moves
+/// the output up to the end of the match into `buf`, stores its length in `out_len`, and
+/// stores where the match starts and ends in `buf` in `out_match_start` and
+/// `out_match_end`. Output after the match stays buffered for the next read. A match may
+/// span any number of reads: each time more output arrives, everything not yet consumed is
+/// searched again. The first match is taken as soon as it exists, so a pattern that could
+/// match more text, like `\d+`, ends with whatever had arrived; anchor or delimit it
+/// (`\d+\n`) to wait for the rest.
+///
+/// On timeout nothing is consumed and `out_len` is 0. If the output up to the end of the
+/// match does not fit in `cap` bytes, nothing is consumed and the call fails with
+/// `out_len` set to the size needed. Output ending before the pattern matches is an error.
+/// A `timeout_ms` of 0 only checks output that has already arrived.
+///
+/// # Safety
+///
+/// Caller must ensure:
+/// - `reader` is a valid, non-null handle obtained from `pty_get_reader`.
+/// - `buf` is a valid, non-null pointer to mutable memory of at least `cap` bytes.
+/// - `out_len`, `out_match_start`, `out_match_end` and `out_err_msg` are valid, non-null pointers to mutable memory.
+/// - The caller must free any error message using `pty_free_err_msg`.
+/// - No concurrent access to the reader handle.
+#[unsafe(no_mangle)]
+#[allow(clippy::too_many_arguments)]
+pub unsafe extern "C" fn pty_read_until_match(
+    reader: ReaderHandle,
+    buf: *mut u8,
+    cap: usize,
+    out_len: *mut usize,
+    out_match_start: *mut usize,
+    out_match_end: *mut usize,
+    timeout_ms: u32,
+    out_err_msg: *mut *mut libc::c_char,
+) -> i32 {
+    ffi_call("pty_read_until_match", out_err_msg, || unsafe {
+        let reader_struct = handle_mut(reader)?;
+        let slice = buf_mut(buf, cap)?;
+        let out_len = out_ref(out_len)?;
+        let out_match_start = out_ref(out_match_start)?;
+        let out_match_end = out_ref(out_match_end)?;
+        *out_len = 0;
+        *out_match_start = 0;
+        *out_match_end = 0;
+        let pattern = reader_struct
+            .pattern
+            .clone()
+            .ok_or_else(|| PtyError::new("No pattern is compiled"))?;
+        let deadline = Instant::now() + Duration::from_millis(u64::from(timeout_ms));
+        let mut start = 0;
+        let end = match reader_struct.peek_until(Some(deadline), |pending, _| {
+            pattern.find(pending).map(|m| {
+                start = m.start();
+                m.end()
+            })
+        }) {
+            Ok(Some(end)) => end,
+            Ok(None) => return Ok(PTY_MATCH_TIMEOUT),
+            Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => {
+                return Err(PtyError::new("Output ended before the pattern matched"));
+            }
+            Err(e) => return Err(e.into()),
+        };
+        if end > cap {
+            *out_len = end;
+            return Err(PtyError::new(format!(
+                "Output up to the match is {end} bytes, more than the buffer holds ({cap})"
+            )));
+        }
+        *out_len = reader_struct.take_pending(&mut slice[..end]);
+        *out_match_start = start;
+        *out_match_end = end;
+        Ok(PTY_MATCH_SEEN)
+    })
+}
+
+/// Transcode this reader's output from the named encoding to UTF-8.
+/// Returns 0 on success, -1 on error; sets out_err_msg to error string (caller must free) or null
+///
+/// `encoding_name` is a WHATWG encoding label such as "latin1", "windows-1252", "shift_jis"
+/// or "euc-kr". Afterwards every read on this reader returns UTF-8; a multibyte character
+/// split across chunks is held back until it is complete, and malformed input becomes
+/// U+FFFD unless `pty_reader_set_invalid_utf8_policy` says otherwise. A UTF-8 label
+/// switches back to the default pass-through of raw bytes, or, once an invalid UTF-8
+/// policy was set, to checking the output against that policy.
+///
+/// # Safety
+///
+/// Caller must ensure:
+/// - `reader` is a valid, non-null handle obtained from `pty_get_reader`.
+/// - `encoding_name` is a valid, non-null pointer to a null-terminated C string.
+/// - `out_err_msg` is a valid, non-null pointer to mutable memory.
+/// - The caller must free any error message using `pty_free_err_msg`.
+/// - No concurrent access to the reader handle.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn pty_reader_set_encoding(
+    reader: ReaderHandle,
+    encoding_name: *const libc::c_char,
+    out_err_msg: *mut *mut libc::c_char,
+) -> i32 {
+    ffi_call("pty_reader_set_encoding", out_err_msg, || unsafe {
+        let reader_struct = handle_mut(reader)?;
+        reader_struct.set_encoding(&str_from_ptr(encoding_name)?)?;
+        Ok(0)
+    })
+}
+
+/// Choose what this reader does with NUL bytes in the output.
+/// Returns 0 on success, -1 on error.
+///
+/// `policy` is `PTY_NUL_PASS` (0, the default) to keep them, `PTY_NUL_STRIP` (1) to remove
+/// them, or `PTY_NUL_SPACE` (2) to replace each with a space. For callers that treat output
+/// as C strings, which would otherwise end at the first NUL a binary-ish program prints.
+/// Applies to every read on this reader from now on, including output it has already
+/// buffered, after transcoding by `pty_reader_set_encoding`; taps receive the result.
+///
+/// # Safety
+///
+/// Caller must ensure:
+/// - `reader` is a valid, non-null handle obtained from `pty_get_reader`.
+/// - No concurrent access to the reader handle.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn pty_reader_set_nul_policy(reader: ReaderHandle, policy: i32) -> i32 {
+    ffi_call(
+        "pty_reader_set_nul_policy",
+        std::ptr::null_mut(),
+        || unsafe {
+            let reader_struct = handle_mut(reader)?;
+            if !matches!(policy, PTY_NUL_PASS | PTY_NUL_STRIP | PTY_NUL_SPACE) {
+                return Err(PtyError::new(format!("Unknown NUL policy: {policy}")));
+            }
+            reader_struct.nul_policy = policy;
+            reader_struct.apply_nul_policy(0);
+            Ok(0)
+        },
+    )
+}
+
+/// Choose what this reader does with malformed input while transcoding.
+/// Returns 0 on success, -1 on error.
+///
+/// `policy` is `PTY_UTF8_REPLACE` (0, the default) to replace each malformed sequence with
+/// U+FFFD, or `PTY_UTF8_ERROR` (1) to drop it and fail the read that pulls it in with -1
+/// and a message saying how many were dropped. The output around it is kept and returned
+/// by the next read, so the stream goes on either way. Applies to the encoding set with
+/// `pty_reader_set_encoding`; a reader passing raw bytes through, as by default, starts
+/// checking that its output is valid UTF-8, and keeps checking it when a later
+/// `pty_reader_set_encoding` switches back to UTF-8. Either way
+/// `pty_reader_invalid_utf8_count` reports how many malformed sequences were met. Affects
+/// output decoded from now on.
+///
+/// # Safety
+///
+/// Caller must ensure:
+/// - `reader` is a valid, non-null handle obtained from `pty_get_reader`.
+/// - No concurrent access to the reader handle.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn pty_reader_set_invalid_utf8_policy(
+    reader: ReaderHandle,
+    policy: i32,
+) -> i32 {
+    ffi_call(
+        "pty_reader_set_invalid_utf8_policy",
+        std::ptr::null_mut(),
+        || unsafe {
+            let reader_struct = handle_mut(reader)?;
+            if !matches!(policy, PTY_UTF8_REPLACE | PTY_UTF8_ERROR) {
+                return Err(PtyError::new(format!(
+                    "Unknown invalid UTF-8 policy: {policy}"
+                )));
+            }
+            reader_struct.invalid_utf8_policy = policy;
+            reader_struct.checks_utf8 = true;
+            if reader_struct.decoder.is_none() {
+                reader_struct.decoder = Some(UTF_8.new_decoder_without_bom_handling());
+            }
+            Ok(0)
+        },
+    )
+}
+
+/// Get the number of malformed sequences this reader's decoder has met so far.
+///
+/// Counts every sequence `pty_reader_set_invalid_utf8_policy` replaced or dropped, for
+/// diagnosing corrupt output or a wrong `pty_reader_set_encoding`. Always 0 for a reader
+/// passing raw bytes through, and for an invalid handle.
+///
+/// # Safety
+///
+/// Caller must ensure:
+/// - `reader` is a valid, non-null handle obtained from `pty_get_reader`.
+/// - No concurrent access to the reader handle.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn pty_reader_invalid_utf8_count(reader: ReaderHandle) -> u64 {
+    unsafe { handle_mut(reader) }.map_or(0, |reader_struct| reader_struct.invalid_utf8)
+}
+
+/// Discard all output that has already arrived, without blocking.
+/// Returns 0 on success, -1 on error; sets out_err_msg to error string (caller must free) or null
+///
+/// Drops the reader's buffered output plus whatever the PTY holds right now, and stores
+/// the number of bytes dropped in `out_discarded`. Use it between commands so the next
+/// command's output is not mixed with the tail of the previous one. Output the child
+/// writes after the call is kept.
+///
+/// # Safety
+///
+/// Caller must ensure:
+/// - `reader` is a valid, non-null handle obtained from `pty_get_reader`.
+/// - `out_discarded` and `out_err_msg` are valid, non-null pointers to mutable memory.
+/// - The caller must free any error message using `pty_free_err_msg`.
+/// - No concurrent access to the reader handle.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn pty_reader_flush_input(
+    reader: ReaderHandle,
+    out_discarded: *mut usize,
+    out_err_msg: *mut *mut libc::c_char,
+) -> i32 {
+    ffi_call("pty_reader_flush_input", out_err_msg, || unsafe {
+        let reader_struct = handle_mut(reader)?;
+        let out_discarded = out_ref(out_discarded)?;
+        *out_discarded = reader_struct.discard_available()?;
+        Ok(0)
+    })
+}
+
+/// Take the output that arrived since the previous call, without waiting.
+/// Returns 0 on success, 2 once the output has ended and everything has been taken, -1 on error; sets out_err_msg (caller frees via pty_free_err_msg).
+///
+/// The first call starts a background thread that drains the PTY as fast as the child
+/// writes, so a slow poller never blocks the child. The thread keeps the most recent
+/// output, 64 KiB by default (see `pty_reader_set_drain_capacity`), and drops the oldest
+/// bytes beyond that; `out_truncated` receives 1 if any were dropped since the previous
+/// call. `out_len` receives the number of bytes stored in `buf`; output that does not fit
+/// in `cap` bytes is kept for the next call. Once draining, other reads on this reader
+/// share the same buffer and can lose output the same way.
+///
+/// # Safety
+///
+/// Caller must ensure:
+/// - `reader` is a valid, non-null handle obtained from `pty_get_reader`.
+/// - `buf` is a valid, non-null pointer to mutable memory of at least `cap` bytes.
+/// - `out_len`, `out_truncated`, and `out_err_msg` are valid, non-null pointers to mutable memory.
+/// - The caller must free any error message using `pty_free_err_msg`.
+/// - No concurrent access to the reader handle.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn pty_reader_take_since(
+    reader: ReaderHandle,
+    buf: *mut u8,
+    cap: usize,
+    out_len: *mut usize,
+    out_truncated: *mut i32,
+    out_err_msg: *mut *mut libc::c_char,
+) -> i32 {
+    ffi_call("pty_reader_take_since", out_err_msg, || unsafe {
+        let reader_struct = handle_mut(reader)?;
+        let slice = buf_mut(buf, cap)?;
+        let out_len = out_ref(out_len)?;
+        let out_truncated = out_ref(out_truncated)?;
+        *out_len = 0;
+        *out_truncated = 0;
+        let (n, truncated, eof) = reader_struct.take_since(slice)?;
+        *out_len = n;
+        *out_truncated = i32::from(truncated);
+        Ok(if eof { PTY_READ_EOF } else { 0 })
+    })
+}
+
+/// Start draining this reader in the background, keeping at most `capacity` bytes.
+/// Returns 0 on success, -1 on error; sets out_err_msg (caller frees via pty_free_err_msg).
+///
+/// Sets the size of the buffer `pty_reader_take_since` collects output in, and starts
+/// collecting right away so output from before the first take is kept. Shrinking the
+/// buffer below what it holds drops the oldest bytes and reports them as truncated.
+///
+/// # Safety
+///
+/// Caller must ensure:
+/// - `reader` is a valid, non-null handle obtained from `pty_get_reader`.
+/// - `out_err_msg` is a valid, non-null pointer to mutable memory.
+/// - The caller must free any error message using `pty_free_err_msg`.
+/// - No concurrent access to the reader handle.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn pty_reader_set_drain_capacity(
+    reader: ReaderHandle,
+    capacity: usize,
+    out_err_msg: *mut *mut libc::c_char,
+) -> i32 {
+    ffi_call("pty_reader_set_drain_capacity", out_err_msg, || unsafe {
+        let reader_struct = handle_mut(reader)?;
+        if capacity == 0 {
+            return Err(PtyError::new("Drain capacity must be at least 1"));
+        }
+        reader_struct.start_drain(Some(capacity));
+        Ok(0)
+    })
+}
+
+/// Limit how fast this reader takes output from the PTY.
+/// Returns 0 on success, -1 on error.
+///
+/// From now on the reader's background thread reads at most `bytes_per_sec` bytes per
+/// second, averaged over each read, so a verbose program cannot flood a slow consumer
+/// such as a UI or a log sink. Quiet periods earn no allowance for a later burst. A
+/// `bytes_per_sec` of 0 removes the limit. Takes effect at once, even mid-wait.
+///
+/// The limit applies backpressure to the child: output it writes faster than the rate
+/// piles up in the PTY, and once the PTY's buffer fills up, the child blocks on its next
+/// write until the reader catches up. A program that must not stall should not be
+/// throttled. With `pty_builder_output_log`, the limit is shared by the master's readers
+/// and also paces the log.
+///
+/// # Safety
+///
+/// Caller must ensure:
+/// - `reader` is a valid, non-null handle obtained from `pty_get_reader`.
+/// - No concurrent access to the reader handle.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn pty_reader_set_rate_limit(
+    reader: ReaderHandle,
+    bytes_per_sec: u64,
+) -> i32 {
+    ffi_call(
+        "pty_reader_set_rate_limit",
+        std::ptr::null_mut(),
+        || unsafe {
+            handle_mut(reader)?.set_rate_limit(bytes_per_sec);
+            Ok(0)
+        },
+    )
+}
+
+/// Write a timestamped record of every chunk of output to a file descriptor (Unix only).
+/// Returns 0 on success, -1 on error, -2 on other platforms.
+///
+/// For replayable session transcripts: while enabled, each chunk of output `reader` takes
+/// from the PTY is also written to `fd` as one record, and reads go on as usual. A record
+/// is a 12-byte header followed by the data:
+///
+/// - bytes 0..8: when the reader took the chunk, in microseconds since the Unix epoch, as
+///   an unsigned little-endian integer;
+/// - bytes 8..12: the length of the data in bytes, as an unsigned little-endian integer;
+/// - then the data, exactly as read from the PTY, before transcoding or NUL handling.
+///
+/// When the output ends, a record with length 0 is written and logging stops. The time is
+/// when `reader` received the chunk, so it lags the program's write if the caller reads
+/// late. Each record is written with a single `write_all`; a failed write ends the logging
+/// without disturbing reads.
+///
+/// `fd` is duplicated and stays owned by the caller. A later call replaces the previous
+/// descriptor; `enabled` false stops logging and ignores `fd`.
+///
+/// # Safety
+///
+/// Caller must ensure:
+/// - `reader` is a valid, non-null handle obtained from `pty_get_reader` or `pty_reader_tap`.
+/// - `fd` is an open file descriptor when `enabled` is true.
+/// - No concurrent access to the reader handle.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn pty_reader_set_framed_log(
+    reader: ReaderHandle,
+    fd: i32,
+    enabled: bool,
+) -> i32 {
+    ffi_call(
+        "pty_reader_set_framed_log",
+        std::ptr::null_mut(),
+        || unsafe {
+            let reader_struct = handle_mut(reader)?;
+            if !enabled {
+                reader_struct.framed_log = None;
+                return Ok(0);
+            }
+            #[cfg(unix)]
+            {
+                if fd < 0 {
+                    return Err(PtyError::new(format!("Invalid file descriptor {fd}")));
+                }
+                let fd = std::os::fd::BorrowedFd::borrow_raw(fd).try_clone_to_owned()?;
+                reader_struct.framed_log = Some(File::from(fd));
+                Ok(0)
+            }
+            #[cfg(not(unix))]
+            {
+                let _ = (reader_struct, fd);
+                Err(PtyError::unsupported("pty_reader_set_framed_log"))
+            }
+        },
+    )
+}
+
+/// Create a second reader that receives a copy of everything this reader takes.
+/// Returns 0 on success, -1 on error; sets out_err_msg (caller frees via pty_free_err_msg).
+///
+/// From now on, all output `reader` takes from the PTY is also queued for the tap stored in
+/// `out_tap`, so one consumer can display the session live while another records a
+/// transcript, without the caller copying every chunk. Output is copied at the moment
+/// `reader` takes it, after any transcoding, including output `reader` discards; so the
+/// tap only sees what `reader` has read. The tap is an ordinary reader: free it with
+/// `pty_free_reader`, and it reports EOF once `reader` reaches EOF or is freed. A tap can
+/// be tapped again.
+///
+/// The tap never holds `reader` back. It keeps up to 64 KiB that has not been read yet and
+/// drops the oldest output beyond that; `pty_reader_set_drain_capacity` changes the limit and
+/// `pty_reader_take_since` reports the loss.
+///
+/// # Safety
+///
+/// Caller must ensure:
+/// - `reader` is a valid, non-null handle obtained from `pty_get_reader` or `pty_reader_tap`.
+/// - `out_tap` and `out_err_msg` are valid, non-null pointers to mutable memory.
+/// - The caller must free the tap handle using `pty_free_reader`.
+/// - The caller must free any error message using `pty_free_err_msg`.
+/// - No concurrent access to the reader handle.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn pty_reader_tap(
+    reader: ReaderHandle,
+    out_tap: *mut ReaderHandle,
+    out_err_msg: *mut *mut libc::c_char,
+) -> i32 {
+    ffi_call("pty_reader_tap", out_err_msg, || unsafe {
+        let reader_struct = handle_mut(reader)?;
+        let out_tap = out_ref(out_tap)?;
+        *out_tap = into_handle(reader_struct.tap());
+        Ok(0)
+    })
+}
+
+/// Read until EOF, passing every chunk of output to `cb` until it returns nonzero.
+/// Returns 0 at EOF, 1 when `cb` stopped the loop, -1 on error; sets out_err_msg (caller frees via pty_free_err_msg).
+///
+/// Invokes `cb(data, len, user_data)` on the calling thread for each chunk as it arrives,
+/// reusing one buffer, so a whole session costs a single call across the FFI boundary.
+/// `data` points into that buffer and is only valid until `cb` returns; copy what must be
+/// kept. Output that arrives after `cb` stops the loop stays in the reader. Blocks until
+/// EOF, an error, or a nonzero return from `cb`.
+///
+/// # Safety
+///
+/// Caller must ensure:
+/// - `reader` is a valid, non-null handle obtained from `pty_get_reader`.
+/// - `cb` is non-null and safe to call with `user_data` until this function returns.
+/// - `out_err_msg` is a valid, non-null pointer to mutable memory.
+/// - The caller must free any error message using `pty_free_err_msg`.
+/// - No concurrent access to the reader handle.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn pty_read_loop(
+    reader: ReaderHandle,
+    cb: Option<PtyReadCallback>,
+    user_data: *mut libc::c_void,
+    out_err_msg: *mut *mut libc::c_char,
+) -> i32 {
+    ffi_call("pty_read_loop", out_err_msg, || unsafe {
+        let reader_struct = handle_mut(reader)?;
+        let cb = cb.ok_or("Null pointer provided")?;
+        let mut chunk = [0u8; CHUNK_SIZE];
+        loop {
+            match reader_struct.read(&mut chunk) {
+                Ok(0) => return Ok(PTY_LOOP_EOF),
+                Ok(n) => {
+                    if cb(chunk.as_ptr(), n, user_data) != 0 {
+                        return Ok(PTY_LOOP_STOPPED);
+                    }
+                }
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+                Err(e) => return Err(e.into()),
+            }
+        }
+    })
+}
+
+/// The output buffer of `pty_read_to_end_cb`, in memory from the caller's allocator. Freed
+/// with the caller's `free` unless handed over with `into_raw`.
+struct CallerBuf {
+    ptr: *mut u8,
+    cap: usize,
+    len: usize,
+    alloc: PtyAllocCallback,
+    realloc: PtyReallocCallback,
+    free: PtyFreeCallback,
+    user_data: *mut libc::c_void,
+}
+
+impl CallerBuf {
+    /// Makes room for at least one more byte, doubling the buffer.
+    fn reserve(&mut self) -> PtyResult<()> {
+        if self.len < self.cap {
+            return Ok(());
+        }
+        let cap = self.cap.saturating_mul(2).max(CHUNK_SIZE);
+        let ptr = if self.ptr.is_null() {
+            (self.alloc)(cap, self.user_data)
+        } else {
+            (self.realloc)(self.ptr, cap, self.user_data)
+        };
+        if ptr.is_null() {
+            return Err(PtyError::new(format!(
+                "The allocator failed to provide {cap} bytes"
+            )));
+        }
+        self.ptr = ptr;
+        self.cap = cap;
+        Ok(())
+    }
+
+    /// The allocated bytes not holding output yet.
+    fn spare(&mut self) -> &mut [u8] {
+        unsafe { std::slice::from_raw_parts_mut(self.ptr.add(self.len), self.cap - self.len) }
+    }
+
+    fn into_raw(mut self) -> (*mut u8, usize) {
+        let ptr = std::mem::replace(&mut self.ptr, std::ptr::null_mut());
+        (ptr, self.len)
+    }
+}
+
+impl Drop for CallerBuf {
+    fn drop(&mut self) {
+        if !self.ptr.is_null() {
+            (self.free)(self.ptr, self.user_data);
+        }
+    }
+}
+
+/// Read until EOF into a buffer obtained from the caller's allocator.
+/// Returns 0 on success, -1 on error; sets out_err_msg (caller frees via pty_free_err_msg).
+///
+/// For embedders that must own all memory: the output is collected in a block from
+/// `alloc_cb(size, user_data)`, grown with `realloc_cb(ptr, size, user_data)`, which must
+/// keep the contents like C's `realloc`, and stored in `out_ptr` and `out_len`. The caller
+/// frees it with its own allocator; no library free function is involved. The block may
+/// be larger than `out_len`. Without any output nothing is allocated and `out_ptr` is
+/// null.
+///
+/// On error, including an allocator returning null, the block is released with
+/// `free_cb(ptr, user_data)`, `out_ptr` is null and `out_len` 0. The callbacks run on
+/// the calling thread. Blocks until the output ends, which for a PTY from `pty_open`
+/// requires the slave to be freed as well.
+///
+/// # Safety
+///
+/// Caller must ensure:
+/// - `reader` is a valid, non-null handle obtained from `pty_get_reader`.
+/// - `alloc_cb`, `realloc_cb` and `free_cb` are non-null and safe to call with `user_data` until this function returns.
+/// - `out_ptr`, `out_len` and `out_err_msg` are valid, non-null pointers to mutable memory.
+/// - The caller must free any error message using `pty_free_err_msg`.
+/// - No concurrent access to the reader handle.
+#[unsafe(no_mangle)]
+#[allow(clippy::too_many_arguments)]
+pub unsafe extern "C" fn pty_read_to_end_cb(
+    reader: ReaderHandle,
+    alloc_cb: Option<PtyAllocCallback>,
+    realloc_cb: Option<PtyReallocCallback>,
+    free_cb: Option<PtyFreeCallback>,
+    user_data: *mut libc::c_void,
+    out_ptr: *mut *mut u8,
+    out_len: *mut usize,
+    out_err_msg: *mut *mut libc::c_char,
+) -> i32 {
+    ffi_call("pty_read_to_end_cb", out_err_msg, || unsafe {
+        let reader_struct = handle_mut(reader)?;
+        let out_ptr = out_ref(out_ptr)?;
+        let out_len = out_ref(out_len)?;
+        *out_ptr = std::ptr::null_mut();
+        *out_len = 0;
+        let mut buf = CallerBuf {
+            ptr: std::ptr::null_mut(),
+            cap: 0,
+            len: 0,
+            alloc: alloc_cb.ok_or("Null pointer provided")?,
+            realloc: realloc_cb.ok_or("Null pointer provided")?,
+            free: free_cb.ok_or("Null pointer provided")?,
+            user_data,
+        };
+        // Output is taken straight into the caller's block, allocated once there is some.
+        while !reader_struct.pending.is_empty() || reader_struct.fill(None)? > 0 {
+            buf.reserve()?;
+            buf.len += reader_struct.take_pending(buf.spare());
+        }
+        (*out_ptr, *out_len) = buf.into_raw();
+        Ok(0)
+    })
+}
+
+/// Get the length of the next line, including its newline, without consuming it.
+/// Returns 0 if a line is available, 1 on timeout, 2 at EOF, -1 on error; sets out_err_msg (caller frees via pty_free_err_msg).
+///
+/// Waits at most `timeout_ms` for a newline (b'\n') to arrive, then stores the number of
+/// bytes up to and including it in `out_len`, so the caller can read exactly that many
+/// bytes next. The output stays in the reader. On timeout `out_len` is 0. At EOF it is the
+/// length of the unterminated last line, if any, and 0 once everything has been read. A
+/// `timeout_ms` of 0 only checks output that has already arrived. Output is buffered in
+/// memory until a newline arrives, so a program that never prints one grows the buffer.
+///
+/// # Safety
+///
+/// Caller must ensure:
+/// - `reader` is a valid, non-null handle obtained from `pty_get_reader`.
+/// - `out_len` and `out_err_msg` are valid, non-null pointers to mutable memory.
+/// - The caller must free any error message using `pty_free_err_msg`.
+/// - No concurrent access to the reader handle.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn pty_peek_line_len(
+    reader: ReaderHandle,
+    timeout_ms: u32,
+    out_len: *mut usize,
+    out_err_msg: *mut *mut libc::c_char,
+) -> i32 {
+    ffi_call("pty_peek_line_len", out_err_msg, || unsafe {
+        let reader_struct = handle_mut(reader)?;
+        let out_len = out_ref(out_len)?;
+        *out_len = 0;
+        let deadline = Instant::now() + Duration::from_millis(u64::from(timeout_ms));
+        match reader_struct.peek_until(Some(deadline), |pending, searched| {
+            find_after(pending, searched, b"\n")
+        }) {
+            Ok(Some(len)) => {
+                *out_len = len;
+                Ok(PTY_READ_FILLED)
+            }
+            Ok(None) => Ok(PTY_READ_TIMEOUT),
+            Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => {
+                *out_len = reader_struct.pending.len();
+                Ok(PTY_READ_EOF)
+            }
+            Err(e) => Err(e.into()),
+        }
+    })
+}
+
+/// Whether `sample` looks like binary data rather than text: it contains a NUL byte, or
+/// more than one byte in ten is a control character that text does not use. Tab, line
+/// feed, carriage return, vertical tab, form feed, backspace, bell and escape count as
+/// text, since terminal output is full of them.
+fn looks_binary(sample: &[u8]) -> bool {
+    let unusual = sample
+        .iter()
+        .filter(|&&b| (b < 0x20 && !b"\t\n\r\x0b\x0c\x08\x07\x1b".contains(&b)) || b == 0x7f)
+        .count();
+    sample.contains(&0) || unusual * 10 > sample.len()
+}
+
+/// Guess whether the output looks like binary data, without consuming it.
+/// Returns 0 on success, -1 on error; sets out_err_msg (caller frees via pty_free_err_msg).
+///
+/// Examines up to `sample_len` bytes of the output the next reads would return and stores
+/// 1 in `out_is_binary` if it looks binary and 0 if it looks like text, so a UI can switch
+/// to a hex view. If no output has arrived yet this waits for the first, like
+/// `pty_read`; beyond that it only looks at output already available. The heuristic is
+/// simple: a NUL byte, or more than one byte in ten being a control character other than
+/// tab, newline, carriage return, vertical tab, form feed, backspace, bell, escape, makes
+/// it binary. Invalid UTF-8 alone does not, so text in other encodings passes. The sample
+/// is taken after `pty_reader_set_encoding` and `pty_reader_set_nul_policy` transform the
+/// output. At end of file with nothing buffered the answer is 0.
+///
+/// # Safety
+///
+/// Caller must ensure:
+/// - `reader` is a valid, non-null handle obtained from `pty_get_reader`.
+/// - `out_is_binary` and `out_err_msg` are valid, non-null pointers to mutable memory.
+/// - The caller must free any error message using `pty_free_err_msg`.
+/// - No concurrent access to the reader handle.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn pty_reader_peek_is_binary(
+    reader: ReaderHandle,
+    sample_len: usize,
+    out_is_binary: *mut i32,
+    out_err_msg: *mut *mut libc::c_char,
+) -> i32 {
+    ffi_call("pty_reader_peek_is_binary", out_err_msg, || unsafe {
+        let reader_struct = handle_mut(reader)?;
+        let out_is_binary = out_ref(out_is_binary)?;
+        if sample_len == 0 {
+            return Err("sample_len must be at least 1".into());
+        }
+        if reader_struct.pending.is_empty() {
+            reader_struct.fill(None)?;
+        }
+        while reader_struct.pending.len() < sample_len {
+            match reader_struct.fill(Some(Instant::now())) {
+                Ok(0) => break,
+                Ok(_) => {}
+                Err(e) if e.kind() == io::ErrorKind::TimedOut => break,
+                Err(e) => return Err(e.into()),
+            }
+        }
+        let pending = reader_struct.pending.make_contiguous();
+        *out_is_binary = i32::from(looks_binary(&pending[..sample_len.min(pending.len())]));
+        Ok(0)
+    })
+}
+
+/// Get the number of bytes the reader holds that have not been read yet.
+///
+/// Output the reader has already taken from the PTY, in its own buffer or the queue of its
+/// pump thread (started by the first timed read), can be read without waiting, and polling
+/// the master fd does not see it. Drain this much before waiting on the fd. Queued output
+/// is counted before transcoding, so with `pty_reader_set_encoding` the bytes eventually
+/// read can differ slightly. Returns 0 when nothing is buffered, as with plain reads only,
+/// and for an invalid handle.
+///
+/// # Safety
+///
+/// Caller must ensure:
+/// - `reader` is a valid, non-null handle obtained from `pty_get_reader` or `pty_reader_tap`.
+/// - No concurrent access to the reader handle.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn pty_reader_buffered_len(reader: ReaderHandle) -> usize {
+    unsafe { handle_mut(reader) }.map_or(0, |reader_struct| reader_struct.buffered_len())
+}
+
+/// Get the most output this reader's background queue has held at once, in bytes.
+///
+/// A pump thread fills the queue from the PTY and reads empty it. A reader gets one with
+/// its first timed read, from `pty_open_ex` with `large_buffers`, from an output log or
+/// once it drains; taps have one of their own. A high-water mark near the queue's
+/// capacity (64 KiB, 4 MiB with `large_buffers`, or what `pty_reader_set_drain_capacity`
+/// set) means the caller fell behind the program: the pump stopped reading and the
+/// program blocked on a full PTY, or for a draining reader or a tap, output was dropped.
+/// A low one means the caller keeps up and a larger buffer would not help. Kept for the
+/// life of the queue; readers sharing a master's queue see the same value. Returns 0 for
+/// a reader without a queue, which reads the PTY directly, and for an invalid handle.
+///
+/// # Safety
+///
+/// Caller must ensure:
+/// - `reader` is a valid, non-null handle obtained from `pty_get_reader` or `pty_reader_tap`.
+/// - No concurrent access to the reader handle.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn pty_reader_high_water(reader: ReaderHandle) -> usize {
+    unsafe { handle_mut(reader) }.map_or(0, |reader_struct| reader_struct.high_water())
+}
+
+/// Turn tracking of the directory the shell reports through OSC 7 on or off.
+/// Returns 0 on success, -1 on error.
+///
+/// While enabled, output read through this reader is watched for OSC 7 sequences
+/// (`ESC ] 7 ; file://host/path ST`), and the latest path can be fetched with
+/// `pty_get_tracked_cwd`. Only shells configured to emit OSC 7 report anything: zsh and
+/// fish on many distributions, bash through a `PROMPT_COMMAND`. The shell reports its own
+/// directory, usually at each prompt, so a `cd` in a running program is not seen.
+///
+/// # Safety
+///
+/// Caller must ensure:
+/// - `reader` is a valid, non-null handle obtained from `pty_get_reader`.
+/// - No concurrent access to the reader handle.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn pty_enable_cwd_tracking(reader: ReaderHandle, enabled: bool) -> i32 {
+    ffi_call("pty_enable_cwd_tracking", std::ptr::null_mut(), || unsafe {
+        handle_mut(reader)?.osc.set_track_cwd(enabled);
+        Ok(0)
+    })
+}
+
+/// Call `cb` with the payload of every OSC sequence with the given code in the output.
+/// Returns 0 on success, -1 on error.
+///
+/// For programs that talk to their host through private escape sequences, e.g. a TUI
+/// announcing events with `ESC ] 7777 ; payload BEL`. Output read through this reader is
+/// scanned for OSC sequences (`ESC ]`, the 7-bit form only) whose code, the digits before
+/// the first `;`, equals `code`; `cb(code, payload, len, user_data)` then receives the
+/// bytes after that `;`, empty if there is none, without the terminator. Both terminators
+/// count: BEL, which most programs send, and ST (`ESC \`). A sequence cut short by any
+/// other escape sequence is dropped, and so is one longer than 4 KiB. Sequences split
+/// across reads are reassembled. The sequences stay in the output, so a caller that
+/// displays it passes them on to its terminal, which ignores codes it does not know.
+///
+/// `cb` runs on the thread reading, inside whichever call takes the output from the PTY,
+/// before that call returns; it must not call back into this reader. One callback per
+/// code: a later call replaces it, and a null `cb` stops watching `code`. Taps and other
+/// readers of the same PTY do not share watches.
+///
+/// # Safety
+///
+/// Caller must ensure:
+/// - `reader` is a valid, non-null handle obtained from `pty_get_reader`.
+/// - `cb`, if non-null, is safe to call with `user_data` from any thread reading through the reader, until the watch is replaced or the reader is freed.
+/// - No concurrent access to the reader handle.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn pty_reader_watch_osc(
+    reader: ReaderHandle,
+    code: u32,
+    cb: Option<PtyOscCallback>,
+    user_data: *mut libc::c_void,
+) -> i32 {
+    ffi_call("pty_reader_watch_osc", std::ptr::null_mut(), || unsafe {
+        handle_mut(reader)?.osc.watch(code, cb, user_data);
+        Ok(0)
+    })
+}
+
+/// Get the working directory the shell last reported through OSC 7.
+/// Returns 0 on success, 1 if no directory has been reported, -1 on error.
+///
+/// Copies the path into `buf` with a terminating NUL and stores its length in `out_len`.
+/// If `len` is too small the call fails but `out_len` still receives the length, so the
+/// caller can retry with `out_len + 1` bytes. Percent-escapes in the reported URL are
+/// decoded; the host part is dropped. Requires `pty_enable_cwd_tracking`.
+///
+/// # Safety
+///
+/// Caller must ensure:
+/// - `reader` is a valid, non-null handle obtained from `pty_get_reader`.
+/// - `buf` is a valid, non-null pointer to mutable memory of at least `len` bytes.
+/// - `out_len` is a valid, non-null pointer to mutable memory.
+/// - No concurrent access to the reader handle.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn pty_get_tracked_cwd(
+    reader: ReaderHandle,
+    buf: *mut u8,
+    len: usize,
+    out_len: *mut usize,
+) -> i32 {
+    ffi_call("pty_get_tracked_cwd", std::ptr::null_mut(), || unsafe {
+        let reader_struct = handle_mut(reader)?;
+        let Some(cwd) = reader_struct.osc.session().cwd() else {
+            *out_ref(out_len)? = 0;
+            return Ok(1);
+        };
+        copy_str_out(&cwd, buf, len, out_len)?;
+        Ok(0)
+    })
+}
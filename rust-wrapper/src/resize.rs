@@ -0,0 +1,159 @@
+//! Noticing size changes made to a PTY from elsewhere.
+//!
+//! Only the slave side's foreground process group receives SIGWINCH, so the master cannot
+//! wait for a signal. Instead a background thread polls the size through its own copy of
+//! the master fd and reports every change.
+
+use crate::MasterHandle;
+#[cfg(not(unix))]
+use crate::error::PtyError;
+use crate::error::ffi_call;
+use crate::handle::handle_mut;
+#[cfg(unix)]
+use std::os::fd::{AsRawFd, OwnedFd, RawFd};
+use std::sync::mpsc;
+#[cfg(unix)]
+use std::sync::mpsc::RecvTimeoutError;
+use std::thread::{self, JoinHandle};
+#[cfg(unix)]
+use std::time::Duration;
+
+/// How often the watch thread compares the size.
+#[cfg(unix)]
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Receives the new size of a PTY.
+pub type PtyResizeCallback = extern "C" fn(rows: u16, cols: u16, user_data: *mut libc::c_void);
+
+/// The callback and its user data, moved to the watch thread.
+struct Notify {
+    cb: PtyResizeCallback,
+    user_data: *mut libc::c_void,
+}
+
+// The caller guarantees `user_data` may be used from the watch thread.
+unsafe impl Send for Notify {}
+
+impl Notify {
+    fn call(&self, rows: u16, cols: u16) {
+        (self.cb)(rows, cols, self.user_data);
+    }
+}
+
+/// A running size watch. Dropping it stops the thread.
+pub(crate) struct ResizeWatch {
+    stop: Option<mpsc::Sender<()>>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl ResizeWatch {
+    #[cfg(unix)]
+    fn start(fd: OwnedFd, notify: Notify) -> std::io::Result<Self> {
+        let (stop, stopped) = mpsc::channel::<()>();
+        let mut last = size(fd.as_raw_fd());
+        let thread = thread::Builder::new()
+            .name("pty-on-resize".into())
+            .spawn(move || {
+                while let Err(RecvTimeoutError::Timeout) = stopped.recv_timeout(POLL_INTERVAL) {
+                    let current = size(fd.as_raw_fd());
+                    if let Some((rows, cols)) = current
+                        && current != last
+                    {
+                        notify.call(rows, cols);
+                    }
+                    last = current;
+                }
+            })?;
+        Ok(ResizeWatch {
+            stop: Some(stop),
+            thread: Some(thread),
+        })
+    }
+}
+
+impl Drop for ResizeWatch {
+    fn drop(&mut self) {
+        drop(self.stop.take());
+        // Waiting for the thread guarantees no callback runs after the watch is gone, except
+        // when the callback itself cancels the watch.
+        if let Some(thread) = self.thread.take()
+            && thread.thread().id() != thread::current().id()
+        {
+            let _ = thread.join();
+        }
+    }
+}
+
+/// Rows and columns of the terminal behind `fd`.
+#[cfg(unix)]
+fn size(fd: RawFd) -> Option<(u16, u16)> {
+    let mut size: libc::winsize = unsafe { std::mem::zeroed() };
+    (unsafe { libc::ioctl(fd, libc::TIOCGWINSZ, &mut size) } == 0)
+        .then_some((size.ws_row, size.ws_col))
+}
+
+/// Call `cb` whenever the size of the PTY changes (Unix only).
+/// Returns 0 on success, -1 on error, -2 on other platforms; sets out_err_msg (caller frees via pty_free_err_msg).
+///
+/// A background thread compares the size every 100 ms and invokes
+/// `cb(rows, cols, user_data)` with the new size when it differs from the last one seen, so
+/// resizes made by another component holding the PTY are noticed without the caller
+/// polling. Resizes made through this handle are reported too. Changes that are
+/// undone within one interval may be missed. A later call replaces the previous callback.
+///
+/// `cb` runs on the watch thread, not the caller's, so it must be thread-safe (from Bun, a
+/// `JSCallback` created with `threadsafe: true`). The watch ends with `pty_cancel_on_resize`
+/// or when the master is freed; after either returns, `cb` is not called again.
+///
+/// # Safety
+///
+/// Caller must ensure:
+/// - `master` is a valid, non-null handle obtained from `pty_open_and_spawn` or `pty_open`.
+/// - `cb` is non-null and safe to call with `user_data` from another thread until the watch ends.
+/// - `out_err_msg` is a valid, non-null pointer to mutable memory.
+/// - The caller must free any error message using `pty_free_err_msg`.
+/// - No concurrent access to the master handle.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn pty_on_resize(
+    master: MasterHandle,
+    cb: Option<PtyResizeCallback>,
+    user_data: *mut libc::c_void,
+    out_err_msg: *mut *mut libc::c_char,
+) -> i32 {
+    ffi_call("pty_on_resize", out_err_msg, || unsafe {
+        let master_struct = handle_mut(master)?;
+        let cb = cb.ok_or("Null pointer provided")?;
+        #[cfg(unix)]
+        {
+            master_struct.resize_watch = None;
+            let fd =
+                std::os::fd::BorrowedFd::borrow_raw(master_struct.fd()?).try_clone_to_owned()?;
+            master_struct.resize_watch = Some(ResizeWatch::start(fd, Notify { cb, user_data })?);
+            Ok(0)
+        }
+        #[cfg(not(unix))]
+        {
+            let _ = (master_struct, cb, user_data);
+            Err(PtyError::unsupported("pty_on_resize"))
+        }
+    })
+}
+
+/// Stop calling the callback registered with `pty_on_resize`.
+/// Returns 0 on success, -1 on error.
+///
+/// Waits for a callback in progress to finish, unless called from within the callback.
+/// Does nothing if no callback is registered.
+///
+/// # Safety
+///
+/// Caller must ensure:
+/// - `master` is a valid, non-null handle obtained from `pty_open_and_spawn` or `pty_open`.
+/// - No concurrent access to the master handle.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn pty_cancel_on_resize(master: MasterHandle) -> i32 {
+    ffi_call("pty_cancel_on_resize", std::ptr::null_mut(), || unsafe {
+        handle_mut(master)?.resize_watch = None;
+        Ok(0)
+    })
+}
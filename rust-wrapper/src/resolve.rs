@@ -0,0 +1,169 @@
+//! Locating programs: the user's default shell and PATH lookups.
+
+use crate::error::{PtyError, PtyResult, ffi_call};
+use crate::{copy_str_out, str_from_ptr};
+use std::ffi::{OsStr, OsString};
+use std::path::{Component, Path, PathBuf};
+
+/// The shell a default-program spawn would start.
+pub(crate) fn default_shell() -> String {
+    #[cfg(unix)]
+    {
+        // $SHELL if it is executable, else the password database, else /bin/sh.
+        portable_pty::CommandBuilder::new_default_prog().get_shell()
+    }
+    #[cfg(windows)]
+    {
+        std::env::var("ComSpec")
+            .ok()
+            .filter(|shell| !shell.is_empty())
+            .unwrap_or_else(|| "cmd.exe".to_string())
+    }
+}
+
+/// Finds the executable for `program`. Names with a directory part are taken relative to
+/// `cwd`; bare names are looked up in the directories of `path` (relative entries are
+/// relative to `cwd` too). On Windows each candidate is also tried with the `PATHEXT`
+/// extensions. Fails with `PTY_ERR_NOT_FOUND` if no candidate exists.
+pub(crate) fn resolve_program(
+    program: &OsStr,
+    cwd: &Path,
+    path: Option<&OsStr>,
+) -> PtyResult<PathBuf> {
+    let display = Path::new(program).display();
+    if Path::new(program).components().count() > 1 {
+        // Drop `.` components so "./tool" resolves to "<cwd>/tool".
+        let relative: PathBuf = Path::new(program)
+            .components()
+            .filter(|c| *c != Component::CurDir)
+            .collect();
+        let base = cwd.join(relative);
+        return match candidates(&base)
+            .into_iter()
+            .find_map(|candidate| check(&candidate).map(|r| (candidate, r)))
+        {
+            Some((candidate, Ok(()))) => Ok(candidate),
+            Some((_, Err(reason))) => {
+                Err(format!("Unable to spawn {display} because {reason}").into())
+            }
+            None => Err(PtyError::not_found(format!(
+                "Unable to spawn {display} because it does not exist"
+            ))),
+        };
+    }
+    let path = path.ok_or_else(|| {
+        PtyError::not_found(format!("Unable to spawn {display}: PATH is not set"))
+    })?;
+    std::env::split_paths(path)
+        .flat_map(|dir| candidates(&cwd.join(dir).join(program)))
+        .find(|candidate| matches!(check(candidate), Some(Ok(()))))
+        .ok_or_else(|| PtyError::not_found(format!("Unable to spawn {display}: not found in PATH")))
+}
+
+/// Paths to try for `base`: itself, plus each `PATHEXT` extension on Windows.
+fn candidates(base: &Path) -> Vec<PathBuf> {
+    let mut paths = vec![base.to_path_buf()];
+    if cfg!(windows) {
+        let exts =
+            std::env::var_os("PATHEXT").unwrap_or_else(|| OsString::from(".COM;.EXE;.BAT;.CMD"));
+        for ext in exts
+            .to_string_lossy()
+            .split(';')
+            .filter(|ext| !ext.is_empty())
+        {
+            let mut path = base.as_os_str().to_owned();
+            path.push(ext);
+            paths.push(PathBuf::from(path));
+        }
+    }
+    paths
+}
+
+/// `None` if nothing exists at `path`, otherwise whether it can be executed.
+fn check(path: &Path) -> Option<Result<(), &'static str>> {
+    if !path.exists() {
+        return None;
+    }
+    if path.is_dir() {
+        return Some(Err("it is a directory"));
+    }
+    #[cfg(unix)]
+    {
+        let Ok(c_path) = std::ffi::CString::new(path.as_os_str().as_encoded_bytes()) else {
+            return Some(Err("it contains a NUL byte"));
+        };
+        if unsafe { libc::access(c_path.as_ptr(), libc::X_OK) } != 0 {
+            return Some(Err("it is not executable"));
+        }
+    }
+    Some(Ok(()))
+}
+
+/// Get the path of the user's default shell.
+/// Returns 0 on success, -1 if `buf` is too small or a pointer is null.
+///
+/// On Unix this is `$SHELL` if it names an executable, else the login shell from the
+/// password database, else `/bin/sh`. On Windows it is `%ComSpec%`, or `cmd.exe` if that
+/// is unset or empty. These are the same rules spawning without a program uses.
+///
+/// The path is stored in `buf` with a terminating NUL and `out_len` receives its length
+/// without the NUL. If `buf_len` is too small, `out_len` still receives the length so
+/// the caller can retry with a buffer of `out_len + 1` bytes.
+///
+/// # Safety
+///
+/// Caller must ensure:
+/// - `buf` is a valid pointer to mutable memory of at least `buf_len` bytes (it may be null if `buf_len` is 0).
+/// - `out_len` is a valid, non-null pointer to mutable memory.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn pty_default_shell(
+    buf: *mut u8,
+    buf_len: usize,
+    out_len: *mut usize,
+) -> i32 {
+    ffi_call("pty_default_shell", std::ptr::null_mut(), || unsafe {
+        copy_str_out(&default_shell(), buf, buf_len, out_len)?;
+        Ok(0)
+    })
+}
+
+/// Resolve `prog` to the absolute path of the executable a spawn would run.
+/// Returns 0 on success, -3 if no such executable exists, -1 on other errors; sets out_err_msg (caller frees via pty_free_err_msg).
+///
+/// Performs the same lookup as spawning, against this process's `PATH` and working
+/// directory: names containing a path separator are taken relative to the working
+/// directory, bare names are searched in `PATH` (with `PATHEXT` on Windows). Passing the
+/// result to spawn instead of the bare name skips the search there, so the program
+/// that runs is the one that was checked.
+///
+/// The path is stored in `buf` with a terminating NUL and `out_len` receives its length
+/// without the NUL. If `buf_len` is too small, `out_len` still receives the length so
+/// the caller can retry with a buffer of `out_len + 1` bytes.
+///
+/// # Safety
+///
+/// Caller must ensure:
+/// - `prog` is a valid, non-null pointer to a null-terminated C string.
+/// - `buf` is a valid pointer to mutable memory of at least `buf_len` bytes (it may be null if `buf_len` is 0).
+/// - `out_len` and `out_err_msg` are valid, non-null pointers to mutable memory.
+/// - The caller must free any error message using `pty_free_err_msg`.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn pty_resolve_executable(
+    prog: *const libc::c_char,
+    buf: *mut u8,
+    buf_len: usize,
+    out_len: *mut usize,
+    out_err_msg: *mut *mut libc::c_char,
+) -> i32 {
+    ffi_call("pty_resolve_executable", out_err_msg, || unsafe {
+        let prog = str_from_ptr(prog)?;
+        let cwd = std::env::current_dir()?;
+        let path = std::env::var_os("PATH");
+        let resolved = resolve_program(OsStr::new(&prog), &cwd, path.as_deref())?;
+        let resolved = resolved
+            .to_str()
+            .ok_or("Resolved path is not valid UTF-8")?;
+        copy_str_out(resolved, buf, buf_len, out_len)?;
+        Ok(0)
+    })
+}
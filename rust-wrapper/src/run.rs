@@ -0,0 +1,549 @@
+//! One-shot execution: spawn a command, drain its output, wait for it, and free everything.
+
+use crate::builder::{Builder, StdioTarget};
+use crate::error::{PTY_ERR_NO_OUTPUT, PTY_ERR_OUTPUT_TOO_LARGE, PtyError, PtyResult, ffi_call};
+use crate::handle::handle_mut;
+use crate::reader::Reader;
+use crate::{BuilderHandle, buf_mut, buf_ref, exit_code, out_ref};
+use portable_pty::ChildKiller;
+use std::io::{self, PipeReader, PipeWriter, Read, Write};
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Receives a chunk of output. `data` is only valid for the duration of the call.
+pub type PtySinkCallback = extern "C" fn(data: *const u8, len: usize, user_data: *mut libc::c_void);
+
+/// Limits `run` enforces on a command.
+#[derive(Default)]
+pub(crate) struct Limits {
+    /// Bytes of output passed to the sink; 0 for no limit. The rest is read and discarded
+    /// so the child does not block on a full PTY.
+    pub(crate) max_output: usize,
+    /// Kill the child as soon as `max_output` is passed.
+    pub(crate) kill_on_limit: bool,
+    /// Kill the child if it has not exited by then.
+    pub(crate) timeout: Option<Duration>,
+    /// Kill the child if it has not produced any output by then.
+    pub(crate) first_byte_timeout: Option<Duration>,
+}
+
+/// How a command run by `run` ended.
+pub(crate) struct Outcome {
+    pub(crate) exit_code: i32,
+    /// Output was cut short by `Limits::max_output`.
+    pub(crate) truncated: bool,
+    /// The child was killed by `Limits::timeout`.
+    pub(crate) timed_out: bool,
+    /// The child was killed by `Limits::first_byte_timeout`.
+    pub(crate) no_output: bool,
+}
+
+/// What the timer hears about while the command runs.
+enum Event {
+    /// The first output arrived.
+    Output,
+    /// The child was reaped.
+    Exited,
+}
+
+/// Which limit, if any, the timer killed the child for.
+#[derive(Clone, Copy, PartialEq)]
+enum Expired {
+    No,
+    Timeout,
+    FirstByte,
+}
+
+/// Input `run` writes to the child's stdin, a pipe, before closing it.
+pub(crate) struct Input {
+    /// The child's end; the parent's copy is closed right after the spawn, so that the
+    /// writer gets EPIPE instead of blocking if the child exits without reading.
+    stdin: PipeReader,
+    feed: PipeWriter,
+    data: Vec<u8>,
+}
+
+impl Input {
+    /// A pipe for `data`, and `builder` changed to spawn with it as stdin.
+    #[cfg(unix)]
+    fn new(builder: &Builder, data: &[u8]) -> PtyResult<(Builder, Input)> {
+        use std::os::fd::AsRawFd;
+        let (stdin, feed) = io::pipe()?;
+        let mut builder = builder.clone();
+        builder.stdio[0] = StdioTarget::Fd(stdin.as_raw_fd());
+        let input = Input {
+            stdin,
+            feed,
+            data: data.to_vec(),
+        };
+        Ok((builder, input))
+    }
+
+    /// Writes the data on a thread of its own, so a child that writes before reading all of
+    /// it cannot deadlock against the caller, and closes the pipe when done.
+    fn start(self) -> thread::JoinHandle<io::Result<()>> {
+        drop(self.stdin);
+        let mut feed = self.feed;
+        let data = self.data;
+        thread::spawn(move || {
+            // A write to a pipe nobody reads raises SIGPIPE, which would kill the host. With
+            // it blocked the write fails with EPIPE instead, and the pending signal is
+            // discarded when this thread exits.
+            #[cfg(unix)]
+            unsafe {
+                let mut set: libc::sigset_t = std::mem::zeroed();
+                libc::sigemptyset(&mut set);
+                libc::sigaddset(&mut set, libc::SIGPIPE);
+                libc::pthread_sigmask(libc::SIG_BLOCK, &set, std::ptr::null_mut());
+            }
+            match feed.write_all(&data) {
+                // The child exited or closed stdin without reading everything, like the
+                // command on the right of a shell pipe may.
+                Err(e) if e.kind() == io::ErrorKind::BrokenPipe => Ok(()),
+                result => result,
+            }
+        })
+    }
+}
+
+/// Kills the child when dropped while armed, so an early return or a panic in the sink
+/// does not leave it running.
+struct KillGuard {
+    killer: Box<dyn ChildKiller + Send + Sync>,
+    armed: bool,
+}
+
+impl Drop for KillGuard {
+    fn drop(&mut self) {
+        if self.armed {
+            let _ = self.killer.kill();
+        }
+    }
+}
+
+/// Kills the child and, on Unix, the rest of its process group, which the child leads, so
+/// background jobs holding the PTY open do not keep the output from ending.
+fn kill_all(killer: &mut dyn ChildKiller, pid: Option<u32>) {
+    #[cfg(unix)]
+    if let Some(pid) = pid {
+        unsafe { libc::killpg(pid as libc::pid_t, libc::SIGKILL) };
+    }
+    #[cfg(not(unix))]
+    let _ = pid;
+    let _ = killer.kill();
+}
+
+/// Spawns the builder's command, passes every chunk of output to `sink`, and reports how
+/// the command ended once the child has exited and its output is drained.
+///
+/// Reading happens on the calling thread while a helper thread waits for the child. Once
+/// the child exits the helper drops the master: on Windows, ConPTY only reports EOF to the
+/// reader after the pseudoconsole is closed, so reading and waiting on one thread would
+/// deadlock (as would waiting before reading, once the PTY buffer fills up). With a
+/// timeout or a first-byte timeout, a second helper kills the child when one expires.
+/// With `input`, a third helper writes it to the child's stdin.
+pub(crate) fn run(
+    builder: &Builder,
+    limits: &Limits,
+    input: Option<Input>,
+    mut sink: impl FnMut(&[u8]),
+) -> PtyResult<Outcome> {
+    // Nothing would read it, so the command could block on a full pipe.
+    if builder.stdio.contains(&StdioTarget::Pipe) {
+        return Err("A stderr pipe needs a reader; spawn with pty_builder_spawn instead".into());
+    }
+    let (master, mut child) = builder.spawn()?;
+    let feeder = input.map(Input::start);
+    let mut reader: Box<dyn Read> = match &master.log_pump {
+        Some(pump) => Box::new(Reader::shared(pump.clone(), master.session.clone())),
+        None => master.inner.try_clone_reader()?,
+    };
+    let pid = child.inner.process_id();
+    let mut guard = KillGuard {
+        killer: child.inner.clone_killer(),
+        armed: true,
+    };
+    // The waiter reports the child reaped, which stops the timer, and the reading loop
+    // reports the first output, which ends the first-byte timeout.
+    let (done, events) = mpsc::channel();
+    let mut first_output = Some(done.clone());
+    let timer = (limits.timeout.is_some() || limits.first_byte_timeout.is_some()).then(|| {
+        let mut killer = guard.killer.clone_killer();
+        let start = Instant::now();
+        let timeout = limits.timeout;
+        let mut first_byte_timeout = limits.first_byte_timeout;
+        thread::spawn(move || {
+            loop {
+                let Some(limit) = first_byte_timeout.into_iter().chain(timeout).min() else {
+                    return Expired::No;
+                };
+                match events.recv_timeout(limit.saturating_sub(start.elapsed())) {
+                    Ok(Event::Output) => first_byte_timeout = None,
+                    Ok(Event::Exited) | Err(RecvTimeoutError::Disconnected) => {
+                        return Expired::No;
+                    }
+                    Err(RecvTimeoutError::Timeout) => {
+                        kill_all(&mut *killer, pid);
+                        return if first_byte_timeout == Some(limit) {
+                            Expired::FirstByte
+                        } else {
+                            Expired::Timeout
+                        };
+                    }
+                }
+            }
+        })
+    });
+    let waiter = thread::spawn(move || {
+        let status = child.wait();
+        let _ = done.send(Event::Exited);
+        drop(master);
+        status
+    });
+
+    let mut chunk = [0u8; 4096];
+    let mut room = if limits.max_output == 0 {
+        usize::MAX
+    } else {
+        limits.max_output
+    };
+    let mut truncated = false;
+    let read_result = loop {
+        let read = reader.read(&mut chunk);
+        if matches!(read, Ok(n) if n > 0)
+            && let Some(first_output) = first_output.take()
+        {
+            let _ = first_output.send(Event::Output);
+        }
+        match read {
+            Ok(0) => break Ok(()),
+            Ok(_) if truncated => {}
+            Ok(n) if n > room => {
+                if room > 0 {
+                    sink(&chunk[..room]);
+                }
+                truncated = true;
+                if limits.kill_on_limit {
+                    let _ = guard.killer.kill();
+                }
+            }
+            Ok(n) => {
+                sink(&chunk[..n]);
+                room -= n;
+            }
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+            Err(e) => break Err(PtyError::from(e)),
+        }
+    };
+    drop(first_output);
+    if read_result.is_err() {
+        // Make sure the waiter can finish before bailing out.
+        let _ = guard.killer.kill();
+    }
+    let status = waiter.join().map_err(|_| "wait thread panicked")?;
+    // The child is reaped; its pid may be reused from here on.
+    guard.armed = false;
+    let expired = match timer {
+        Some(timer) => timer.join().map_err(|_| "timer thread panicked")?,
+        None => Expired::No,
+    };
+    read_result?;
+    // A writer still blocked means something outside the child, such as a background job
+    // it started, holds stdin open without reading; leave it rather than hang.
+    if let Some(feeder) = feeder.filter(|feeder| feeder.is_finished()) {
+        feeder.join().map_err(|_| "input thread panicked")??;
+    }
+    Ok(Outcome {
+        exit_code: exit_code(&status?),
+        truncated,
+        timed_out: expired == Expired::Timeout,
+        no_output: expired == Expired::FirstByte,
+    })
+}
+
+/// Run the builder's command to completion, streaming its output to `sink_cb`.
+/// Returns 0 on success, -1 on error; sets out_err_msg (caller frees via pty_free_err_msg).
+///
+/// Opens a PTY, spawns the command, invokes `sink_cb(data, len, user_data)` for every chunk
+/// of output, waits for the child, and frees all handles before returning. `sink_cb` may be
+/// null to discard output; it is always invoked on the calling thread. On success
+/// `exit_code_out` receives the child's exit code. Prefer the granular API for interactive
+/// sessions.
+///
+/// # Safety
+///
+/// Caller must ensure:
+/// - `opts` is a valid, non-null handle obtained from `pty_builder_new`.
+/// - `sink_cb`, if non-null, is safe to call with `user_data` until this function returns.
+/// - `exit_code_out` and `out_err_msg` are valid, non-null pointers to mutable memory.
+/// - The caller must free any error message using `pty_free_err_msg`.
+/// - No concurrent mutation of the builder handle.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn pty_run_capture(
+    opts: BuilderHandle,
+    sink_cb: Option<PtySinkCallback>,
+    user_data: *mut libc::c_void,
+    exit_code_out: *mut i32,
+    out_err_msg: *mut *mut libc::c_char,
+) -> i32 {
+    ffi_call("pty_run_capture", out_err_msg, || unsafe {
+        let builder = handle_mut(opts)?;
+        let exit_code_out = out_ref(exit_code_out)?;
+        let outcome = run(builder, &Limits::default(), None, |data| {
+            if let Some(cb) = sink_cb {
+                cb(data.as_ptr(), data.len(), user_data);
+            }
+        })?;
+        *exit_code_out = outcome.exit_code;
+        Ok(0)
+    })
+}
+
+/// Run the builder's command to completion like `pty_run_capture`, capping the output.
+/// Returns 0 on success, -1 on error, -5 if the output exceeded `max_output_bytes`; sets out_err_msg (caller frees via pty_free_err_msg).
+///
+/// Only the first `max_output_bytes` bytes of output are passed to `sink_cb`; a value of 0
+/// means no limit. Past the limit the remaining output is discarded while the command runs
+/// on, unless `kill_on_limit` is set, in which case the child is killed. Either way the
+/// function still waits for the child, and `exit_code_out` receives its exit code on -5 as
+/// well as on success. Use this for untrusted or buggy commands that might produce
+/// unbounded output.
+///
+/// # Safety
+///
+/// Caller must ensure:
+/// - `opts` is a valid, non-null handle obtained from `pty_builder_new`.
+/// - `sink_cb`, if non-null, is safe to call with `user_data` until this function returns.
+/// - `exit_code_out` and `out_err_msg` are valid, non-null pointers to mutable memory.
+/// - The caller must free any error message using `pty_free_err_msg`.
+/// - No concurrent mutation of the builder handle.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn pty_run_capture_limited(
+    opts: BuilderHandle,
+    sink_cb: Option<PtySinkCallback>,
+    user_data: *mut libc::c_void,
+    max_output_bytes: u64,
+    kill_on_limit: bool,
+    exit_code_out: *mut i32,
+    out_err_msg: *mut *mut libc::c_char,
+) -> i32 {
+    ffi_call("pty_run_capture_limited", out_err_msg, || unsafe {
+        let builder = handle_mut(opts)?;
+        let exit_code_out = out_ref(exit_code_out)?;
+        let limits = Limits {
+            max_output: usize::try_from(max_output_bytes).unwrap_or(usize::MAX),
+            kill_on_limit,
+            timeout: None,
+            first_byte_timeout: None,
+        };
+        let outcome = run(builder, &limits, None, |data| {
+            if let Some(cb) = sink_cb {
+                cb(data.as_ptr(), data.len(), user_data);
+            }
+        })?;
+        *exit_code_out = outcome.exit_code;
+        if outcome.truncated {
+            return Err(PtyError::with_code(
+                PTY_ERR_OUTPUT_TOO_LARGE,
+                format!("Output exceeded {max_output_bytes} bytes"),
+            ));
+        }
+        Ok(0)
+    })
+}
+
+/// Run the builder's command to completion like `pty_run_capture`, requiring it to start
+/// producing output in time.
+/// Returns 0 on success, -1 on error, -8 if there was no output within `first_byte_timeout_ms`; sets out_err_msg (caller frees via pty_free_err_msg).
+///
+/// If the command has not written a single byte `first_byte_timeout_ms` milliseconds after
+/// the spawn, it and its process group are killed and the function returns -8 once the
+/// child is reaped; 0 means no limit. This tells a command that hangs at startup, e.g.
+/// on a lock, a network mount or a prompt nobody answers, from one that is working but
+/// slow: once the first byte arrives the command may run as long as it needs. Use it to
+/// probe whether a command is responsive at all before waiting for it. A command that
+/// exits without output before the limit is not killed and the function returns 0.
+/// `exit_code_out` receives the exit code on -8 as well as on success, which after the
+/// kill is that of the killed child.
+///
+/// # Safety
+///
+/// Caller must ensure:
+/// - `opts` is a valid, non-null handle obtained from `pty_builder_new`.
+/// - `sink_cb`, if non-null, is safe to call with `user_data` until this function returns.
+/// - `exit_code_out` and `out_err_msg` are valid, non-null pointers to mutable memory.
+/// - The caller must free any error message using `pty_free_err_msg`.
+/// - No concurrent mutation of the builder handle.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn pty_run_capture_first_byte(
+    opts: BuilderHandle,
+    sink_cb: Option<PtySinkCallback>,
+    user_data: *mut libc::c_void,
+    first_byte_timeout_ms: u32,
+    exit_code_out: *mut i32,
+    out_err_msg: *mut *mut libc::c_char,
+) -> i32 {
+    ffi_call("pty_run_capture_first_byte", out_err_msg, || unsafe {
+        let builder = handle_mut(opts)?;
+        let exit_code_out = out_ref(exit_code_out)?;
+        let limits = Limits {
+            first_byte_timeout: (first_byte_timeout_ms > 0)
+                .then(|| Duration::from_millis(u64::from(first_byte_timeout_ms))),
+            ..Limits::default()
+        };
+        let outcome = run(builder, &limits, None, |data| {
+            if let Some(cb) = sink_cb {
+                cb(data.as_ptr(), data.len(), user_data);
+            }
+        })?;
+        *exit_code_out = outcome.exit_code;
+        if outcome.no_output {
+            return Err(PtyError::with_code(
+                PTY_ERR_NO_OUTPUT,
+                format!("No output within {first_byte_timeout_ms} ms; the command was killed"),
+            ));
+        }
+        Ok(0)
+    })
+}
+
+/// Run the builder's command to completion with a time limit, capturing its output.
+/// Returns 0 on success, -1 on error; sets out_err_msg (caller frees via pty_free_err_msg).
+///
+/// The non-interactive entry point for CI-style use: opens a PTY, spawns the command,
+/// drains its output while waiting for it, and frees every handle before returning, also
+/// on errors and panics. Up to `max_bytes` bytes of output are copied into `out_buf` and
+/// `out_len` receives how many; further output is discarded while the command runs on, and
+/// `out_truncated` is set to 1. If the command is still running after `timeout_ms`
+/// milliseconds, it and its process group are killed and `out_timed_out` is set to 1; 0
+/// means no time limit. `exit_code_out` receives the exit code, which after a timeout is
+/// that of the killed child. Hitting either limit is not an error.
+///
+/// # Safety
+///
+/// Caller must ensure:
+/// - `opts` is a valid, non-null handle obtained from `pty_builder_new`.
+/// - `out_buf` is a valid, non-null pointer to mutable memory of at least `max_bytes` bytes.
+/// - `out_len`, `out_truncated`, `out_timed_out`, `exit_code_out` and `out_err_msg` are valid, non-null pointers to mutable memory.
+/// - The caller must free any error message using `pty_free_err_msg`.
+/// - No concurrent mutation of the builder handle.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn pty_run(
+    opts: BuilderHandle,
+    timeout_ms: u32,
+    max_bytes: usize,
+    out_buf: *mut u8,
+    out_len: *mut usize,
+    out_truncated: *mut i32,
+    out_timed_out: *mut i32,
+    exit_code_out: *mut i32,
+    out_err_msg: *mut *mut libc::c_char,
+) -> i32 {
+    ffi_call("pty_run", out_err_msg, || unsafe {
+        let builder = handle_mut(opts)?;
+        let out_len = out_ref(out_len)?;
+        let out_truncated = out_ref(out_truncated)?;
+        let out_timed_out = out_ref(out_timed_out)?;
+        let exit_code_out = out_ref(exit_code_out)?;
+        if max_bytes == 0 {
+            return Err("max_bytes must be at least 1".into());
+        }
+        let buf = buf_mut(out_buf, max_bytes)?;
+        let limits = Limits {
+            max_output: max_bytes,
+            kill_on_limit: false,
+            timeout: (timeout_ms > 0).then(|| Duration::from_millis(u64::from(timeout_ms))),
+            first_byte_timeout: None,
+        };
+        let mut len = 0;
+        let outcome = run(builder, &limits, None, |data| {
+            buf[len..len + data.len()].copy_from_slice(data);
+            len += data.len();
+        })?;
+        *out_len = len;
+        *out_truncated = i32::from(outcome.truncated);
+        *out_timed_out = i32::from(outcome.timed_out);
+        *exit_code_out = outcome.exit_code;
+        Ok(0)
+    })
+}
+
+/// Run the builder's command to completion with `input` as its entire stdin, capturing
+/// its output (Unix only).
+/// Returns 0 on success, -1 on error, -2 on other platforms, -5 if the output exceeded `cap`; sets out_err_msg (caller frees via pty_free_err_msg).
+///
+/// The one-call `printf %s "$input" | cmd`: the child's stdin is a pipe that receives
+/// `input` and is then closed, so the command reads exactly those bytes and then sees end
+/// of file, while its stdout and stderr stay on the PTY. Unlike typing into the PTY,
+/// nothing is echoed into the output, there is no line length limit and no byte is taken
+/// as a control character. The input is written on a helper thread while the output is
+/// drained, so a command that prints a lot before reading cannot deadlock; one that exits
+/// without reading all of it is not an error. Any stdin target set on the builder is
+/// replaced.
+///
+/// Up to `cap` bytes of output are copied into `out_buf` and `out_len` receives how many;
+/// past that the output is discarded while the command runs on, and the function returns
+/// -5 once it exits. `exit_code_out` receives the exit code on -5 as well as on success.
+/// Every handle is freed before returning, on every path.
+///
+/// # Safety
+///
+/// Caller must ensure:
+/// - `opts` is a valid, non-null handle obtained from `pty_builder_new`.
+/// - `input` is a valid, non-null pointer to immutable memory of at least `input_len` bytes.
+/// - `out_buf` is a valid, non-null pointer to mutable memory of at least `cap` bytes.
+/// - `out_len`, `exit_code_out` and `out_err_msg` are valid, non-null pointers to mutable memory.
+/// - The caller must free any error message using `pty_free_err_msg`.
+/// - No concurrent mutation of the builder handle.
+#[unsafe(no_mangle)]
+#[allow(clippy::too_many_arguments)]
+pub unsafe extern "C" fn pty_run_with_input(
+    opts: BuilderHandle,
+    input: *const u8,
+    input_len: usize,
+    out_buf: *mut u8,
+    cap: usize,
+    out_len: *mut usize,
+    exit_code_out: *mut i32,
+    out_err_msg: *mut *mut libc::c_char,
+) -> i32 {
+    ffi_call("pty_run_with_input", out_err_msg, || unsafe {
+        let builder = handle_mut(opts)?;
+        let input = buf_ref(input, input_len)?;
+        let out_len = out_ref(out_len)?;
+        let exit_code_out = out_ref(exit_code_out)?;
+        *out_len = 0;
+        if cap == 0 {
+            return Err("cap must be at least 1".into());
+        }
+        let buf = buf_mut(out_buf, cap)?;
+        #[cfg(unix)]
+        {
+            let (builder, input) = Input::new(builder, input)?;
+            let limits = Limits {
+                max_output: cap,
+                ..Limits::default()
+            };
+            let mut len = 0;
+            let outcome = run(&builder, &limits, Some(input), |data| {
+                buf[len..len + data.len()].copy_from_slice(data);
+                len += data.len();
+            })?;
+            *out_len = len;
+            *exit_code_out = outcome.exit_code;
+            if outcome.truncated {
+                return Err(PtyError::with_code(
+                    PTY_ERR_OUTPUT_TOO_LARGE,
+                    format!("Output exceeded {cap} bytes"),
+                ));
+            }
+            Ok(0)
+        }
+        #[cfg(not(unix))]
+        {
+            let _ = (builder, input, buf, exit_code_out);
+            Err(PtyError::unsupported("pty_run_with_input"))
+        }
+    })
+}
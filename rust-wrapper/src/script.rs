@@ -0,0 +1,327 @@
+//! Replaying scripted and pasted input into a program, and feeding it files.
+
+use crate::error::{PtyError, ffi_call};
+use crate::handle::handle_mut;
+#[cfg(unix)]
+use crate::write_error;
+use crate::{WriterHandle, buf_ref, out_ref};
+use std::ffi::CStr;
+use std::io::Write;
+use std::thread;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Write `count` lines to the PTY, each followed by a newline, pausing `delay_ms` between them.
+/// Returns 0 on success, -1 on error, -4 if the child has exited; sets out_err_msg (caller frees via pty_free_err_msg).
+///
+/// Meant for pasting a script into a REPL at a pace it can keep up with. Each line is
+/// written and flushed in full before the pause; there is no pause after the last line.
+/// The first failed write stops the script, and the error names the line it failed on.
+/// Blocks the calling thread for roughly `(count - 1) * delay_ms` milliseconds.
+///
+/// # Safety
+///
+/// Caller must ensure:
+/// - `writer` is a valid, non-null handle obtained from `pty_get_writer`.
+/// - If `count` > 0, `lines` is a valid, non-null pointer to an array of `count` non-null pointers, each pointing to a null-terminated C string.
+/// - `out_err_msg` is a valid, non-null pointer to mutable memory.
+/// - The caller must free any error message using `pty_free_err_msg`.
+/// - No concurrent access to the writer handle.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn pty_write_script(
+    writer: WriterHandle,
+    lines: *const *const libc::c_char,
+    count: usize,
+    delay_ms: u32,
+    out_err_msg: *mut *mut libc::c_char,
+) -> i32 {
+    ffi_call("pty_write_script", out_err_msg, || unsafe {
+        let writer_struct = handle_mut(writer)?;
+        if count == 0 {
+            return Ok(0);
+        }
+        if lines.is_null() {
+            return Err("Null pointer provided".into());
+        }
+        let lines = std::slice::from_raw_parts(lines, count);
+        // Check every line up front so a bad array does not leave half a script sent.
+        if let Some(index) = lines.iter().position(|line| line.is_null()) {
+            return Err(PtyError::new(format!(
+                "Line {} is a null pointer",
+                index + 1
+            )));
+        }
+        for (index, &line) in lines.iter().enumerate() {
+            if index > 0 && delay_ms > 0 {
+                thread::sleep(Duration::from_millis(u64::from(delay_ms)));
+            }
+            let mut data = CStr::from_ptr(line).to_bytes().to_vec();
+            data.push(b'\n');
+            let inner = &mut writer_struct.inner;
+            inner
+                .write_all(&data)
+                .and_then(|()| inner.flush())
+                .map_err(|e| in_context(e, &format!("Writing line {} failed", index + 1)))?;
+        }
+        Ok(0)
+    })
+}
+
+/// Maps a failed write with `write_error`, keeping its code, and prefixes `context`.
+fn in_context(e: std::io::Error, context: &str) -> PtyError {
+    let e = write_error(e);
+    PtyError::with_code(e.code, format!("{context}: {}", e.message))
+}
+
+/// The splitmix64 generator: small, fast and plenty random for typing delays.
+struct SplitMix64(u64);
+
+impl SplitMix64 {
+    fn next(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+}
+
+/// Type `len` bytes into the PTY one at a time, pausing a random time between them.
+/// Returns 0 on success, -1 on error, -4 if the child has exited; sets out_err_msg (caller frees via pty_free_err_msg).
+///
+/// For demos and automation that should look like a person typing, or programs that
+/// drop input arriving all at once. Each byte is written and flushed on its own, and
+/// before every byte but the first the call sleeps between `min_delay_ms` and
+/// `max_delay_ms` milliseconds inclusive, chosen at random; equal bounds give a fixed
+/// pace. A multibyte character is typed byte by byte like everything else, which
+/// programs reading a terminal cope with. A nonzero `seed` makes the delays the same on
+/// every run, for reproducible demos; 0 seeds from the clock. The first failed write
+/// stops the typing, and the error says how many bytes went through. Blocks the calling
+/// thread until everything is typed.
+///
+/// # Safety
+///
+/// Caller must ensure:
+/// - `writer` is a valid, non-null handle obtained from `pty_get_writer`.
+/// - If `len` > 0, `buf` is a valid, non-null pointer to immutable memory of at least `len` bytes.
+/// - `out_err_msg` is a valid, non-null pointer to mutable memory.
+/// - The caller must free any error message using `pty_free_err_msg`.
+/// - No concurrent access to the writer handle.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn pty_type(
+    writer: WriterHandle,
+    buf: *const u8,
+    len: usize,
+    min_delay_ms: u32,
+    max_delay_ms: u32,
+    seed: u64,
+    out_err_msg: *mut *mut libc::c_char,
+) -> i32 {
+    ffi_call("pty_type", out_err_msg, || unsafe {
+        let writer_struct = handle_mut(writer)?;
+        if min_delay_ms > max_delay_ms {
+            return Err(PtyError::new(format!(
+                "Minimum delay {min_delay_ms} ms exceeds the maximum {max_delay_ms} ms"
+            )));
+        }
+        let data = if len == 0 { &[][..] } else { buf_ref(buf, len)? };
+        let seed = match seed {
+            0 => SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map_or(0, |now| now.as_nanos() as u64),
+            seed => seed,
+        };
+        let mut rng = SplitMix64(seed);
+        let spread = u64::from(max_delay_ms - min_delay_ms) + 1;
+        for (index, byte) in data.iter().enumerate() {
+            if index > 0 {
+                let delay = u64::from(min_delay_ms) + rng.next() % spread;
+                if delay > 0 {
+                    thread::sleep(Duration::from_millis(delay));
+                }
+            }
+            let inner = &mut writer_struct.inner;
+            inner
+                .write_all(std::slice::from_ref(byte))
+                .and_then(|()| inner.flush())
+                .map_err(|e| {
+                    in_context(e, &format!("Typing failed after {index} of {len} bytes"))
+                })?;
+        }
+        Ok(0)
+    })
+}
+
+/// `pty_write_line_ending` ending: `\n` (the default).
+const PTY_LINE_LF: i32 = 0;
+/// `pty_write_line_ending` ending: `\r`.
+const PTY_LINE_CR: i32 = 1;
+/// `pty_write_line_ending` ending: `\r\n`.
+const PTY_LINE_CRLF: i32 = 2;
+
+/// Write `len` bytes followed by the line terminator selected by `ending`.
+/// Returns 0 on success, -1 on error, -4 if the child has exited; sets out_err_msg (caller frees via pty_free_err_msg).
+///
+/// `ending` is `PTY_LINE_LF` (0), `PTY_LINE_CR` (1) or `PTY_LINE_CRLF` (2). Most programs
+/// take LF; some Windows console programs and raw-mode programs only act on CR, which is
+/// also what the Enter key sends. The line and its terminator are written and flushed
+/// together; line breaks inside `buf` are written as they are.
+///
+/// # Safety
+///
+/// Caller must ensure:
+/// - `writer` is a valid, non-null handle obtained from `pty_get_writer`.
+/// - If `len` > 0, `buf` is a valid, non-null pointer to immutable memory of at least `len` bytes.
+/// - `out_err_msg` is a valid, non-null pointer to mutable memory.
+/// - The caller must free any error message using `pty_free_err_msg`.
+/// - No concurrent access to the writer handle.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn pty_write_line_ending(
+    writer: WriterHandle,
+    buf: *const u8,
+    len: usize,
+    ending: i32,
+    out_err_msg: *mut *mut libc::c_char,
+) -> i32 {
+    ffi_call("pty_write_line_ending", out_err_msg, || unsafe {
+        let writer_struct = handle_mut(writer)?;
+        let terminator: &[u8] = match ending {
+            PTY_LINE_LF => b"\n",
+            PTY_LINE_CR => b"\r",
+            PTY_LINE_CRLF => b"\r\n",
+            _ => return Err(PtyError::new(format!("Unknown line ending: {ending}"))),
+        };
+        let line = if len == 0 { &[][..] } else { buf_ref(buf, len)? };
+        let mut data = Vec::with_capacity(line.len() + terminator.len());
+        data.extend_from_slice(line);
+        data.extend_from_slice(terminator);
+        writer_struct.inner.write_all(&data).map_err(write_error)?;
+        writer_struct.inner.flush().map_err(write_error)?;
+        Ok(0)
+    })
+}
+
+/// Starts a bracketed paste.
+const PASTE_START: &[u8] = b"\x1b[200~";
+/// Ends a bracketed paste.
+const PASTE_END: &[u8] = b"\x1b[201~";
+
+/// Wraps `data` in bracketed-paste markers, dropping any markers inside it so the pasted
+/// text cannot end the paste early and have the rest run as typed input.
+fn bracket_paste(data: &[u8]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(data.len() + PASTE_START.len() + PASTE_END.len());
+    out.extend_from_slice(PASTE_START);
+    let mut i = 0;
+    while i < data.len() {
+        let rest = &data[i..];
+        if rest.starts_with(PASTE_START) || rest.starts_with(PASTE_END) {
+            i += PASTE_START.len();
+            continue;
+        }
+        out.push(data[i]);
+        i += 1;
+    }
+    out.extend_from_slice(PASTE_END);
+    out
+}
+
+/// Write `len` bytes as a bracketed paste, wrapped in `ESC [200~` and `ESC [201~`.
+/// Returns 0 on success, -1 on error, -4 if the child has exited; sets out_err_msg (caller frees via pty_free_err_msg).
+///
+/// A program that enabled bracketed paste mode (`ESC [?2004h`, as bash, zsh, vim and most
+/// REPLs do) takes the text as one paste, so newlines in it are inserted instead of running
+/// each line. Programs without it see the markers as stray input, so only paste this way
+/// into programs known to support it. Paste markers inside the text are removed. The whole
+/// paste is written and flushed before returning.
+///
+/// # Safety
+///
+/// Caller must ensure:
+/// - `writer` is a valid, non-null handle obtained from `pty_get_writer`.
+/// - `buf` is a valid, non-null pointer to immutable memory of at least `len` bytes.
+/// - `out_err_msg` is a valid, non-null pointer to mutable memory.
+/// - The caller must free any error message using `pty_free_err_msg`.
+/// - No concurrent access to the writer handle.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn pty_write_paste(
+    writer: WriterHandle,
+    buf: *const u8,
+    len: usize,
+    out_err_msg: *mut *mut libc::c_char,
+) -> i32 {
+    ffi_call("pty_write_paste", out_err_msg, || unsafe {
+        let writer_struct = handle_mut(writer)?;
+        let data = bracket_paste(buf_ref(buf, len)?);
+        writer_struct.inner.write_all(&data).map_err(write_error)?;
+        writer_struct.inner.flush().map_err(write_error)?;
+        Ok(0)
+    })
+}
+
+/// Bytes `pty_write_from_fd` reads from the file descriptor at a time.
+#[cfg(unix)]
+const COPY_CHUNK: usize = 64 * 1024;
+
+/// Write everything read from a file descriptor to the PTY, until end of file (Unix only).
+/// Returns 0 on success, -1 on error, -2 on other platforms, -4 if the child has exited; sets out_err_msg (caller frees via pty_free_err_msg).
+///
+/// Feeds a file, pipe or socket to the program as input without passing it through the
+/// caller, e.g. a large input file for a program reading stdin. Reads `fd` in chunks and
+/// writes each in full, retrying short writes and interrupted calls, blocking while the
+/// program is not reading. `out_bytes` receives the number of bytes written, also when an
+/// error stops the copy partway. The data is input like typed text: the line discipline
+/// echoes it and interprets control characters unless the program set raw mode, and
+/// Ctrl-D only ends a line, not the input. `fd` is read from its current offset and stays
+/// owned by the caller, who must close it; it is not closed here, also on errors.
+///
+/// # Safety
+///
+/// Caller must ensure:
+/// - `writer` is a valid, non-null handle obtained from `pty_get_writer`.
+/// - `fd` is an open file descriptor not closed or read by others during the call.
+/// - `out_bytes` and `out_err_msg` are valid, non-null pointers to mutable memory.
+/// - The caller must free any error message using `pty_free_err_msg`.
+/// - No concurrent access to the writer handle.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn pty_write_from_fd(
+    writer: WriterHandle,
+    fd: i32,
+    out_bytes: *mut u64,
+    out_err_msg: *mut *mut libc::c_char,
+) -> i32 {
+    ffi_call("pty_write_from_fd", out_err_msg, || unsafe {
+        let writer_struct = handle_mut(writer)?;
+        let out_bytes = out_ref(out_bytes)?;
+        *out_bytes = 0;
+        #[cfg(unix)]
+        {
+            let mut chunk = vec![0u8; COPY_CHUNK];
+            loop {
+                let n = match libc::read(fd, chunk.as_mut_ptr().cast(), chunk.len()) {
+                    -1 => {
+                        let e = std::io::Error::last_os_error();
+                        if e.kind() == std::io::ErrorKind::Interrupted {
+                            continue;
+                        }
+                        return Err(PtyError::new(format!(
+                            "Unable to read file descriptor {fd}: {e}"
+                        )));
+                    }
+                    0 => break,
+                    n => n as usize,
+                };
+                writer_struct
+                    .inner
+                    .write_all(&chunk[..n])
+                    .map_err(write_error)?;
+                *out_bytes += n as u64;
+            }
+            writer_struct.inner.flush().map_err(write_error)?;
+            Ok(0)
+        }
+        #[cfg(not(unix))]
+        {
+            let _ = (writer_struct, fd);
+            Err(PtyError::unsupported("pty_write_from_fd"))
+        }
+    })
+}
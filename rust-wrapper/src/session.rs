@@ -0,0 +1,506 @@
+//! State shared by everything opened on one PTY.
+//!
+//! A `Session` is created with the master and shared with the readers cloned from it, so
+//! observations made while reading output (such as title changes) can be queried through
+//! the master handle. It also holds metadata about the PTY, such as when it was opened.
+//!
+//! Every session has an id, unique for the life of the process. The ids of sessions whose
+//! master is still open are kept in a registry so they can be listed.
+
+use crate::ansi::{self, Kind};
+use crate::error::{PtyError, PtyResult, ffi_call};
+use crate::handle::handle_mut;
+use crate::{Master, MasterHandle, copy_str_out, out_ref, set_string_out, str_from_ptr};
+use std::collections::BTreeSet;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Incomplete sequences longer than this at the end of a chunk are not tracked.
+const MAX_CARRY: usize = 4096;
+
+/// Receives the payload of an OSC sequence `pty_reader_watch_osc` watches for. `payload`
+/// is only valid for the duration of the call.
+pub type PtyOscCallback =
+    extern "C" fn(code: u32, payload: *const u8, len: usize, user_data: *mut libc::c_void);
+
+/// A callback registered with `pty_reader_watch_osc`.
+struct OscWatch {
+    code: u32,
+    cb: PtyOscCallback,
+    user_data: *mut libc::c_void,
+}
+
+// The caller guarantees `user_data` may be used from whichever thread reads.
+unsafe impl Send for OscWatch {}
+
+/// Id of the next session.
+static NEXT_ID: AtomicU64 = AtomicU64::new(1);
+/// Ids of the sessions whose master is open, in creation order.
+static LIVE: Mutex<BTreeSet<u64>> = Mutex::new(BTreeSet::new());
+
+pub(crate) struct Session {
+    pub(crate) id: u64,
+    title: Mutex<Option<String>>,
+    /// Working directory last reported through OSC 7.
+    cwd: Mutex<Option<String>>,
+    /// When the PTY was opened.
+    created_at: SystemTime,
+}
+
+impl Session {
+    pub(crate) fn new() -> Arc<Self> {
+        Arc::new(Session {
+            id: NEXT_ID.fetch_add(1, Ordering::Relaxed),
+            title: Mutex::new(None),
+            cwd: Mutex::new(None),
+            created_at: SystemTime::now(),
+        })
+    }
+
+    /// Lists the session in the registry. Called when its master is created.
+    pub(crate) fn register(&self) {
+        LIVE.lock().unwrap().insert(self.id);
+    }
+
+    /// Removes the session from the registry. Called when its master is dropped.
+    pub(crate) fn unregister(&self) {
+        LIVE.lock().unwrap().remove(&self.id);
+    }
+
+    pub(crate) fn title(&self) -> Option<String> {
+        self.title.lock().unwrap().clone()
+    }
+
+    pub(crate) fn set_title(&self, title: String) {
+        *self.title.lock().unwrap() = Some(title);
+    }
+
+    pub(crate) fn cwd(&self) -> Option<String> {
+        self.cwd.lock().unwrap().clone()
+    }
+
+    pub(crate) fn set_cwd(&self, cwd: String) {
+        *self.cwd.lock().unwrap() = Some(cwd);
+    }
+}
+
+/// Watches output for OSC 0 and OSC 2 ("set window title") sequences, and OSC 7 ("current
+/// directory") ones if enabled, and records them in the session. Sequences split across
+/// chunks are carried over to the next one.
+pub(crate) struct OscTracker {
+    session: Arc<Session>,
+    carry: Vec<u8>,
+    track_cwd: bool,
+    watches: Vec<OscWatch>,
+}
+
+impl OscTracker {
+    pub(crate) fn new(session: Arc<Session>) -> Self {
+        OscTracker {
+            session,
+            carry: Vec::new(),
+            track_cwd: false,
+            watches: Vec::new(),
+        }
+    }
+
+    pub(crate) fn session(&self) -> &Arc<Session> {
+        &self.session
+    }
+
+    pub(crate) fn set_track_cwd(&mut self, enabled: bool) {
+        self.track_cwd = enabled;
+    }
+
+    /// Calls `cb` with the payload of every OSC `code` sequence from now on, replacing any
+    /// earlier callback for `code`; `None` stops watching it.
+    pub(crate) fn watch(
+        &mut self,
+        code: u32,
+        cb: Option<PtyOscCallback>,
+        user_data: *mut libc::c_void,
+    ) {
+        self.watches.retain(|watch| watch.code != code);
+        if let Some(cb) = cb {
+            self.watches.push(OscWatch {
+                code,
+                cb,
+                user_data,
+            });
+        }
+    }
+
+    pub(crate) fn feed(&mut self, data: &[u8]) {
+        if self.carry.is_empty() {
+            if !data.contains(&0x1b) {
+                return;
+            }
+            let rest = self.scan(data);
+            self.carry = rest.to_vec();
+        } else {
+            let mut buf = std::mem::take(&mut self.carry);
+            buf.extend_from_slice(data);
+            let rest = self.scan(&buf).to_vec();
+            self.carry = rest;
+        }
+        if self.carry.len() > MAX_CARRY {
+            self.carry.clear();
+        }
+    }
+
+    /// Records the titles and directories set in `buf` and returns the incomplete sequence
+    /// at its end.
+    fn scan<'a>(&self, buf: &'a [u8]) -> &'a [u8] {
+        let mut i = 0;
+        while let Some(offset) = buf[i..].iter().position(|&c| c == 0x1b) {
+            let start = i + offset;
+            let Some((kind, len)) = ansi::sequence_at(&buf[start..]) else {
+                return &buf[start..];
+            };
+            if kind == Kind::Osc {
+                self.osc(&buf[start + 2..start + len]);
+            }
+            i = start + len;
+        }
+        &[]
+    }
+
+    fn osc(&self, body: &[u8]) {
+        let terminated = body
+            .strip_suffix(b"\x07")
+            .or_else(|| body.strip_suffix(b"\x1b\\"));
+        if let Some(body) = terminated {
+            self.notify(body);
+        }
+        let body = terminated.unwrap_or(body);
+        let title = body
+            .strip_prefix(b"0;")
+            .or_else(|| body.strip_prefix(b"2;"));
+        if let Some(title) = title {
+            self.session
+                .set_title(String::from_utf8_lossy(title).into_owned());
+        } else if self.track_cwd
+            && let Some(path) = body.strip_prefix(b"7;").and_then(file_url_path)
+        {
+            self.session.set_cwd(path);
+        }
+    }
+
+    /// Passes the payload of a terminated OSC sequence to the callback watching its code.
+    fn notify(&self, body: &[u8]) {
+        if self.watches.is_empty() {
+            return;
+        }
+        let (code, payload) = match body.iter().position(|&c| c == b';') {
+            Some(semicolon) => (&body[..semicolon], &body[semicolon + 1..]),
+            None => (body, &[][..]),
+        };
+        let Some(code) = std::str::from_utf8(code)
+            .ok()
+            .filter(|code| !code.is_empty() && code.bytes().all(|c| c.is_ascii_digit()))
+            .and_then(|code| code.parse::<u32>().ok())
+        else {
+            return;
+        };
+        for watch in self.watches.iter().filter(|watch| watch.code == code) {
+            (watch.cb)(code, payload.as_ptr(), payload.len(), watch.user_data);
+        }
+    }
+}
+
+/// The decoded path of a `file://host/path` URL, or `None` for anything else.
+fn file_url_path(url: &[u8]) -> Option<String> {
+    let rest = url.strip_prefix(b"file://")?;
+    let path = &rest[rest.iter().position(|&c| c == b'/')?..];
+    let mut decoded = Vec::with_capacity(path.len());
+    let mut i = 0;
+    while i < path.len() {
+        let escaped = (path[i] == b'%')
+            .then(|| path.get(i + 1..i + 3))
+            .flatten()
+            .and_then(|hex| u8::from_str_radix(std::str::from_utf8(hex).ok()?, 16).ok());
+        match escaped {
+            Some(byte) => {
+                decoded.push(byte);
+                i += 3;
+            }
+            None => {
+                decoded.push(path[i]);
+                i += 1;
+            }
+        }
+    }
+    Some(String::from_utf8_lossy(&decoded).into_owned())
+}
+
+/// Set the session's window title.
+/// Returns 0 on success, -1 on error; sets out_err_msg (caller frees via pty_free_err_msg).
+///
+/// Changes the title the way a program in the terminal would, so it reaches whatever
+/// displays the output as well as `pty_get_title`, which reports it at once. On Unix the
+/// title belongs to the terminal emulator, not the PTY, so an OSC 2 sequence carrying it
+/// is written to the PTY's output, where readers see it like one the child printed. On
+/// Windows, where ConPTY does not pass OSC sequences through, the console's own title is
+/// set by running `cmd.exe`'s `title` command in the pseudoconsole, and ConPTY reports
+/// the change in the output as OSC 0. The helper is in the session only while it runs; it
+/// is given 5 seconds and killed after that, which happens when nobody reads the output
+/// and ConPTY cannot write the OSC 0. That needs a master from a spawn; one from
+/// `pty_open` fails there. Titles with control characters are rejected, since they would
+/// end the sequence early.
+///
+/// # Safety
+///
+/// Caller must ensure:
+/// - `master` is a valid, non-null handle obtained from `pty_open_and_spawn` or `pty_open`.
+/// - `title` is a valid, non-null pointer to a null-terminated C string.
+/// - `out_err_msg` is a valid, non-null pointer to mutable memory.
+/// - The caller must free any error message using `pty_free_err_msg`.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn pty_set_title(
+    master: MasterHandle,
+    title: *const libc::c_char,
+    out_err_msg: *mut *mut libc::c_char,
+) -> i32 {
+    ffi_call("pty_set_title", out_err_msg, || unsafe {
+        let master_struct = handle_mut(master)?;
+        let title = str_from_ptr(title)?;
+        if title.chars().any(char::is_control) {
+            return Err(PtyError::new(format!(
+                "Title contains control characters: {title:?}"
+            )));
+        }
+        announce_title(master_struct, &title)?;
+        master_struct.session.set_title(title);
+        Ok(0)
+    })
+}
+
+/// Writes OSC 2 with `title` to the PTY's output through a slave fd opened for the purpose.
+#[cfg(unix)]
+fn announce_title(master: &Master, title: &str) -> PtyResult<()> {
+    use std::io::Write;
+    let mut slave = std::fs::File::from(crate::fd::open_slave(&*master.inner)?);
+    slave.write_all(format!("\x1b]2;{title}\x07").as_bytes())?;
+    Ok(())
+}
+
+/// How long `pty_set_title` waits for its helper on Windows before killing it.
+#[cfg(windows)]
+const TITLE_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(5);
+
+/// Sets the pseudoconsole's title with `cmd.exe`'s `title` command. The title travels in
+/// an environment variable expanded with delayed expansion, after cmd has parsed the
+/// line, so characters like `&` and `"` in it need no quoting.
+#[cfg(windows)]
+fn announce_title(master: &Master, title: &str) -> PtyResult<()> {
+    use std::time::{Duration, Instant};
+    let console = master
+        .console
+        .as_ref()
+        .ok_or("Only the master of a spawned PTY can set the console title")?;
+    let mut cmd = portable_pty::CommandBuilder::new("cmd.exe");
+    cmd.args(["/d", "/v:on", "/c", "title", "!PTY_TITLE!"]);
+    cmd.env("PTY_TITLE", title);
+    let mut helper = console.spawn_command(cmd)?;
+    let deadline = Instant::now() + TITLE_TIMEOUT;
+    let status = loop {
+        if let Some(status) = helper.try_wait()? {
+            break status;
+        }
+        if Instant::now() >= deadline {
+            let _ = helper.kill();
+            let _ = helper.wait();
+            return Err(PtyError::new(
+                "Setting the console title timed out; is the PTY's output being read?",
+            ));
+        }
+        std::thread::sleep(Duration::from_millis(10));
+    };
+    if !status.success() {
+        return Err(PtyError::new(format!(
+            "Setting the console title failed with exit code {}",
+            status.exit_code()
+        )));
+    }
+    Ok(())
+}
+
+/// Get the session's current window title.
+/// Returns 0 on success, -1 on error; sets out_err_msg (caller frees via pty_free_err_msg).
+///
+/// The title is the last one set with `pty_set_title` or by the child through an OSC 0 or
+/// OSC 2 sequence in output that has been read. On Windows, ConPTY reports console title
+/// changes (`SetConsoleTitle`, cmd's `title`) as OSC 0 as well, so the result is the same
+/// on every platform. `title_out` receives null if no title has been set.
+///
+/// # Safety
+///
+/// Caller must ensure:
+/// - `master` is a valid, non-null handle obtained from `pty_open_and_spawn` or `pty_open`.
+/// - `title_out` and `out_err_msg` are valid, non-null pointers to mutable memory.
+/// - The caller must free the string returned in `title_out` using `pty_free_string`.
+/// - The caller must free any error message using `pty_free_err_msg`.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn pty_get_title(
+    master: MasterHandle,
+    title_out: *mut *mut libc::c_char,
+    out_err_msg: *mut *mut libc::c_char,
+) -> i32 {
+    ffi_call("pty_get_title", out_err_msg, || unsafe {
+        let master_struct = handle_mut(master)?;
+        let title_out = out_ref(title_out)?;
+        set_string_out(title_out, master_struct.session.title().as_deref())?;
+        Ok(0)
+    })
+}
+
+/// Get the time the PTY was opened, in milliseconds since the Unix epoch.
+/// Returns 0 on success, -1 on error; sets out_err_msg (caller frees via pty_free_err_msg).
+///
+/// Taken from the wall clock when the master was created, so subtracting it from the
+/// current time gives how long the session has been running. Adjusting the system clock
+/// afterwards shifts that difference accordingly.
+///
+/// # Safety
+///
+/// Caller must ensure:
+/// - `master` is a valid, non-null handle obtained from `pty_open_and_spawn` or `pty_open`.
+/// - `out_unix_millis` and `out_err_msg` are valid, non-null pointers to mutable memory.
+/// - The caller must free any error message using `pty_free_err_msg`.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn pty_master_created_at(
+    master: MasterHandle,
+    out_unix_millis: *mut u64,
+    out_err_msg: *mut *mut libc::c_char,
+) -> i32 {
+    ffi_call("pty_master_created_at", out_err_msg, || unsafe {
+        let master_struct = handle_mut(master)?;
+        let out_unix_millis = out_ref(out_unix_millis)?;
+        let since_epoch = master_struct
+            .session
+            .created_at
+            .duration_since(UNIX_EPOCH)
+            .map_err(|_| "The PTY was opened before the Unix epoch")?;
+        *out_unix_millis = since_epoch.as_millis() as u64;
+        Ok(0)
+    })
+}
+
+/// Get the id of the PTY's session.
+/// Returns 0 on success, -1 on error.
+///
+/// Ids start at 1 and are never reused within the process, so an id outlives its handle
+/// safely: after the master is freed it simply no longer appears in `pty_list_sessions`.
+///
+/// # Safety
+///
+/// Caller must ensure:
+/// - `master` is a valid, non-null handle obtained from `pty_open_and_spawn` or `pty_open`.
+/// - `out_id` is a valid, non-null pointer to mutable memory.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn pty_master_session_id(master: MasterHandle, out_id: *mut u64) -> i32 {
+    ffi_call("pty_master_session_id", std::ptr::null_mut(), || unsafe {
+        let master_struct = handle_mut(master)?;
+        *out_ref(out_id)? = master_struct.session.id;
+        Ok(0)
+    })
+}
+
+/// List the ids of all sessions whose master is still open, oldest first.
+/// Returns 0 on success, -1 on error.
+///
+/// Fills `out_ids` with up to `cap` ids and stores the number of live sessions in
+/// `out_count`. If that is more than `cap` the call fails, but `out_count` still receives
+/// the number so the caller can retry with a larger array. Safe to call from any thread;
+/// the list is a snapshot, so masters opened or freed concurrently may or may not be in
+/// it. The registry holds one entry per open master and drops it when the master is freed,
+/// so it does not grow over the life of the process.
+///
+/// # Safety
+///
+/// Caller must ensure:
+/// - If `cap` > 0, `out_ids` is a valid, non-null pointer to mutable memory for `cap` `u64` ids.
+/// - `out_count` is a valid, non-null pointer to mutable memory.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn pty_list_sessions(
+    out_ids: *mut u64,
+    cap: usize,
+    out_count: *mut usize,
+) -> i32 {
+    ffi_call("pty_list_sessions", std::ptr::null_mut(), || unsafe {
+        let out_count = out_ref(out_count)?;
+        let live = LIVE.lock().unwrap();
+        *out_count = live.len();
+        if live.len() > cap {
+            return Err(format!("Array too small: {} ids needed", live.len()).into());
+        }
+        if live.is_empty() {
+            return Ok(0);
+        }
+        if out_ids.is_null() {
+            return Err("Null pointer provided".into());
+        }
+        let ids = std::slice::from_raw_parts_mut(out_ids, live.len());
+        for (slot, &id) in ids.iter_mut().zip(live.iter()) {
+            *slot = id;
+        }
+        Ok(0)
+    })
+}
+
+/// Attach a human-readable label to the PTY, for logs and UIs.
+/// Returns 0 on success, -1 on error.
+///
+/// The label is metadata kept with the master handle and never sent anywhere. It starts
+/// empty; a null or empty `label` clears it.
+///
+/// # Safety
+///
+/// Caller must ensure:
+/// - `master` is a valid, non-null handle obtained from `pty_open_and_spawn` or `pty_open`.
+/// - `label` is null or a valid pointer to a null-terminated C string.
+/// - No concurrent access to the master handle.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn pty_master_set_label(
+    master: MasterHandle,
+    label: *const libc::c_char,
+) -> i32 {
+    ffi_call("pty_master_set_label", std::ptr::null_mut(), || unsafe {
+        let master_struct = handle_mut(master)?;
+        master_struct.label = if label.is_null() {
+            String::new()
+        } else {
+            str_from_ptr(label)?
+        };
+        Ok(0)
+    })
+}
+
+/// Get the label set with `pty_master_set_label`.
+/// Returns 0 on success, -1 on error.
+///
+/// Copies the label into `buf` with a terminating NUL and stores its length in `out_len`;
+/// an unlabeled PTY yields an empty string. If `len` is too small the call fails but
+/// `out_len` still receives the length, so the caller can retry with `out_len + 1` bytes.
+///
+/// # Safety
+///
+/// Caller must ensure:
+/// - `master` is a valid, non-null handle obtained from `pty_open_and_spawn` or `pty_open`.
+/// - `buf` is a valid, non-null pointer to mutable memory of at least `len` bytes.
+/// - `out_len` is a valid, non-null pointer to mutable memory.
+/// - No concurrent access to the master handle.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn pty_master_get_label(
+    master: MasterHandle,
+    buf: *mut u8,
+    len: usize,
+    out_len: *mut usize,
+) -> i32 {
+    ffi_call("pty_master_get_label", std::ptr::null_mut(), || unsafe {
+        let master_struct = handle_mut(master)?;
+        copy_str_out(&master_struct.label, buf, len, out_len)?;
+        Ok(0)
+    })
+}
@@ -0,0 +1,149 @@
+//! Shutting down every child at once, for hosts about to exit.
+//!
+//! Each child is listed in a registry while its handle is open, so `pty_shutdown_all` can
+//! reach children the host has lost track of. On Unix the registry holds pids, which is
+//! safe against reuse because a child's pid stays reserved until it is reaped, and the
+//! shutdown only signals pids that are still unreaped children of this process. On Windows
+//! it holds a killer with its own process handle.
+
+use crate::error::ffi_call;
+use std::collections::BTreeMap;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicU64, Ordering};
+#[cfg(unix)]
+use std::time::{Duration, Instant};
+
+/// How often the shutdown checks whether the children have exited.
+#[cfg(unix)]
+const POLL_INTERVAL: Duration = Duration::from_millis(10);
+/// How long children are given to die after SIGKILL before they are left behind.
+#[cfg(unix)]
+const KILL_GRACE: Duration = Duration::from_secs(1);
+
+/// Id of the next registered child.
+static NEXT_ID: AtomicU64 = AtomicU64::new(1);
+/// The children whose handle is open, by registration id.
+static CHILDREN: Mutex<BTreeMap<u64, Entry>> = Mutex::new(BTreeMap::new());
+
+struct Entry {
+    #[cfg(unix)]
+    pid: libc::pid_t,
+    #[cfg(not(unix))]
+    killer: Box<dyn portable_pty::ChildKiller + Send + Sync>,
+}
+
+/// Lists `child` in the registry and returns its id, or 0 if it cannot be reached.
+pub(crate) fn register(child: &dyn portable_pty::Child) -> u64 {
+    #[cfg(unix)]
+    let Some(entry) = child.process_id().map(|pid| Entry {
+        pid: pid as libc::pid_t,
+    }) else {
+        return 0;
+    };
+    #[cfg(not(unix))]
+    let entry = Entry {
+        killer: child.clone_killer(),
+    };
+    let id = NEXT_ID.fetch_add(1, Ordering::Relaxed);
+    CHILDREN.lock().unwrap().insert(id, entry);
+    id
+}
+
+/// Removes a child from the registry. Called when its handle is freed.
+pub(crate) fn unregister(id: u64) {
+    CHILDREN.lock().unwrap().remove(&id);
+}
+
+/// Whether `pid` is a child of this process that has not been reaped, without reaping it.
+#[cfg(unix)]
+fn unreaped(pid: libc::pid_t) -> bool {
+    let mut info: libc::siginfo_t = unsafe { std::mem::zeroed() };
+    unsafe {
+        libc::waitid(
+            libc::P_PID,
+            pid as libc::id_t,
+            &mut info,
+            libc::WEXITED | libc::WNOHANG | libc::WNOWAIT,
+        ) == 0
+    }
+}
+
+/// Sends `signal` to the child's process group, or to the child alone if it leads none.
+#[cfg(unix)]
+fn signal_job(pid: libc::pid_t, signal: libc::c_int) {
+    unsafe {
+        if libc::killpg(pid, signal) == -1 {
+            libc::kill(pid, signal);
+        }
+    }
+}
+
+/// Reaps those of `pids` that have exited, polling until `deadline`, and returns the rest.
+#[cfg(unix)]
+fn reap_until(mut pids: Vec<libc::pid_t>, deadline: Instant) -> Vec<libc::pid_t> {
+    loop {
+        pids.retain(|&pid| {
+            let mut status = 0;
+            // 0 means still running; anything else means reaped, here or elsewhere.
+            unsafe { libc::waitpid(pid, &mut status, libc::WNOHANG) == 0 }
+        });
+        if pids.is_empty() || Instant::now() >= deadline {
+            return pids;
+        }
+        std::thread::sleep(POLL_INTERVAL);
+    }
+}
+
+/// Shut down every child whose handle is still open, for a host that is about to exit.
+/// Returns the number of children shut down.
+///
+/// Ends the children the way a hangup would: on Unix each gets SIGHUP, sent to its process
+/// group so that jobs it started go too, then up to `timeout_ms` to exit. Those still
+/// running are killed with SIGKILL, and all of them are reaped, so none is left orphaned
+/// or as a zombie. A child that survives even SIGKILL for a second, e.g. stuck in an
+/// uninterruptible wait, is left behind and not counted. On Windows each child is
+/// terminated at once and `timeout_ms` is unused. Children whose handle was freed, such as
+/// detached ones, are not included.
+///
+/// Call it from the host's exit path, e.g. a Bun `process.on("exit")` handler. It takes
+/// locks and allocates, so it must not run inside a signal handler itself; have the
+/// handler schedule it instead. After it returns, treat every child handle as invalid:
+/// its process is gone and its status was collected here, so waits on it fail. Handles
+/// are not freed, since the host still owns them and would free them a second time; free
+/// them as usual, or simply exit.
+#[unsafe(no_mangle)]
+pub extern "C" fn pty_shutdown_all(timeout_ms: u32) -> i32 {
+    ffi_call("pty_shutdown_all", std::ptr::null_mut(), || {
+        let entries = std::mem::take(&mut *CHILDREN.lock().unwrap());
+        #[cfg(unix)]
+        {
+            let pids: Vec<libc::pid_t> = entries
+                .into_values()
+                .map(|entry| entry.pid)
+                .filter(|&pid| unreaped(pid))
+                .collect();
+            let count = pids.len();
+            for &pid in &pids {
+                signal_job(pid, libc::SIGHUP);
+                // A stopped child cannot act on SIGHUP until it is continued.
+                signal_job(pid, libc::SIGCONT);
+            }
+            let deadline = Instant::now() + Duration::from_millis(u64::from(timeout_ms));
+            let survivors = reap_until(pids, deadline);
+            for &pid in &survivors {
+                signal_job(pid, libc::SIGKILL);
+            }
+            let stuck = reap_until(survivors, Instant::now() + KILL_GRACE);
+            Ok((count - stuck.len()) as i32)
+        }
+        #[cfg(not(unix))]
+        {
+            let _ = timeout_ms;
+            let count = entries
+                .into_values()
+                .filter_map(|mut entry| entry.killer.kill().ok())
+                .count();
+            Ok(count as i32)
+        }
+    })
+}
@@ -0,0 +1,301 @@
+//! Unix spawn path used by builders.
+//!
+//! portable-pty's `SlavePty::spawn_command` runs a fixed setup between fork and exec with no
+//! way to extend it, so builders spawn through here instead. The child gets the same setup
+//! (new session, the PTY as controlling terminal, stray fds closed) plus whatever post-fork
+//! options the builder carries.
+
+use crate::builder::{Builder, StdioTarget};
+use crate::error::{PtyError, PtyResult};
+use crate::resolve::resolve_program;
+use crate::{Child, Master, slave_device};
+use portable_pty::{CommandBuilder, native_pty_system};
+use std::fs::File;
+use std::io::{self, Write};
+use std::os::fd::{AsRawFd, BorrowedFd, OwnedFd, RawFd};
+use std::os::unix::process::CommandExt;
+use std::path::PathBuf;
+use std::process::{Command, Stdio};
+
+/// Highest signal number tried when resetting dispositions. `signal` rejects numbers the
+/// platform does not have, so overshooting is harmless.
+const MAX_SIGNAL: libc::c_int = 64;
+
+/// Opens a PTY of the builder's size and spawns its command on the slave side.
+pub(crate) fn spawn(builder: &Builder) -> PtyResult<(Master, Child)> {
+    let pair = native_pty_system().openpty(builder.size)?;
+    let slave = File::from(crate::fd::open_slave(&*pair.master)?);
+
+    announce_labels(builder, &slave)?;
+    let mut cmd = command(&builder.cmd)?;
+    if let Some(arg0) = &builder.arg0 {
+        cmd.arg0(arg0);
+    }
+    let [stdin, stdout, stderr] = builder.stdio;
+    cmd.stdin(stdio(stdin, &slave)?);
+    cmd.stdout(stdio(stdout, &slave)?);
+    let mut stderr_pipe = None;
+    cmd.stderr(match stderr {
+        StdioTarget::Pipe => {
+            let (reader, writer) = io::pipe()?;
+            stderr_pipe = Some(OwnedFd::from(reader));
+            writer.into()
+        }
+        target => stdio(target, &slave)?,
+    });
+
+    let signals = if builder.reset_signals {
+        Signals::Default
+    } else {
+        Signals::inherit()
+    };
+    // The child inherits this descriptor until exec, so it can claim the PTY as its
+    // controlling terminal even when stdin goes elsewhere.
+    let controlling_tty = builder.cmd.get_controlling_tty().then(|| slave.as_raw_fd());
+    let max_fds = builder.max_fds;
+    let minimal_fds = builder.minimal_fds;
+    #[cfg(target_os = "linux")]
+    let affinity = cpu_set(&builder.cpu_affinity);
+    #[cfg(target_os = "linux")]
+    let namespaces = crate::namespace::Namespaces::new(builder.namespaces);
+    #[cfg(target_os = "linux")]
+    let sandboxed = namespaces.is_some();
+    unsafe {
+        cmd.pre_exec(move || {
+            post_fork(&signals, controlling_tty)?;
+            #[cfg(target_os = "linux")]
+            set_affinity(affinity.as_ref())?;
+            // Entered while std's pipe for reporting exec errors is still open, so a refusal
+            // fails the spawn instead of the command.
+            #[cfg(target_os = "linux")]
+            if let Some(namespaces) = &namespaces {
+                namespaces.enter()?;
+            }
+            portable_pty::unix::close_random_fds();
+            if minimal_fds {
+                cloexec_stray_fds();
+            }
+            lower_max_fds(max_fds)
+        });
+    }
+    let child = cmd.spawn().map_err(|e| {
+        #[cfg(target_os = "linux")]
+        if sandboxed {
+            return crate::namespace::spawn_error(e);
+        }
+        PtyError::from(e)
+    })?;
+    drop(slave);
+    // Only the child needs the slave open; keeping it would hold off EOF on the master.
+    // Likewise the write end of a stderr pipe, which `cmd` holds.
+    drop(pair.slave);
+    drop(cmd);
+    let tty_dev = slave_device(&*pair.master);
+    let mut master = Master::new(pair.master);
+    master.stderr = stderr_pipe;
+    if let Some(title) = &builder.title {
+        master.session.set_title(title.clone());
+    }
+    Ok((master, Child::new(Box::new(child), tty_dev)))
+}
+
+/// Writes the builder's title (OSC 2) and icon name (OSC 1) to the slave, so they reach the
+/// master's output ahead of anything the child writes.
+fn announce_labels(builder: &Builder, mut slave: &File) -> io::Result<()> {
+    let mut sequences = String::new();
+    if let Some(icon_name) = &builder.icon_name {
+        sequences.push_str(&format!("\x1b]1;{icon_name}\x07"));
+    }
+    if let Some(title) = &builder.title {
+        sequences.push_str(&format!("\x1b]2;{title}\x07"));
+    }
+    if !sequences.is_empty() {
+        slave.write_all(sequences.as_bytes())?;
+    }
+    Ok(())
+}
+
+/// The `Stdio` for a stream sent to `target`.
+fn stdio(target: StdioTarget, slave: &File) -> PtyResult<Stdio> {
+    Ok(match target {
+        StdioTarget::Pty => slave.try_clone()?.into(),
+        StdioTarget::Pipe => unreachable!("only stderr goes to a pipe"),
+        StdioTarget::Null => Stdio::null(),
+        StdioTarget::Fd(fd) => unsafe { BorrowedFd::borrow_raw(fd) }
+            .try_clone_to_owned()
+            .map_err(|e| PtyError::new(format!("Unable to use file descriptor {fd}: {e}")))?
+            .into(),
+    })
+}
+
+/// Signal state the child starts with.
+enum Signals {
+    /// Every disposition reset to default and nothing blocked.
+    Default,
+    /// The parent's state. Handlers are still reset by exec, but ignored signals and the
+    /// blocked mask carry over. Captured up front because std resets the mask and SIGPIPE
+    /// in the child before running `pre_exec` hooks.
+    Inherit {
+        mask: libc::sigset_t,
+        sigpipe_ignored: bool,
+    },
+}
+
+impl Signals {
+    fn inherit() -> Self {
+        unsafe {
+            let mut mask: libc::sigset_t = std::mem::zeroed();
+            libc::pthread_sigmask(libc::SIG_BLOCK, std::ptr::null(), &mut mask);
+            let mut action: libc::sigaction = std::mem::zeroed();
+            libc::sigaction(libc::SIGPIPE, std::ptr::null(), &mut action);
+            Signals::Inherit {
+                mask,
+                sigpipe_ignored: action.sa_sigaction == libc::SIG_IGN,
+            }
+        }
+    }
+}
+
+/// Runs in the child between fork and exec.
+fn post_fork(signals: &Signals, controlling_tty: Option<RawFd>) -> io::Result<()> {
+    unsafe {
+        match signals {
+            Signals::Default => {
+                for signo in 1..=MAX_SIGNAL {
+                    if signo != libc::SIGKILL && signo != libc::SIGSTOP {
+                        libc::signal(signo, libc::SIG_DFL);
+                    }
+                }
+                let empty_set: libc::sigset_t = std::mem::zeroed();
+                libc::sigprocmask(libc::SIG_SETMASK, &empty_set, std::ptr::null_mut());
+            }
+            Signals::Inherit {
+                mask,
+                sigpipe_ignored,
+            } => {
+                if *sigpipe_ignored {
+                    libc::signal(libc::SIGPIPE, libc::SIG_IGN);
+                }
+                libc::sigprocmask(libc::SIG_SETMASK, mask, std::ptr::null_mut());
+            }
+        }
+
+        if libc::setsid() == -1 {
+            return Err(io::Error::last_os_error());
+        }
+        // Without a controlling terminal the child gets no SIGWINCH on resize and no
+        // job control.
+        #[allow(clippy::cast_lossless)]
+        if let Some(tty) = controlling_tty
+            && libc::ioctl(tty, libc::TIOCSCTTY as _, 0) == -1
+        {
+            return Err(io::Error::last_os_error());
+        }
+    }
+    Ok(())
+}
+
+/// The `cpu_set_t` for a builder's CPU mask, built before fork; `None` to inherit.
+#[cfg(target_os = "linux")]
+fn cpu_set(mask: &[u64]) -> Option<libc::cpu_set_t> {
+    if mask.is_empty() {
+        return None;
+    }
+    let mut set: libc::cpu_set_t = unsafe { std::mem::zeroed() };
+    for cpu in 0..(mask.len() * 64).min(libc::CPU_SETSIZE as usize) {
+        if mask[cpu / 64] & (1 << (cpu % 64)) != 0 {
+            unsafe { libc::CPU_SET(cpu, &mut set) };
+        }
+    }
+    Some(set)
+}
+
+/// Runs in the child between fork and exec.
+#[cfg(target_os = "linux")]
+fn set_affinity(set: Option<&libc::cpu_set_t>) -> io::Result<()> {
+    if let Some(set) = set
+        && unsafe { libc::sched_setaffinity(0, size_of::<libc::cpu_set_t>(), set) } == -1
+    {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+/// Marks every descriptor above stderr close-on-exec, so none reaches the program even if
+/// `close_random_fds` could not list them. Leaving the closing to exec keeps open std's
+/// pipe for reporting exec errors, which is close-on-exec already.
+fn cloexec_stray_fds() {
+    #[cfg(target_os = "linux")]
+    if unsafe {
+        libc::syscall(
+            libc::SYS_close_range,
+            3,
+            libc::c_uint::MAX,
+            libc::CLOSE_RANGE_CLOEXEC,
+        )
+    } == 0
+    {
+        return;
+    }
+    // Without close_range (before Linux 5.11, or elsewhere), every possible descriptor.
+    let mut limit: libc::rlimit = unsafe { std::mem::zeroed() };
+    let end = match unsafe { libc::getrlimit(libc::RLIMIT_NOFILE, &mut limit) } {
+        0 if limit.rlim_cur != libc::RLIM_INFINITY => limit.rlim_cur.min(1 << 20),
+        _ => 1 << 20,
+    };
+    for fd in 3..end as libc::c_int {
+        unsafe { libc::fcntl(fd, libc::F_SETFD, libc::FD_CLOEXEC) };
+    }
+}
+
+/// Runs in the child right before exec. Lowered last: closing stray fds needs a descriptor
+/// to list them.
+fn lower_max_fds(max_fds: Option<u64>) -> io::Result<()> {
+    if let Some(max_fds) = max_fds {
+        let limit = libc::rlimit {
+            rlim_cur: max_fds as libc::rlim_t,
+            rlim_max: max_fds as libc::rlim_t,
+        };
+        if unsafe { libc::setrlimit(libc::RLIMIT_NOFILE, &limit) } == -1 {
+            return Err(io::Error::last_os_error());
+        }
+    }
+    Ok(())
+}
+
+/// Translates a `CommandBuilder` into a `Command` the way portable-pty does: the full
+/// environment, the configured cwd (falling back to the home directory), and a login
+/// shell for the default program.
+fn command(builder: &CommandBuilder) -> PtyResult<Command> {
+    let home = builder
+        .get_env("HOME")
+        .map(PathBuf::from)
+        .unwrap_or_else(|| PathBuf::from("/"));
+    let dir = builder
+        .get_cwd()
+        .map(PathBuf::from)
+        .filter(|dir| dir.is_dir())
+        .unwrap_or(home);
+    let shell = builder.get_shell();
+
+    let mut cmd = if builder.is_default_prog() {
+        let mut cmd = Command::new(&shell);
+        let basename = shell.rsplit('/').next().unwrap_or(&shell);
+        cmd.arg0(format!("-{basename}"));
+        cmd
+    } else {
+        let argv = builder.get_argv();
+        // Not-found is reported as a plain spawn error here.
+        let program = resolve_program(&argv[0], &dir, builder.get_env("PATH"))
+            .map_err(|e| PtyError::new(e.message))?;
+        let mut cmd = Command::new(program);
+        cmd.arg0(&argv[0]);
+        cmd.args(&argv[1..]);
+        cmd
+    };
+    cmd.current_dir(&dir);
+    cmd.env_clear();
+    cmd.env("SHELL", &shell);
+    cmd.envs(builder.iter_full_env_as_str());
+    Ok(cmd)
+}
@@ -0,0 +1,295 @@
+//! Saving and restoring the PTY's terminal settings as a whole, resetting them, and
+//! inspecting the line discipline.
+//!
+//! A snapshot is the raw `termios` struct behind a short header recording its size, so a
+//! blob from a build with a different layout is rejected instead of misread. Blobs are
+//! only meant to be restored by the same build on the same system.
+
+use crate::error::{PtyError, ffi_call};
+use crate::handle::handle_mut;
+use crate::{MasterHandle, buf_ref, out_ref};
+
+/// Marks the start of a snapshot.
+#[cfg(unix)]
+const MAGIC: &[u8; 4] = b"PTYT";
+/// Bytes in front of the `termios` struct: the magic and the struct's size.
+#[cfg(unix)]
+const HEADER_LEN: usize = 8;
+#[cfg(unix)]
+const BLOB_LEN: usize = HEADER_LEN + std::mem::size_of::<libc::termios>();
+
+/// Serializes `termios` into a snapshot.
+#[cfg(unix)]
+fn to_blob(termios: &libc::termios) -> Vec<u8> {
+    let mut blob = Vec::with_capacity(BLOB_LEN);
+    blob.extend_from_slice(MAGIC);
+    blob.extend_from_slice(&(std::mem::size_of::<libc::termios>() as u32).to_ne_bytes());
+    let raw = unsafe {
+        std::slice::from_raw_parts(
+            (termios as *const libc::termios).cast::<u8>(),
+            std::mem::size_of::<libc::termios>(),
+        )
+    };
+    blob.extend_from_slice(raw);
+    blob
+}
+
+/// Parses a snapshot made by `to_blob`.
+#[cfg(unix)]
+fn from_blob(blob: &[u8]) -> Result<libc::termios, PtyError> {
+    let size = std::mem::size_of::<libc::termios>();
+    if blob.len() != BLOB_LEN
+        || &blob[..4] != MAGIC
+        || blob[4..HEADER_LEN] != (size as u32).to_ne_bytes()
+    {
+        return Err(PtyError::new(
+            "Not a terminal settings snapshot from pty_get_termios_blob",
+        ));
+    }
+    let mut termios = std::mem::MaybeUninit::<libc::termios>::uninit();
+    unsafe {
+        std::ptr::copy_nonoverlapping(
+            blob[HEADER_LEN..].as_ptr(),
+            termios.as_mut_ptr().cast::<u8>(),
+            size,
+        );
+        Ok(termios.assume_init())
+    }
+}
+
+/// Take a snapshot of the PTY's terminal settings (Unix only).
+/// Returns 0 on success, -1 on error, -2 on other platforms; sets out_err_msg (caller frees via pty_free_err_msg).
+///
+/// Stores an opaque blob of every termios setting (modes, control characters, speeds) in
+/// `out_blob` and its length in `out_len`; restore it with `pty_set_termios_blob`, e.g.
+/// after putting the terminal in raw mode. The length is stored even when `blob_len` is
+/// too small, so the caller can retry with a larger buffer; 128 bytes is enough on Linux
+/// and macOS. Windows pseudoconsoles expose no console mode to the host side, so there is
+/// nothing to snapshot there.
+///
+/// # Safety
+///
+/// Caller must ensure:
+/// - `master` is a valid, non-null handle obtained from `pty_open_and_spawn` or `pty_open`.
+/// - `out_blob` is a valid, non-null pointer to mutable memory of at least `blob_len` bytes.
+/// - `out_len` and `out_err_msg` are valid, non-null pointers to mutable memory.
+/// - The caller must free any error message using `pty_free_err_msg`.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn pty_get_termios_blob(
+    master: MasterHandle,
+    out_blob: *mut u8,
+    blob_len: usize,
+    out_len: *mut usize,
+    out_err_msg: *mut *mut libc::c_char,
+) -> i32 {
+    ffi_call("pty_get_termios_blob", out_err_msg, || unsafe {
+        let master_struct = handle_mut(master)?;
+        let out_len = out_ref(out_len)?;
+        #[cfg(unix)]
+        {
+            *out_len = BLOB_LEN;
+            if blob_len < BLOB_LEN {
+                return Err(PtyError::new(format!(
+                    "Buffer too small: {BLOB_LEN} bytes needed"
+                )));
+            }
+            let out_blob = crate::buf_mut(out_blob, blob_len)?;
+            let mut termios = std::mem::MaybeUninit::<libc::termios>::uninit();
+            if libc::tcgetattr(master_struct.fd()?, termios.as_mut_ptr()) == -1 {
+                return Err(std::io::Error::last_os_error().into());
+            }
+            out_blob[..BLOB_LEN].copy_from_slice(&to_blob(&termios.assume_init()));
+            Ok(0)
+        }
+        #[cfg(not(unix))]
+        {
+            let _ = (master_struct, out_blob, blob_len, out_len);
+            Err(PtyError::unsupported("pty_get_termios_blob"))
+        }
+    })
+}
+
+/// Restore terminal settings saved with `pty_get_termios_blob` (Unix only).
+/// Returns 0 on success, -1 on error, -2 on other platforms; sets out_err_msg (caller frees via pty_free_err_msg).
+///
+/// Applies every setting in the snapshot at once (`tcsetattr` with `TCSANOW`). A blob not
+/// produced by `pty_get_termios_blob` of this build is rejected. It may come from another
+/// PTY, which copies that PTY's settings over.
+///
+/// # Safety
+///
+/// Caller must ensure:
+/// - `master` is a valid, non-null handle obtained from `pty_open_and_spawn` or `pty_open`.
+/// - `blob` is a valid, non-null pointer to immutable memory of at least `len` bytes.
+/// - `out_err_msg` is a valid, non-null pointer to mutable memory.
+/// - The caller must free any error message using `pty_free_err_msg`.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn pty_set_termios_blob(
+    master: MasterHandle,
+    blob: *const u8,
+    len: usize,
+    out_err_msg: *mut *mut libc::c_char,
+) -> i32 {
+    ffi_call("pty_set_termios_blob", out_err_msg, || unsafe {
+        let master_struct = handle_mut(master)?;
+        let blob = buf_ref(blob, len)?;
+        #[cfg(unix)]
+        {
+            let termios = from_blob(blob)?;
+            if libc::tcsetattr(master_struct.fd()?, libc::TCSANOW, &termios) == -1 {
+                return Err(std::io::Error::last_os_error().into());
+            }
+            Ok(0)
+        }
+        #[cfg(not(unix))]
+        {
+            let _ = (master_struct, blob);
+            Err(PtyError::unsupported("pty_set_termios_blob"))
+        }
+    })
+}
+
+/// Get the number of the PTY's current line discipline (Linux only).
+/// Returns 0 on success, -1 on error, -2 on other platforms; sets out_err_msg (caller frees via pty_free_err_msg).
+///
+/// A diagnostic for unusual terminal behavior: the line discipline is the kernel layer
+/// between the terminal and the program that implements echo, line editing and signal
+/// characters. Stores its number (`TIOCGETD`) in `out_ldisc`; 0 is `N_TTY`, the standard
+/// one. The names of the disciplines the kernel has loaded are listed in
+/// `/proc/tty/ldiscs`. The program's side of the PTY is asked, through a slave fd opened
+/// for the purpose, since that is where a program changes it with `TIOCSETD`; the master
+/// keeps a discipline of its own.
+///
+/// # Safety
+///
+/// Caller must ensure:
+/// - `master` is a valid, non-null handle obtained from `pty_open_and_spawn` or `pty_open`.
+/// - `out_ldisc` and `out_err_msg` are valid, non-null pointers to mutable memory.
+/// - The caller must free any error message using `pty_free_err_msg`.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn pty_line_discipline(
+    master: MasterHandle,
+    out_ldisc: *mut i32,
+    out_err_msg: *mut *mut libc::c_char,
+) -> i32 {
+    ffi_call("pty_line_discipline", out_err_msg, || unsafe {
+        let master_struct = handle_mut(master)?;
+        let out_ldisc = out_ref(out_ldisc)?;
+        #[cfg(target_os = "linux")]
+        {
+            use std::os::fd::AsRawFd;
+
+            let slave = crate::fd::open_slave(&*master_struct.inner)?;
+            let mut ldisc: libc::c_int = 0;
+            if libc::ioctl(slave.as_raw_fd(), libc::TIOCGETD, &mut ldisc) == -1 {
+                return Err(std::io::Error::last_os_error().into());
+            }
+            *out_ldisc = ldisc;
+            Ok(0)
+        }
+        #[cfg(not(target_os = "linux"))]
+        {
+            let _ = (master_struct, out_ldisc);
+            Err(PtyError::unsupported("pty_line_discipline"))
+        }
+    })
+}
+
+/// Full reset (RIS): makes the terminal drop modes, attributes and the screen contents.
+#[cfg(unix)]
+const RESET_SEQUENCE: &[u8] = b"\x1bc";
+
+/// Replaces `termios` with settings like those of `stty sane`: cooked mode with echo, the
+/// usual output processing and the standard control characters. Speeds, character size
+/// and the other hardware settings are kept, and so is IUTF8 on Linux.
+#[cfg(unix)]
+fn make_sane(termios: &mut libc::termios) {
+    #[cfg(target_os = "linux")]
+    let utf8 = termios.c_iflag & libc::IUTF8;
+    #[cfg(not(target_os = "linux"))]
+    let utf8 = 0;
+    termios.c_iflag = libc::BRKINT | libc::ICRNL | libc::IXON | libc::IMAXBEL | utf8;
+    termios.c_oflag = libc::OPOST | libc::ONLCR;
+    termios.c_cflag |= libc::CREAD;
+    termios.c_lflag = libc::ISIG
+        | libc::ICANON
+        | libc::IEXTEN
+        | libc::ECHO
+        | libc::ECHOE
+        | libc::ECHOK
+        | libc::ECHOCTL
+        | libc::ECHOKE;
+    for (index, value) in [
+        (libc::VINTR, 0x03),
+        (libc::VQUIT, 0x1c),
+        (libc::VERASE, 0x7f),
+        (libc::VKILL, 0x15),
+        (libc::VEOF, 0x04),
+        (libc::VEOL, 0),
+        (libc::VEOL2, 0),
+        (libc::VSTART, 0x11),
+        (libc::VSTOP, 0x13),
+        (libc::VSUSP, 0x1a),
+        (libc::VREPRINT, 0x12),
+        (libc::VWERASE, 0x17),
+        (libc::VLNEXT, 0x16),
+        (libc::VDISCARD, 0x0f),
+        (libc::VMIN, 1),
+        (libc::VTIME, 0),
+    ] {
+        termios.c_cc[index] = value;
+    }
+}
+
+/// Put the terminal back into a known-good state, like `tput reset` (Unix only).
+/// Returns 0 on success, -1 on error, -2 on other platforms; sets out_err_msg (caller frees via pty_free_err_msg).
+///
+/// The button for a session gone sideways, e.g. after a program crashed in raw mode.
+/// First the terminal settings are restored to those of `stty sane`: line editing,
+/// echo, signal keys (Ctrl-C, Ctrl-Z, ...) and newline translation on, with the
+/// standard control characters; speeds and character size stay as they are. Then the
+/// full reset sequence `ESC c` (RIS) is written to the program's side of the PTY, so it
+/// comes out of the master as if the program had printed it. Whatever renders the
+/// output, the caller's terminal emulator or a `pty_vt_new` screen, then drops colors,
+/// modes, the alternate screen and the scroll region and clears the screen. A program
+/// that is still running is not told and may set its modes again.
+///
+/// # Safety
+///
+/// Caller must ensure:
+/// - `master` is a valid, non-null handle obtained from `pty_open_and_spawn` or `pty_open`.
+/// - `out_err_msg` is a valid, non-null pointer to mutable memory.
+/// - The caller must free any error message using `pty_free_err_msg`.
+/// - No concurrent mutation or invalidation of the master handle.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn pty_reset_terminal(
+    master: MasterHandle,
+    out_err_msg: *mut *mut libc::c_char,
+) -> i32 {
+    ffi_call("pty_reset_terminal", out_err_msg, || unsafe {
+        let master_struct = handle_mut(master)?;
+        #[cfg(unix)]
+        {
+            use std::io::Write;
+
+            let fd = master_struct.fd()?;
+            let mut termios = std::mem::MaybeUninit::<libc::termios>::uninit();
+            if libc::tcgetattr(fd, termios.as_mut_ptr()) == -1 {
+                return Err(std::io::Error::last_os_error().into());
+            }
+            let mut termios = termios.assume_init();
+            make_sane(&mut termios);
+            if libc::tcsetattr(fd, libc::TCSANOW, &termios) == -1 {
+                return Err(std::io::Error::last_os_error().into());
+            }
+            std::fs::File::from(crate::fd::open_slave(&*master_struct.inner)?)
+                .write_all(RESET_SEQUENCE)?;
+            Ok(0)
+        }
+        #[cfg(not(unix))]
+        {
+            let _ = master_struct;
+            Err(PtyError::unsupported("pty_reset_terminal"))
+        }
+    })
+}
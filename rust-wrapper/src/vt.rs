@@ -0,0 +1,507 @@
+//! A minimal terminal emulator that renders output as a screen of plain text.
+//!
+//! Output is fed in as it arrives and applied to a grid of cells, so the screen a program
+//! drew can be read back, e.g. for snapshot tests of terminal UIs. Only the common
+//! sequences are interpreted: cursor movement and positioning, erasing, inserting and
+//! deleting characters and lines, scrolling and scroll regions, tab stops every 8
+//! columns, saving the cursor, autowrap and the alternate screen. Everything else, such
+//! as colors and other attributes, titles and the remaining modes, is parsed and ignored.
+//! Every character takes one cell: wide CJK characters and emoji take one column instead
+//! of two and combining marks a cell of their own, so lines containing them come out
+//! misaligned.
+
+use crate::ansi;
+use crate::error::{PtyError, ffi_call};
+use crate::handle::{free_handle, handle_mut, into_handle};
+use crate::{VtHandle, buf_ref, copy_str_out, out_ref};
+
+const ESC: u8 = 0x1b;
+const TAB_WIDTH: usize = 8;
+/// Longest unfinished escape sequence kept for the next feed; a longer one is dropped.
+const MAX_PARTIAL: usize = 4096;
+/// Largest CSI parameter; bigger ones are clamped to it, as in xterm.
+const MAX_PARAM: usize = u16::MAX as usize;
+
+type Grid = Vec<Vec<char>>;
+
+pub(crate) struct Vt {
+    rows: usize,
+    cols: usize,
+    cells: Grid,
+    /// The main screen's cells while the alternate screen is shown.
+    main: Option<Grid>,
+    row: usize,
+    col: usize,
+    /// The last column was written: the next character goes to the start of a new line.
+    wrap_pending: bool,
+    autowrap: bool,
+    /// Scroll region, first and last row inclusive.
+    top: usize,
+    bottom: usize,
+    /// Cursor saved with `ESC 7` or `CSI s`.
+    saved: (usize, usize),
+    /// An escape sequence or UTF-8 character cut off at the end of the last feed.
+    partial: Vec<u8>,
+}
+
+fn blank_grid(rows: usize, cols: usize) -> Grid {
+    vec![vec![' '; cols]; rows]
+}
+
+/// Length of the UTF-8 sequence `lead` starts; 1 for bytes that cannot start one.
+fn utf8_len(lead: u8) -> usize {
+    match lead {
+        0xc2..=0xdf => 2,
+        0xe0..=0xef => 3,
+        0xf0..=0xf4 => 4,
+        _ => 1,
+    }
+}
+
+impl Vt {
+    fn new(rows: usize, cols: usize) -> Self {
+        Vt {
+            rows,
+            cols,
+            cells: blank_grid(rows, cols),
+            main: None,
+            row: 0,
+            col: 0,
+            wrap_pending: false,
+            autowrap: true,
+            top: 0,
+            bottom: rows - 1,
+            saved: (0, 0),
+            partial: Vec::new(),
+        }
+    }
+
+    /// Applies a chunk of output. Sequences and characters split across chunks are
+    /// completed by the next one.
+    fn feed(&mut self, data: &[u8]) {
+        let mut buf = std::mem::take(&mut self.partial);
+        buf.extend_from_slice(data);
+        let mut i = 0;
+        while i < buf.len() {
+            let byte = buf[i];
+            if byte == ESC {
+                let Some((_, len)) = ansi::sequence_at(&buf[i..]) else {
+                    break;
+                };
+                self.escape(&buf[i..i + len]);
+                i += len;
+            } else if byte < 0x20 || byte == 0x7f {
+                self.control(byte);
+                i += 1;
+            } else {
+                let len = utf8_len(byte);
+                if i + len > buf.len() {
+                    break;
+                }
+                match std::str::from_utf8(&buf[i..i + len]) {
+                    Ok(s) => {
+                        self.print(s.chars().next().unwrap_or(char::REPLACEMENT_CHARACTER));
+                        i += len;
+                    }
+                    Err(_) => {
+                        self.print(char::REPLACEMENT_CHARACTER);
+                        i += 1;
+                    }
+                }
+            }
+        }
+        if buf.len() - i <= MAX_PARTIAL {
+            self.partial = buf[i..].to_vec();
+        }
+    }
+
+    fn print(&mut self, c: char) {
+        if self.wrap_pending {
+            self.wrap_pending = false;
+            self.col = 0;
+            self.line_feed();
+        }
+        self.cells[self.row][self.col] = c;
+        if self.col + 1 < self.cols {
+            self.col += 1;
+        } else if self.autowrap {
+            self.wrap_pending = true;
+        }
+    }
+
+    fn control(&mut self, byte: u8) {
+        match byte {
+            0x08 => {
+                if !self.wrap_pending {
+                    self.col = self.col.saturating_sub(1);
+                }
+            }
+            b'\t' => self.col = ((self.col / TAB_WIDTH + 1) * TAB_WIDTH).min(self.cols - 1),
+            b'\n' | 0x0b | 0x0c => self.line_feed(),
+            b'\r' => self.col = 0,
+            _ => return,
+        }
+        self.wrap_pending = false;
+    }
+
+    /// Moves down a line, scrolling the region when the cursor is on its last line.
+    fn line_feed(&mut self) {
+        if self.row == self.bottom {
+            self.scroll_up(1);
+        } else if self.row + 1 < self.rows {
+            self.row += 1;
+        }
+    }
+
+    /// Moves up a line, scrolling the region when the cursor is on its first line.
+    fn reverse_index(&mut self) {
+        if self.row == self.top {
+            self.scroll_down(1);
+        } else {
+            self.row = self.row.saturating_sub(1);
+        }
+    }
+
+    /// Removes `n` lines at `at` within the scroll region, adding blank ones at its end.
+    fn delete_lines(&mut self, at: usize, n: usize) {
+        for _ in 0..n.min(self.bottom + 1 - at) {
+            self.cells.remove(at);
+            self.cells.insert(self.bottom, vec![' '; self.cols]);
+        }
+    }
+
+    /// Adds `n` blank lines at `at` within the scroll region, dropping lines off its end.
+    fn insert_lines(&mut self, at: usize, n: usize) {
+        for _ in 0..n.min(self.bottom + 1 - at) {
+            self.cells.remove(self.bottom);
+            self.cells.insert(at, vec![' '; self.cols]);
+        }
+    }
+
+    fn scroll_up(&mut self, n: usize) {
+        self.delete_lines(self.top, n);
+    }
+
+    fn scroll_down(&mut self, n: usize) {
+        self.insert_lines(self.top, n);
+    }
+
+    /// Moves the cursor up, stopping at the top of the scroll region from inside it.
+    fn cursor_up(&mut self, n: usize) {
+        let limit = if self.row >= self.top { self.top } else { 0 };
+        self.row = self.row.saturating_sub(n).max(limit);
+    }
+
+    /// Moves the cursor down, stopping at the bottom of the scroll region from inside it.
+    fn cursor_down(&mut self, n: usize) {
+        let limit = if self.row <= self.bottom {
+            self.bottom
+        } else {
+            self.rows - 1
+        };
+        self.row = self.row.saturating_add(n).min(limit);
+    }
+
+    fn in_region(&self) -> bool {
+        (self.top..=self.bottom).contains(&self.row)
+    }
+
+    fn erase(&mut self, row: usize, cols: std::ops::Range<usize>) {
+        let end = cols.end.min(self.cols);
+        self.cells[row][cols.start.min(end)..end].fill(' ');
+    }
+
+    fn switch_screen(&mut self, alternate: bool) {
+        match (alternate, self.main.take()) {
+            (true, None) => {
+                let main = std::mem::replace(&mut self.cells, blank_grid(self.rows, self.cols));
+                self.main = Some(main);
+            }
+            (false, Some(main)) => self.cells = main,
+            (_, main) => self.main = main,
+        }
+    }
+
+    fn escape(&mut self, seq: &[u8]) {
+        match seq.get(1) {
+            Some(b'[') => self.csi(seq),
+            Some(&c) if seq.len() == 2 => match c {
+                b'7' => self.saved = (self.row, self.col),
+                b'8' => {
+                    (self.row, self.col) = self.saved;
+                    self.wrap_pending = false;
+                }
+                b'D' => self.line_feed(),
+                b'M' => self.reverse_index(),
+                b'E' => {
+                    self.col = 0;
+                    self.line_feed();
+                }
+                b'c' => *self = Vt::new(self.rows, self.cols),
+                _ => {}
+            },
+            _ => {}
+        }
+    }
+
+    fn csi(&mut self, seq: &[u8]) {
+        let (&last, body) = seq[2..].split_last().unwrap_or((&0, &[]));
+        let (private, body) = match body.split_first() {
+            Some((b'?', rest)) => (true, rest),
+            Some((b'<' | b'=' | b'>', _)) => return,
+            _ => (false, body),
+        };
+        // A malformed sequence, or one with intermediates (like `CSI 2 SP q`): ignored.
+        if !(0x40..=0x7e).contains(&last)
+            || body
+                .iter()
+                .any(|&c| !matches!(c, b'0'..=b'9' | b';' | b':'))
+        {
+            return;
+        }
+        let params: Vec<usize> = body
+            .split(|&c| c == b';')
+            .map(|param| {
+                // Sub-parameters after ':' only refine colors, which are not tracked.
+                let main = param.split(|&c| c == b':').next().unwrap_or_default();
+                match std::str::from_utf8(main) {
+                    Ok("") | Err(_) => 0,
+                    // Only digits are left, so parsing fails only on overflow.
+                    Ok(n) => n.parse().map_or(MAX_PARAM, |n: usize| n.min(MAX_PARAM)),
+                }
+            })
+            .collect();
+        let param = |index: usize| params.get(index).copied().unwrap_or(0);
+        // Missing and zero counts mean 1.
+        let count = |index: usize| param(index).max(1);
+        if private {
+            if matches!(last, b'h' | b'l') {
+                let set = last == b'h';
+                for mode in &params {
+                    self.private_mode(*mode, set);
+                }
+            }
+            return;
+        }
+        if last == b'm' {
+            return;
+        }
+        self.wrap_pending = false;
+        let (rows, cols) = (self.rows, self.cols);
+        match last {
+            b'A' => self.cursor_up(count(0)),
+            b'B' | b'e' => self.cursor_down(count(0)),
+            b'C' | b'a' => self.col = self.col.saturating_add(count(0)).min(cols - 1),
+            b'D' => self.col = self.col.saturating_sub(count(0)),
+            b'E' => {
+                self.cursor_down(count(0));
+                self.col = 0;
+            }
+            b'F' => {
+                self.cursor_up(count(0));
+                self.col = 0;
+            }
+            b'G' | b'`' => self.col = (count(0) - 1).min(cols - 1),
+            b'd' => self.row = (count(0) - 1).min(rows - 1),
+            b'H' | b'f' => {
+                self.row = (count(0) - 1).min(rows - 1);
+                self.col = (count(1) - 1).min(cols - 1);
+            }
+            b'J' => match param(0) {
+                0 => {
+                    self.erase(self.row, self.col..cols);
+                    for row in self.row + 1..rows {
+                        self.erase(row, 0..cols);
+                    }
+                }
+                1 => {
+                    for row in 0..self.row {
+                        self.erase(row, 0..cols);
+                    }
+                    self.erase(self.row, 0..self.col + 1);
+                }
+                2 | 3 => self.cells = blank_grid(rows, cols),
+                _ => {}
+            },
+            b'K' => match param(0) {
+                0 => self.erase(self.row, self.col..cols),
+                1 => self.erase(self.row, 0..self.col + 1),
+                2 => self.erase(self.row, 0..cols),
+                _ => {}
+            },
+            b'L' if self.in_region() => self.insert_lines(self.row, count(0)),
+            b'M' if self.in_region() => self.delete_lines(self.row, count(0)),
+            b'@' => {
+                let line = &mut self.cells[self.row];
+                for _ in 0..count(0).min(cols - self.col) {
+                    line.pop();
+                    line.insert(self.col, ' ');
+                }
+            }
+            b'P' => {
+                let line = &mut self.cells[self.row];
+                for _ in 0..count(0).min(cols - self.col) {
+                    line.remove(self.col);
+                    line.push(' ');
+                }
+            }
+            b'X' => self.erase(self.row, self.col..self.col.saturating_add(count(0))),
+            b'S' => self.scroll_up(count(0)),
+            b'T' => self.scroll_down(count(0)),
+            b'r' => {
+                let top = count(0) - 1;
+                let bottom = match param(1) {
+                    0 => rows,
+                    n => n.min(rows),
+                } - 1;
+                if top < bottom {
+                    (self.top, self.bottom) = (top, bottom);
+                    (self.row, self.col) = (0, 0);
+                }
+            }
+            b's' => self.saved = (self.row, self.col),
+            b'u' => (self.row, self.col) = self.saved,
+            _ => {}
+        }
+    }
+
+    fn private_mode(&mut self, mode: usize, set: bool) {
+        match mode {
+            7 => self.autowrap = set,
+            47 | 1047 => self.switch_screen(set),
+            1049 => {
+                if set {
+                    self.saved = (self.row, self.col);
+                    self.switch_screen(true);
+                } else {
+                    self.switch_screen(false);
+                    (self.row, self.col) = self.saved;
+                    self.wrap_pending = false;
+                }
+            }
+            _ => {}
+        }
+    }
+
+    /// The screen as text: one line per row without trailing spaces, and without the
+    /// blank rows below the last non-blank one.
+    fn dump(&self) -> String {
+        let mut lines: Vec<String> = self
+            .cells
+            .iter()
+            .map(|row| {
+                let line: String = row.iter().collect();
+                line.trim_end_matches(' ').to_owned()
+            })
+            .collect();
+        while lines.last().is_some_and(String::is_empty) {
+            lines.pop();
+        }
+        lines.join("\n")
+    }
+}
+
+/// Create a virtual terminal screen that renders output fed to it as text.
+/// Returns 0 on success, -1 on error; sets out_err_msg (caller frees via pty_free_err_msg).
+///
+/// For headless rendering and snapshot tests of terminal UIs: feed it what the program
+/// wrote with `pty_vt_feed`, usually everything read from the PTY, then take the screen
+/// with `pty_vt_dump`. Give it the size of the PTY, since programs draw for that size.
+/// The screen starts blank with the cursor at the top left. It interprets the common
+/// sequences only (cursor movement, erasing, inserting and deleting, scroll regions,
+/// autowrap, the alternate screen), ignores colors and other attributes, and gives every
+/// character one cell, so wide CJK characters and emoji misalign their lines.
+///
+/// # Safety
+///
+/// Caller must ensure:
+/// - `vt_out` and `out_err_msg` are valid, non-null pointers to mutable memory.
+/// - The caller must free any error message using `pty_free_err_msg`.
+/// - The handle returned in `vt_out` must be freed using `pty_free_vt`.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn pty_vt_new(
+    rows: u16,
+    cols: u16,
+    vt_out: *mut VtHandle,
+    out_err_msg: *mut *mut libc::c_char,
+) -> i32 {
+    ffi_call("pty_vt_new", out_err_msg, || unsafe {
+        let vt_out = out_ref(vt_out)?;
+        if rows == 0 || cols == 0 {
+            return Err(PtyError::new(format!(
+                "Invalid screen size {rows}x{cols}: both must be at least 1"
+            )));
+        }
+        *vt_out = into_handle(Vt::new(usize::from(rows), usize::from(cols)));
+        Ok(0)
+    })
+}
+
+/// Apply output to a virtual terminal screen.
+/// Returns 0 on success, -1 on error.
+///
+/// Feed output in the order it was read, in chunks of any size: an escape sequence or
+/// UTF-8 character cut off at the end of one chunk is completed by the next. Invalid
+/// UTF-8 shows as U+FFFD.
+///
+/// # Safety
+///
+/// Caller must ensure:
+/// - `vt` is a valid, non-null handle obtained from `pty_vt_new`.
+/// - `buf` is a valid pointer to at least `len` readable bytes (it may be null if `len` is 0).
+/// - No concurrent access to the vt handle.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn pty_vt_feed(vt: VtHandle, buf: *const u8, len: usize) -> i32 {
+    ffi_call("pty_vt_feed", std::ptr::null_mut(), || unsafe {
+        let vt_struct = handle_mut(vt)?;
+        if len > 0 {
+            vt_struct.feed(buf_ref(buf, len)?);
+        }
+        Ok(0)
+    })
+}
+
+/// Get the virtual terminal's screen as plain text.
+/// Returns 0 on success, -1 on error; sets out_err_msg (caller frees via pty_free_err_msg).
+///
+/// Rows are separated by `\n` and have their trailing spaces removed; blank rows below
+/// the last non-blank one are left out, so a blank screen is the empty string. While the
+/// program shows the alternate screen, that is what is returned. Copied into `buf` as
+/// UTF-8 with a terminating NUL; `out_len` receives the length even if `buf_len` is too
+/// small, so the caller can retry with `out_len + 1` bytes.
+///
+/// # Safety
+///
+/// Caller must ensure:
+/// - `vt` is a valid, non-null handle obtained from `pty_vt_new`.
+/// - `buf` is a valid, non-null pointer to mutable memory of at least `buf_len` bytes.
+/// - `out_len` and `out_err_msg` are valid, non-null pointers to mutable memory.
+/// - The caller must free any error message using `pty_free_err_msg`.
+/// - No concurrent access to the vt handle.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn pty_vt_dump(
+    vt: VtHandle,
+    buf: *mut u8,
+    buf_len: usize,
+    out_len: *mut usize,
+    out_err_msg: *mut *mut libc::c_char,
+) -> i32 {
+    ffi_call("pty_vt_dump", out_err_msg, || unsafe {
+        let vt_struct = handle_mut(vt)?;
+        copy_str_out(&vt_struct.dump(), buf, buf_len, out_len)?;
+        Ok(0)
+    })
+}
+
+/// Free the vt handle.
+///
+/// # Safety
+///
+/// Caller must ensure:
+/// - `vt` is either null or a valid handle obtained from `pty_vt_new`.
+/// - The handle is not used after freeing.
+/// - No double-free (call at most once per handle).
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn pty_free_vt(vt: VtHandle) {
+    unsafe { free_handle(vt) }
+}
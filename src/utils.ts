@@ -11,3 +11,13 @@ export function extractErrorMessage(errPtrNumber?: bigint): string {
 		return "FFI call failed with no error message";
 	}
 }
+
+export function extractString(strPtrNumber?: bigint): string | null {
+	const strPtr = Number(strPtrNumber) as Pointer;
+	if (strPtr === 0) {
+		return null;
+	}
+	const str = new CString(strPtr).toString();
+	symbols.pty_free_string(strPtr);
+	return str;
+}
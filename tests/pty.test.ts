@@ -1,5 +1,30 @@
+import { FFIType, JSCallback, type Pointer, ptr } from "bun:ffi";
 import { expect, test } from "bun:test";
+import {
+	closeSync,
+	mkdtempSync,
+	openSync,
+	readFileSync,
+	rmSync,
+	symlinkSync,
+} from "node:fs";
+import { tmpdir } from "node:os";
+import path from "node:path";
+import {
+	BuilderHandle,
+	ChildHandle,
+	MasterHandle,
+	ReaderHandle,
+	VtHandle,
+	pty_child_wait,
+	pty_get_reader,
+	pty_get_stderr_reader,
+	pty_open_and_spawn,
+	pty_read,
+	symbols,
+} from "../index.ts";
 import { Pty } from "../pty.ts";
+import { extractErrorMessage } from "../src/utils";
 
 class Waiter {
 	public resolve: () => void = () => {};
@@ -120,3 +145,401 @@ test("spawn error", async () => {
 		"Unable to spawn /usr/bin/env bash because it doesn't exist on the filesystem (ENOENT: No such file or directory)",
 	);
 });
+
+test.skipIf(isWindows)("child wait survives signals delivered while waiting", () => {
+	let signals = 0;
+	const onSignal = () => {
+		signals++;
+	};
+	process.on("SIGUSR2", onSignal);
+	try {
+		const { master, child } = pty_open_and_spawn(24, 80, "sh", [
+			"-c",
+			"sleep 1; exit 0",
+		]);
+		using _master = master;
+		using _child = child;
+		Bun.spawn([
+			"sh",
+			"-c",
+			`sleep 0.2; kill -USR2 ${process.pid}; sleep 0.2; kill -USR2 ${process.pid}`,
+		]);
+		// Blocks the JS thread; the signals arrive during the native wait.
+		const { exitCode } = pty_child_wait(child);
+		expect(exitCode).toBe(0);
+		// The handle survives the wait and reports the same status again.
+		expect(pty_child_wait(child)).toEqual({ exitCode: 0, signal: 0 });
+	} finally {
+		process.off("SIGUSR2", onSignal);
+	}
+}, 10000);
+
+test.skipIf(isWindows)("full-duplex: write lines while reading echoes from cat", async () => {
+	const receivedMessages: string[] = [];
+	const lineCount = 2000;
+	const lastLine = `line-${lineCount - 1}-${"x".repeat(40)}`;
+	const ready = new Waiter("ready");
+	const done = new Waiter(lastLine);
+
+	let output = "";
+	using pty = new Pty(
+		24,
+		80,
+		"sh",
+		["-c", "stty -echo; echo ready; exec cat"],
+		(msg) => {
+			output += msg;
+			receivedMessages.push(msg);
+			ready.test(output);
+			done.test(output);
+		},
+	);
+	await waitWithTimeout(ready.promise, ready.waitFor, receivedMessages);
+
+	// Far more than the PTY buffers hold: this only completes if the worker keeps
+	// reading while the main thread is writing.
+	for (let i = 0; i < lineCount; i++) {
+		pty.write(`line-${i}-${"x".repeat(40)}\n`);
+	}
+	await waitWithTimeout(done.promise, done.waitFor, receivedMessages);
+
+	const lines = output.split("\r\n").filter((line) => line.startsWith("line-"));
+	expect(lines.length).toBe(lineCount);
+	expect(lines[0]).toBe(`line-0-${"x".repeat(40)}`);
+}, 10000);
+
+test.skipIf(isWindows)("run capture stops at max_output_bytes", () => {
+	const builderOut = new BigUint64Array(1);
+	const errOut = new BigUint64Array(1);
+	expect(
+		symbols.pty_builder_new(Buffer.from("sh\0"), builderOut, errOut),
+	).toBe(0);
+	using builder = new BuilderHandle(Number(builderOut[0]) as Pointer);
+	const args = [Buffer.from("-c\0"), Buffer.from("yes\0")];
+	const argv = new BigUint64Array(args.map((arg) => BigInt(ptr(arg))));
+	expect(symbols.pty_builder_args(builder.handle, argv, args.length)).toBe(0);
+
+	let captured = 0;
+	const sink = new JSCallback(
+		(_data: Pointer, len: number) => {
+			captured += len;
+		},
+		{ args: [FFIType.ptr, FFIType.u64, FFIType.ptr], returns: FFIType.void },
+	);
+	try {
+		const exitCodeOut = new Int32Array(1);
+		// `yes` never stops on its own: this only returns because the cap kills it.
+		const status = symbols.pty_run_capture_limited(
+			builder.handle,
+			sink.ptr,
+			null,
+			4096,
+			true,
+			exitCodeOut,
+			errOut,
+		);
+		expect(status).toBe(-5);
+		expect(extractErrorMessage(errOut[0])).toContain("4096");
+		expect(captured).toBe(4096);
+	} finally {
+		sink.close();
+	}
+}, 10000);
+
+test.skipIf(process.platform !== "linux" || process.arch !== "x64")(
+	"spawn rejects an argument over the per-string limit",
+	() => {
+		expect(Number(symbols.pty_arg_max())).toBeGreaterThan(131072);
+		const errOut = new BigUint64Array(1);
+		const exitCodeOut = new Int32Array(1);
+		// Linux allows 32 pages (128 KiB) per argument, counting its terminating NUL.
+		const runWithArgument = (len: number) => {
+			const builderOut = new BigUint64Array(1);
+			expect(
+				symbols.pty_builder_new(Buffer.from("true\0"), builderOut, errOut),
+			).toBe(0);
+			using builder = new BuilderHandle(Number(builderOut[0]) as Pointer);
+			const arg = Buffer.alloc(len + 1, "a");
+			arg[len] = 0;
+			const argv = new BigUint64Array([BigInt(ptr(arg))]);
+			expect(symbols.pty_builder_args(builder.handle, argv, 1)).toBe(0);
+			return symbols.pty_run_capture_limited(
+				builder.handle,
+				null,
+				null,
+				0,
+				false,
+				exitCodeOut,
+				errOut,
+			);
+		};
+
+		expect(runWithArgument(131071)).toBe(0);
+		expect(exitCodeOut[0]).toBe(0);
+		expect(runWithArgument(131072)).toBe(-6);
+		expect(extractErrorMessage(errOut[0])).toContain("Arguments too long");
+	},
+	10000,
+);
+
+test.skipIf(isWindows)(
+	"spawn full runs a symlinked multi-call binary under the chosen argv0",
+	() => {
+		const bash = Bun.which("bash");
+		if (!bash) throw new Error("bash not found");
+		const dir = mkdtempSync(path.join(tmpdir(), "pty-full-"));
+		try {
+			const link = path.join(dir, "mybash");
+			symlinkSync(bash, link);
+			const masterOut = new BigUint64Array(1);
+			const childOut = new BigUint64Array(1);
+			const errOut = new BigUint64Array(1);
+			const spawnFull = (prog: string) => {
+				const args = ["-c", "shopt -qo posix && echo POSIX-MODE $0"].map(
+					(arg) => Buffer.from(`${arg}\0`),
+				);
+				const argv = new BigUint64Array(args.map((arg) => BigInt(ptr(arg))));
+				return symbols.pty_open_and_spawn_full(
+					24,
+					80,
+					Buffer.from(`${prog}\0`),
+					Buffer.from("sh\0"),
+					argv,
+					args.length,
+					masterOut,
+					childOut,
+					errOut,
+				);
+			};
+
+			// Started as "sh", bash runs in POSIX mode whatever the link is called.
+			expect(spawnFull(link)).toBe(0);
+			using master = new MasterHandle(Number(masterOut[0]) as Pointer);
+			using child = new ChildHandle(Number(childOut[0]) as Pointer);
+			using reader = pty_get_reader(master);
+			const buf = Buffer.alloc(1024);
+			let output = "";
+			while (!output.includes("\n")) {
+				const n = pty_read(reader, buf);
+				if (n === 0) break;
+				output += buf.toString("utf8", 0, n);
+			}
+			expect(output).toContain("POSIX-MODE sh");
+			expect(pty_child_wait(child).exitCode).toBe(0);
+
+			expect(spawnFull(path.join(dir, "missing"))).toBe(-3);
+			expect(extractErrorMessage(errOut[0])).toContain("missing");
+		} finally {
+			rmSync(dir, { recursive: true, force: true });
+		}
+	},
+	10000,
+);
+
+test.skipIf(isWindows)("stderr split off the PTY is read separately", () => {
+	const builderOut = new BigUint64Array(1);
+	const errOut = new BigUint64Array(1);
+	expect(
+		symbols.pty_builder_new(Buffer.from("sh\0"), builderOut, errOut),
+	).toBe(0);
+	using builder = new BuilderHandle(Number(builderOut[0]) as Pointer);
+	const args = [
+		Buffer.from("-c\0"),
+		Buffer.from("echo to-stdout; echo to-stderr >&2; echo more-stderr >&2\0"),
+	];
+	const argv = new BigUint64Array(args.map((arg) => BigInt(ptr(arg))));
+	expect(symbols.pty_builder_args(builder.handle, argv, args.length)).toBe(0);
+	expect(symbols.pty_builder_stdio(builder.handle, 2, 3, -1)).toBe(0);
+
+	const masterOut = new BigUint64Array(1);
+	const childOut = new BigUint64Array(1);
+	expect(
+		symbols.pty_builder_spawn(builder.handle, masterOut, childOut, errOut),
+	).toBe(0);
+	using master = new MasterHandle(Number(masterOut[0]) as Pointer);
+	using child = new ChildHandle(Number(childOut[0]) as Pointer);
+	using reader = pty_get_reader(master);
+	using stderrReader = pty_get_stderr_reader(master);
+
+	// A timed read, to show the stderr reader supports the pty_read* conveniences.
+	const buf = Buffer.alloc(1024);
+	const lenOut = new BigUint64Array(1);
+	const reasonOut = new Int32Array(1);
+	let errors = "";
+	do {
+		expect(
+			symbols.pty_read_bounded(
+				stderrReader.handle,
+				buf,
+				buf.length,
+				TIMEOUT_MS,
+				lenOut,
+				reasonOut,
+				errOut,
+			),
+		).toBe(0);
+		errors += buf.toString("utf8", 0, Number(lenOut[0]));
+	} while (reasonOut[0] === 0);
+	// End of file, not a timeout.
+	expect(reasonOut[0]).toBe(2);
+	expect(errors).toBe("to-stderr\nmore-stderr\n");
+
+	let output = "";
+	for (let n = pty_read(reader, buf); n > 0; n = pty_read(reader, buf)) {
+		output += buf.toString("utf8", 0, n);
+	}
+	expect(output).toContain("to-stdout");
+	expect(output).not.toContain("stderr");
+	expect(pty_child_wait(child).exitCode).toBe(0);
+}, 10000);
+
+test.skipIf(isWindows)("a tap keeps receiving output after the encoding changes", () => {
+	const { master, child } = pty_open_and_spawn(24, 80, "sh", [
+		"-c",
+		"printf 'caf\\351\\n'",
+	]);
+	using _master = master;
+	using _child = child;
+	using reader = pty_get_reader(master);
+	const tapOut = new BigUint64Array(1);
+	const errOut = new BigUint64Array(1);
+	expect(symbols.pty_reader_tap(reader.handle, tapOut, errOut)).toBe(0);
+	using tap = new ReaderHandle(Number(tapOut[0]) as Pointer);
+	expect(
+		symbols.pty_reader_set_encoding(
+			reader.handle,
+			Buffer.from("latin1\0"),
+			errOut,
+		),
+	).toBe(0);
+
+	const readAll = (from: ReaderHandle) => {
+		const buf = Buffer.alloc(1024);
+		let output = "";
+		for (let n = pty_read(from, buf); n > 0; n = pty_read(from, buf)) {
+			output += buf.toString("utf8", 0, n);
+		}
+		return output;
+	};
+	expect(readAll(reader)).toBe("caf\u00e9\r\n");
+	// The tap only reaches EOF with the reader, and gets the transcoded output.
+	expect(readAll(tap)).toBe("caf\u00e9\r\n");
+	expect(pty_child_wait(child).exitCode).toBe(0);
+}, 10000);
+
+test.skipIf(isWindows)("the framed log runs on across an encoding change", () => {
+	const dir = mkdtempSync(path.join(tmpdir(), "pty-log-"));
+	const logPath = path.join(dir, "log");
+	const fd = openSync(logPath, "w");
+	try {
+		const { master, child } = pty_open_and_spawn(24, 80, "sh", [
+			"-c",
+			"printf 'caf\\351\\n'",
+		]);
+		using _master = master;
+		using _child = child;
+		using reader = pty_get_reader(master);
+		const errOut = new BigUint64Array(1);
+		expect(
+			symbols.pty_reader_set_framed_log(reader.handle, fd, true),
+		).toBe(0);
+		expect(
+			symbols.pty_reader_set_encoding(
+				reader.handle,
+				Buffer.from("latin1\0"),
+				errOut,
+			),
+		).toBe(0);
+		const buf = Buffer.alloc(1024);
+		let n: number;
+		do {
+			n = pty_read(reader, buf);
+		} while (n > 0);
+		expect(pty_child_wait(child).exitCode).toBe(0);
+
+		// Records are a 12-byte header (timestamp, length) and the raw data.
+		const log = readFileSync(logPath);
+		const lengths: number[] = [];
+		const data: Buffer[] = [];
+		for (let at = 0; at < log.length; ) {
+			const len = log.readUInt32LE(at + 8);
+			lengths.push(len);
+			data.push(log.subarray(at + 12, at + 12 + len));
+			at += 12 + len;
+		}
+		// Only the end of the output writes the empty record.
+		expect(lengths.indexOf(0)).toBe(lengths.length - 1);
+		expect(Buffer.concat(data)).toEqual(
+			Buffer.from("caf\xe9\r\n", "latin1"),
+		);
+	} finally {
+		closeSync(fd);
+		rmSync(dir, { recursive: true, force: true });
+	}
+}, 10000);
+
+// Feeds each chunk to a new virtual terminal screen with its own `pty_vt_feed` call.
+function renderScreen(
+	rows: number,
+	cols: number,
+	chunks: (string | Buffer)[],
+) {
+	const vtOut = new BigUint64Array(1);
+	const errOut = new BigUint64Array(1);
+	expect(symbols.pty_vt_new(rows, cols, vtOut, errOut)).toBe(0);
+	using vt = new VtHandle(Number(vtOut[0]) as Pointer);
+	for (const chunk of chunks) {
+		const bytes = Buffer.from(chunk);
+		expect(symbols.pty_vt_feed(vt.handle, bytes, bytes.length)).toBe(0);
+	}
+	const buf = Buffer.alloc(4096);
+	const lenOut = new BigUint64Array(1);
+	expect(
+		symbols.pty_vt_dump(vt.handle, buf, buf.length, lenOut, errOut),
+	).toBe(0);
+	return buf.toString("utf8", 0, Number(lenOut[0]));
+}
+
+test("vt wraps at the last column and scrolls at the bottom", () => {
+	expect(renderScreen(3, 5, ["abcdefgh"])).toBe("abcde\nfgh");
+	expect(renderScreen(2, 3, ["abcdefghi"])).toBe("def\nghi");
+	// Without autowrap, each character past the end overwrites the last column.
+	expect(renderScreen(2, 5, ["\x1b[?7labcdefgh"])).toBe("abcdh");
+});
+
+test("vt scrolls only inside the scroll region", () => {
+	const screen = renderScreen(4, 10, [
+		"head\r\n1\r\n2\r\nfoot",
+		// Rows 2 and 3 scroll; two line feeds on row 3 push out "1" and "2".
+		"\x1b[2;3r\x1b[3;1H\n\nx",
+	]);
+	expect(screen).toBe("head\n\nx\nfoot");
+});
+
+test("vt shows the alternate screen and restores the main one", () => {
+	expect(renderScreen(3, 10, ["main\x1b[?1049halt"])).toBe("    alt");
+	expect(renderScreen(3, 10, ["main\x1b[?1049halt\x1b[?1049l!"])).toBe(
+		"main!",
+	);
+});
+
+test("vt completes sequences and characters split across feeds", () => {
+	const e9 = Buffer.from("\u00e9");
+	const screen = renderScreen(2, 10, [
+		"a\x1b[",
+		"2",
+		";5Hb",
+		e9.subarray(0, 1),
+		e9.subarray(1),
+	]);
+	expect(screen).toBe("a\n    b\u00e9");
+});
+
+test("vt clamps huge counts instead of overflowing", () => {
+	const max = "18446744073709551615";
+	const screen = renderScreen(3, 10, [
+		`ab\x1b[${max}Cc\x1b[${max}B\x1b[${max}9Xd`,
+		`\x1b[2;1Hz\x1b[${max}X\x1b[${max}e`,
+	]);
+	expect(screen).toBe("ab       c\nz\n         d");
+});